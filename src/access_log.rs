@@ -0,0 +1,274 @@
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+    response::Response,
+};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::body::{self, BodyObserver};
+use crate::config::{AccessLogConfig, AccessLogOutput};
+use crate::jsonl::JsonlWriter;
+use crate::request_id::RequestId;
+use crate::usage::UsageTracker;
+
+/// Directory mounted by `docker-compose.yml` for log output.
+pub const DEFAULT_LOG_DIRECTORY: &str = "/var/log/anthropic-http-proxy";
+
+/// Writes one JSON line per proxied request to stdout or a rotating file.
+pub struct AccessLog {
+    writer: JsonlWriter,
+}
+
+/// A single access log line.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub time: String,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub key_id: Option<String>,
+    pub endpoint: String,
+    pub method: String,
+    pub path: String,
+    pub model: Option<String>,
+    /// The alias the client asked for, when `model` was resolved from one
+    pub model_alias: Option<String>,
+    /// Sensitive values redacted from the prompt, for audit
+    pub redactions: Option<u64>,
+    /// The guardrail classifier's verdict, when it was not `pass`
+    pub guardrail: Option<String>,
+    pub status: u16,
+    pub upstream: Option<String>,
+    pub latency_ms: u64,
+    pub ttfb_ms: Option<u64>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    pub upstream_request_id: Option<String>,
+}
+
+impl AccessLog {
+    pub fn from_config(config: &AccessLogConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let writer = match config.output.unwrap_or_default() {
+            AccessLogOutput::Stdout => JsonlWriter::stdout(),
+            AccessLogOutput::File => JsonlWriter::rolling(
+                config.directory.as_deref().unwrap_or(DEFAULT_LOG_DIRECTORY),
+                "access",
+                "log",
+                config.rotation.unwrap_or_default(),
+                config.max_files,
+            )?,
+        };
+
+        Ok(Self { writer })
+    }
+
+    pub fn with_writer(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: JsonlWriter::new(writer),
+        }
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) {
+        self.writer.write(entry);
+    }
+
+    /// Wraps the response body so the entry is written once the body has been
+    /// fully streamed to the client (or the client went away).
+    pub fn log_response(
+        self: &Arc<Self>,
+        mut entry: AccessLogEntry,
+        started: Instant,
+        response: Response,
+    ) -> Response {
+        entry.status = response.status().as_u16();
+
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok());
+        let usage = UsageTracker::new(content_type);
+
+        body::observe(
+            response,
+            LoggedBody {
+                log: self.clone(),
+                entry: Some(entry),
+                started,
+                usage: Some(usage),
+            },
+        )
+    }
+}
+
+impl AccessLogEntry {
+    /// Starts an entry for an incoming request; response fields are filled in later.
+    pub fn new(endpoint: &str, request: &Request) -> Self {
+        let client_ip = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|request_id| request_id.to_string());
+
+        Self {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            request_id,
+            client_ip,
+            key_id: key_fingerprint(request.headers()),
+            endpoint: endpoint.to_string(),
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            model: None,
+            model_alias: None,
+            redactions: None,
+            guardrail: None,
+            status: 0,
+            upstream: None,
+            latency_ms: 0,
+            ttfb_ms: None,
+            input_tokens: None,
+            output_tokens: None,
+            upstream_request_id: None,
+        }
+    }
+}
+
+/// Identifies the caller's API key without logging it: a short SHA-256 prefix of
+/// the credential sent in `x-api-key`, `api-key` or `Authorization: Bearer`.
+pub fn key_fingerprint(headers: &HeaderMap) -> Option<String> {
+    let credential = headers
+        .get("x-api-key")
+        .or_else(|| headers.get("api-key"))
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })?;
+
+    let digest = Sha256::digest(credential.trim().as_bytes());
+    Some(format!("key_{}", &hex::encode(digest)[..12]))
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+struct LoggedBody {
+    log: Arc<AccessLog>,
+    entry: Option<AccessLogEntry>,
+    started: Instant,
+    usage: Option<UsageTracker>,
+}
+
+impl BodyObserver for LoggedBody {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        let started = self.started;
+        if let Some(entry) = self.entry.as_mut() {
+            entry.ttfb_ms.get_or_insert_with(|| elapsed_ms(started));
+        }
+        if let Some(usage) = self.usage.as_mut() {
+            usage.observe(chunk);
+        }
+    }
+
+    fn on_end(&mut self, _complete: bool) {
+        if let Some(mut entry) = self.entry.take() {
+            if let Some(usage) = self.usage.take() {
+                let usage = usage.finish();
+                entry.input_tokens = usage.input_tokens;
+                entry.output_tokens = usage.output_tokens;
+            }
+            entry.latency_ms = elapsed_ms(self.started);
+            self.log.write(&entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::HeaderValue;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_key_fingerprint_hides_credential() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-ant-secret"));
+
+        let key_id = key_fingerprint(&headers).unwrap();
+        assert!(key_id.starts_with("key_"));
+        assert!(!key_id.contains("secret"));
+
+        let mut bearer = HeaderMap::new();
+        bearer.insert("authorization", HeaderValue::from_static("Bearer sk-ant-secret"));
+        assert_eq!(key_fingerprint(&bearer), Some(key_id));
+    }
+
+    #[tokio::test]
+    async fn test_log_response_writes_entry_after_body() {
+        let buffer = SharedBuffer::default();
+        let log = Arc::new(AccessLog::with_writer(Box::new(buffer.clone())));
+
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/dev/v1/messages")
+            .header("x-api-key", "sk-test")
+            .body(Body::empty())
+            .unwrap();
+        request.extensions_mut().insert(RequestId("req_local".to_string()));
+        let mut entry = AccessLogEntry::new("dev", &request);
+        entry.model = Some("claude-3-haiku".to_string());
+        entry.upstream_request_id = Some("req_123".to_string());
+
+        let response = Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"usage":{"input_tokens":3,"output_tokens":4}}"#))
+            .unwrap();
+
+        let response = log.log_response(entry, Instant::now(), response);
+        assert!(buffer.0.lock().unwrap().is_empty());
+
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(written.lines().count(), 1);
+
+        let line: serde_json::Value = serde_json::from_str(written.trim()).unwrap();
+        assert_eq!(line["request_id"], "req_local");
+        assert_eq!(line["endpoint"], "dev");
+        assert_eq!(line["method"], "POST");
+        assert_eq!(line["path"], "/dev/v1/messages");
+        assert_eq!(line["model"], "claude-3-haiku");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["input_tokens"], 3);
+        assert_eq!(line["output_tokens"], 4);
+        assert_eq!(line["upstream_request_id"], "req_123");
+        assert!(line["ttfb_ms"].is_u64());
+    }
+}
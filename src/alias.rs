@@ -0,0 +1,150 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::{CONTENT_LENGTH, CONTENT_TYPE}, HeaderValue},
+    response::Response,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::sse::SseEvent;
+use crate::translate::stream::{self, StreamTranscoder};
+
+/// Response header naming the model a request's alias was rewritten to.
+pub const MODEL_RESOLVED_HEADER: &str = "x-proxy-model-resolved";
+
+/// A request whose `model` was an alias.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelRewrite {
+    pub alias: String,
+    pub resolved: String,
+}
+
+/// Endpoint aliases take precedence over global ones. Aliases do not chain,
+/// so an alias may safely resolve to a name that is itself an alias.
+pub fn resolve<'a>(
+    endpoint: &'a HashMap<String, String>,
+    global: &'a HashMap<String, String>,
+    model: &str,
+) -> Option<&'a str> {
+    endpoint.get(model).or_else(|| global.get(model)).map(String::as_str)
+}
+
+/// Rewrites the body's `model` if it is an alias; other bodies are left alone.
+pub fn rewrite_request(
+    body: &[u8],
+    endpoint: &HashMap<String, String>,
+    global: &HashMap<String, String>,
+) -> Option<(Vec<u8>, ModelRewrite)> {
+    let mut body: Value = serde_json::from_slice(body).ok()?;
+    let alias = body.get("model")?.as_str()?.to_string();
+    let resolved = resolve(endpoint, global, &alias)?.to_string();
+    body["model"] = Value::String(resolved.clone());
+    Some((body.to_string().into_bytes(), ModelRewrite { alias, resolved }))
+}
+
+/// Puts the alias back wherever the response names the resolved model,
+/// in streamed events and in buffered JSON bodies alike.
+pub async fn restore_response(response: Response, alias: &str) -> Response {
+    let content_type = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/event-stream") {
+        return stream::transcode(response, Box::new(RestoreModel { alias: alias.to_string() }));
+    }
+    if !content_type.starts_with("application/json") {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let mut value = serde_json::from_slice::<Value>(&body).unwrap_or_default();
+    let body = if restore_model(&mut value, alias) {
+        let restored = value.to_string();
+        parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(restored.len()));
+        restored.into()
+    } else {
+        body
+    };
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Anthropic and OpenAI bodies carry `model` at the top level; Anthropic's
+/// `message_start` event nests it under `message`.
+fn restore_model(value: &mut Value, alias: &str) -> bool {
+    let target = match value.get("message").filter(|message| message.get("model").is_some()) {
+        Some(_) => &mut value["message"]["model"],
+        None => match value.get_mut("model") {
+            Some(model) => model,
+            None => return false,
+        },
+    };
+    *target = Value::String(alias.to_string());
+    true
+}
+
+struct RestoreModel {
+    alias: String,
+}
+
+impl StreamTranscoder for RestoreModel {
+    fn event(&mut self, mut event: SseEvent) -> Vec<SseEvent> {
+        if let Some(mut value) = event.json_data() {
+            if restore_model(&mut value, &self.alias) {
+                event.data = value.to_string();
+            }
+        }
+        vec![event]
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(alias, model)| (alias.to_string(), model.to_string())).collect()
+    }
+
+    #[test]
+    fn test_endpoint_aliases_override_global() {
+        let global = table(&[("fast", "claude-3-5-haiku-latest"), ("smart", "claude-3-5-sonnet-latest")]);
+        let endpoint = table(&[("fast", "anthropic.claude-3-haiku-20240307-v1:0")]);
+
+        assert_eq!(resolve(&endpoint, &global, "fast"), Some("anthropic.claude-3-haiku-20240307-v1:0"));
+        assert_eq!(resolve(&endpoint, &global, "smart"), Some("claude-3-5-sonnet-latest"));
+        assert_eq!(resolve(&endpoint, &global, "claude-3-opus"), None);
+
+        let (body, rewrite) = rewrite_request(br#"{"model":"smart","max_tokens":8}"#, &endpoint, &global).unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["model"], "claude-3-5-sonnet-latest");
+        assert_eq!(body["max_tokens"], 8);
+        assert_eq!(rewrite.alias, "smart");
+        assert!(rewrite_request(br#"{"model":"claude-3-opus"}"#, &endpoint, &global).is_none());
+        assert!(rewrite_request(b"not json", &endpoint, &global).is_none());
+    }
+
+    #[test]
+    fn test_restore_model_in_events() {
+        let mut transcoder = RestoreModel { alias: "fast".to_string() };
+        let start = SseEvent::new(
+            Some("message_start"),
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude-3-5-haiku-latest"}}"#,
+        );
+        let events = transcoder.event(start);
+        assert_eq!(events[0].event.as_deref(), Some("message_start"));
+        assert_eq!(events[0].json_data().unwrap()["message"]["model"], "fast");
+
+        let chunk = SseEvent::new(None, r#"{"id":"chatcmpl-1","model":"gpt-4o-mini","choices":[]}"#);
+        assert_eq!(transcoder.event(chunk)[0].json_data().unwrap()["model"], "fast");
+
+        let done = SseEvent::new(None, "[DONE]");
+        assert_eq!(transcoder.event(done)[0].data, "[DONE]");
+    }
+}
@@ -0,0 +1,303 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use url::Url;
+
+use crate::config::BedrockConfig;
+
+/// Characters SigV4 leaves unescaped: `A-Z a-z 0-9 - _ . ~`.
+const SIGV4_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Explicit configuration first, then `AWS_*` environment variables, then
+    /// the profile in the shared credentials file.
+    pub fn load(config: &BedrockConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        if let (Some(access_key_id), Some(secret_access_key)) = (&config.access_key_id, &config.secret_access_key) {
+            return Ok(Self {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                session_token: config.session_token.clone(),
+            });
+        }
+        if let Some(credentials) = Self::from_env() {
+            return Ok(credentials);
+        }
+
+        let profile = profile_name(config);
+        let path = shared_file("AWS_SHARED_CREDENTIALS_FILE", "credentials")
+            .ok_or("no AWS credentials configured and no home directory to look in")?;
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("no AWS credentials configured and {} is unreadable: {}", path.display(), e))?;
+        Self::from_profile(&contents, &profile)
+            .ok_or_else(|| format!("profile '{}' in {} has no AWS credentials", profile, path.display()).into())
+    }
+
+    fn from_env() -> Option<Self> {
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID").ok().filter(|value| !value.is_empty())?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY").ok().filter(|value| !value.is_empty())?;
+        Some(Self {
+            access_key_id,
+            secret_access_key,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok().filter(|value| !value.is_empty()),
+        })
+    }
+
+    /// Reads a profile from the contents of a shared credentials file.
+    pub fn from_profile(contents: &str, profile: &str) -> Option<Self> {
+        let section = ini_section(contents, profile)?;
+        Some(Self {
+            access_key_id: section.get("aws_access_key_id")?.clone(),
+            secret_access_key: section.get("aws_secret_access_key")?.clone(),
+            session_token: section.get("aws_session_token").cloned(),
+        })
+    }
+}
+
+/// The configured region, else the environment, else the profile in the
+/// shared config file.
+pub fn region(config: &BedrockConfig) -> Option<String> {
+    config
+        .region
+        .clone()
+        .or_else(|| std::env::var("AWS_REGION").ok())
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+        .filter(|region| !region.is_empty())
+        .or_else(|| {
+            let contents = std::fs::read_to_string(shared_file("AWS_CONFIG_FILE", "config")?).ok()?;
+            let profile = profile_name(config);
+            // The config file names every profile but the default `[profile name]`
+            let section = match profile.as_str() {
+                "default" => ini_section(&contents, "default"),
+                name => ini_section(&contents, &format!("profile {}", name)),
+            }?;
+            section.get("region").cloned()
+        })
+}
+
+fn profile_name(config: &BedrockConfig) -> String {
+    config
+        .profile
+        .clone()
+        .or_else(|| std::env::var("AWS_PROFILE").ok())
+        .unwrap_or_else(|| "default".to_string())
+}
+
+fn shared_file(env_var: &str, name: &str) -> Option<PathBuf> {
+    if let Ok(path) = std::env::var(env_var) {
+        return Some(PathBuf::from(path));
+    }
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).ok()?;
+    Some(PathBuf::from(home).join(".aws").join(name))
+}
+
+fn ini_section(contents: &str, name: &str) -> Option<HashMap<String, String>> {
+    let mut current: Option<&str> = None;
+    let mut section = None;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            current = Some(header.trim());
+            if current == Some(name) {
+                section.get_or_insert_with(HashMap::new);
+            }
+            continue;
+        }
+        if current != Some(name) {
+            continue;
+        }
+        if let (Some(section), Some((key, value))) = (section.as_mut(), line.split_once('=')) {
+            section.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    section
+}
+
+/// Signs requests with AWS Signature Version 4.
+pub struct SigV4Signer {
+    credentials: AwsCredentials,
+    region: String,
+    service: &'static str,
+}
+
+impl SigV4Signer {
+    pub fn new(credentials: AwsCredentials, region: &str, service: &'static str) -> Self {
+        Self {
+            credentials,
+            region: region.to_string(),
+            service,
+        }
+    }
+
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// Headers that authenticate the request: `x-amz-date`, the session
+    /// token if any, and `authorization`. Only `host` and these are signed,
+    /// so the remaining headers may change freely.
+    pub fn sign(&self, method: &str, url: &Url, body: &[u8], now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_string(),
+        };
+        let mut headers = vec![("host", host), ("x-amz-date", amz_date.clone())];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_uri(url),
+            canonical_query(url),
+            canonical_headers,
+            signed_headers,
+            hex::encode(Sha256::digest(body)),
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let key = [date, self.region.as_str(), self.service, "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.credentials.secret_access_key).into_bytes(), |key, part| {
+                hmac(&key, part.as_bytes())
+            });
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        headers.remove(0);
+        headers.push((
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.credentials.access_key_id, scope, signed_headers, signature
+            ),
+        ));
+        headers
+    }
+}
+
+/// Each path segment is escaped again, as every service but S3 expects.
+fn canonical_uri(url: &Url) -> String {
+    let path = url.path();
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, SIGV4_ESCAPE).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| {
+            (
+                utf8_percent_encode(&key, SIGV4_ESCAPE).to_string(),
+                utf8_percent_encode(&value, SIGV4_ESCAPE).to_string(),
+            )
+        })
+        .collect();
+    pairs.sort();
+    pairs.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("&")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn example_signer() -> SigV4Signer {
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        SigV4Signer::new(credentials, "us-east-1", "service")
+    }
+
+    #[test]
+    fn test_sigv4_test_suite_get_vanilla() {
+        // From the AWS Signature Version 4 test suite
+        let now = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = example_signer().sign("GET", &url, b"", now);
+
+        assert_eq!(headers[0], ("x-amz-date", "20150830T123600Z".to_string()));
+        assert_eq!(
+            headers[1].1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn test_canonical_uri_and_query() {
+        let url = Url::parse(
+            "https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-v2%3A1/invoke?b=2&a=x y",
+        )
+        .unwrap();
+        assert_eq!(canonical_uri(&url), "/model/anthropic.claude-v2%253A1/invoke");
+        assert_eq!(canonical_query(&url), "a=x%20y&b=2");
+    }
+
+    #[test]
+    fn test_session_token_is_signed() {
+        let mut signer = example_signer();
+        signer.credentials.session_token = Some("token".to_string());
+        let url = Url::parse("http://127.0.0.1:9000/model/m/invoke").unwrap();
+        let headers = signer.sign("POST", &url, b"{}", Utc::now());
+
+        assert_eq!(headers[1], ("x-amz-security-token", "token".to_string()));
+        assert!(headers[2].1.contains("SignedHeaders=host;x-amz-date;x-amz-security-token,"));
+    }
+
+    #[test]
+    fn test_credentials_from_profile() {
+        let contents = "\
+[default]
+aws_access_key_id = AKIDDEFAULT
+aws_secret_access_key = secret-default
+
+# team account
+[bedrock]
+aws_access_key_id=AKIDBEDROCK
+aws_secret_access_key=secret-bedrock
+aws_session_token=session
+";
+        let credentials = AwsCredentials::from_profile(contents, "bedrock").unwrap();
+        assert_eq!(credentials.access_key_id, "AKIDBEDROCK");
+        assert_eq!(credentials.session_token.as_deref(), Some("session"));
+        assert_eq!(AwsCredentials::from_profile(contents, "default").unwrap().session_token, None);
+        assert!(AwsCredentials::from_profile(contents, "missing").is_none());
+    }
+}
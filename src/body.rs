@@ -0,0 +1,67 @@
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    response::Response,
+};
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Watches a response body as it is streamed to the client.
+pub trait BodyObserver: Send + Unpin + 'static {
+    fn on_chunk(&mut self, chunk: &Bytes);
+
+    /// Called exactly once: when the body ends, fails, or is dropped early
+    /// because the client went away. `complete` is true only in the first case.
+    fn on_end(&mut self, complete: bool);
+}
+
+/// Wraps the response body so `observer` sees every chunk without the body
+/// being buffered.
+pub fn observe<O: BodyObserver>(response: Response, observer: O) -> Response {
+    response.map(|body| {
+        Body::from_stream(ObservedBody {
+            inner: body.into_data_stream(),
+            observer: Some(observer),
+        })
+    })
+}
+
+struct ObservedBody<O: BodyObserver> {
+    inner: BodyDataStream,
+    observer: Option<O>,
+}
+
+impl<O: BodyObserver> ObservedBody<O> {
+    fn finish(&mut self, complete: bool) {
+        if let Some(mut observer) = self.observer.take() {
+            observer.on_end(complete);
+        }
+    }
+}
+
+impl<O: BodyObserver> Stream for ObservedBody<O> {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(observer) = self.observer.as_mut() {
+                    observer.on_chunk(chunk);
+                }
+            }
+            Poll::Ready(None) => self.finish(true),
+            Poll::Ready(Some(Err(_))) => self.finish(false),
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+}
+
+impl<O: BodyObserver> Drop for ObservedBody<O> {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
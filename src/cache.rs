@@ -0,0 +1,410 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error};
+
+use crate::access_log::key_fingerprint;
+use crate::body::{self, BodyObserver};
+use crate::config::CacheConfig;
+use crate::fingerprint::{canonical_json, Fingerprint};
+use crate::request_id::UPSTREAM_REQUEST_ID_HEADER;
+
+/// Response header reporting `HIT`, `MISS` or `BYPASS`.
+pub const CACHE_HEADER: &str = "x-proxy-cache";
+
+const DEFAULT_TTL_SECS: u64 = 3600;
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+/// Responses larger than this are passed through but not cached.
+const MAX_CACHED_BODY: usize = 8 * 1024 * 1024;
+
+/// Response headers that describe the original transfer rather than the
+/// content; a hit is not the upstream call that stored it, so its id goes too.
+const UNCACHED_HEADERS: &[&str] = &["transfer-encoding", "connection", "set-cookie", "date", UPSTREAM_REQUEST_ID_HEADER];
+
+/// Per-endpoint cache of successful responses to deterministic requests, with
+/// an in-memory LRU tier and an optional on-disk tier.
+pub struct ResponseCache {
+    ttl: Duration,
+    deterministic_only: bool,
+    memory: Mutex<LruCache<String, CachedResponse>>,
+    directory: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    /// Seconds since the Unix epoch.
+    stored_at: u64,
+    status: u16,
+    headers: Vec<(String, String)>,
+    /// The body's bytes in base64, so compressed bodies are kept too;
+    /// streamed responses are kept as the original event stream.
+    body: String,
+}
+
+/// What the client asked for in its `cache-control` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheDirectives {
+    /// `no-cache`: skip the lookup but refresh the entry.
+    pub no_cache: bool,
+    /// `no-store`: do not store the response either.
+    pub no_store: bool,
+}
+
+impl CacheDirectives {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut directives = Self::default();
+        for value in headers.get_all("cache-control") {
+            for directive in value.to_str().unwrap_or("").split(',') {
+                match directive.trim().to_ascii_lowercase().as_str() {
+                    "no-cache" => directives.no_cache = true,
+                    "no-store" => directives.no_store = true,
+                    _ => {}
+                }
+            }
+        }
+        directives
+    }
+}
+
+impl ResponseCache {
+    pub fn from_config(config: &CacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES))
+            .unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            ttl: Duration::from_secs(config.ttl_seconds.unwrap_or(DEFAULT_TTL_SECS)),
+            deterministic_only: config.deterministic_only.unwrap_or(true),
+            memory: Mutex::new(LruCache::new(capacity)),
+            directory: config.directory.as_ref().map(PathBuf::from),
+        }
+    }
+
+    /// Cache key for a request, or `None` when the request is not cacheable:
+    /// not a JSON object body, or sampled with a non-zero temperature while
+    /// `deterministic_only` is set. The caller's credential is part of the key
+    /// so one key's responses are never served to another, as are the query
+    /// and the headers that select an API version or a content encoding.
+    pub fn key(&self, endpoint: &str, path: &str, query: Option<&str>, headers: &HeaderMap, body: &[u8]) -> Option<String> {
+        let parsed: Value = serde_json::from_slice(body).ok()?;
+        let request = parsed.as_object()?;
+
+        if self.deterministic_only {
+            let temperature = request.get("temperature").and_then(Value::as_f64);
+            if temperature != Some(0.0) {
+                return None;
+            }
+        }
+
+        let model = request.get("model").and_then(Value::as_str).unwrap_or("");
+        let fingerprint = Fingerprint::new()
+            .update("endpoint", endpoint.as_bytes())
+            .update("path", path.as_bytes())
+            .update("query", query.unwrap_or("").as_bytes())
+            .update("key", key_fingerprint(headers).unwrap_or_default().as_bytes())
+            .update("model", model.as_bytes())
+            .keyed_headers(headers);
+        Some(fingerprint.update("body", &canonical_json(body)).finish())
+    }
+
+    /// Looks in memory first, then on disk (promoting disk hits into memory).
+    pub async fn get(&self, key: &str) -> Option<Response> {
+        let cached = self.get_from_memory(key);
+        let cached = match cached {
+            Some(cached) => Some(cached),
+            None => self.get_from_disk(key).await,
+        }?;
+
+        // Entries written before bodies were base64-encoded are misses
+        let body = STANDARD.decode(&cached.body).ok()?;
+        debug!("Cache hit: {}", key);
+        let mut builder = Response::builder().status(cached.status);
+        for (name, value) in &cached.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder
+            .header(CACHE_HEADER, "HIT")
+            .body(Body::from(body))
+            .ok()
+    }
+
+    fn get_from_memory(&self, key: &str) -> Option<CachedResponse> {
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        let cached = memory.get(key)?.clone();
+        if self.is_expired(&cached) {
+            memory.pop(key);
+            return None;
+        }
+        Some(cached)
+    }
+
+    async fn get_from_disk(&self, key: &str) -> Option<CachedResponse> {
+        let path = self.directory.as_ref()?.join(format!("{}.json", key));
+        let bytes = tokio::fs::read(&path).await.ok()?;
+        let cached: CachedResponse = serde_json::from_slice(&bytes).ok()?;
+        if self.is_expired(&cached) {
+            let _ = tokio::fs::remove_file(&path).await;
+            return None;
+        }
+
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        memory.put(key.to_string(), cached.clone());
+        Some(cached)
+    }
+
+    fn is_expired(&self, cached: &CachedResponse) -> bool {
+        now_secs().saturating_sub(cached.stored_at) >= self.ttl.as_secs()
+    }
+
+    /// Marks a response as a cache miss and, for successful responses, stores
+    /// it once the body has streamed through completely.
+    pub fn store_on_completion(self: &Arc<Self>, key: String, mut response: Response) -> Response {
+        response
+            .headers_mut()
+            .insert(CACHE_HEADER, HeaderValue::from_static("MISS"));
+
+        if !response.status().is_success() {
+            return response;
+        }
+
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                !UNCACHED_HEADERS.contains(&name.as_str()) && name.as_str() != CACHE_HEADER
+            })
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+            .collect();
+        let status = response.status().as_u16();
+
+        body::observe(
+            response,
+            CacheFiller {
+                cache: self.clone(),
+                key,
+                status,
+                headers,
+                body: Vec::new(),
+                oversized: false,
+            },
+        )
+    }
+
+    fn insert(&self, key: &str, cached: CachedResponse) {
+        if let Some(directory) = &self.directory {
+            let path = directory.join(format!("{}.json", key));
+            let result = std::fs::create_dir_all(directory).and_then(|_| {
+                let bytes = serde_json::to_vec(&cached).map_err(std::io::Error::other)?;
+                std::fs::write(&path, bytes)
+            });
+            if let Err(e) = result {
+                error!("Failed to write cache entry {}: {}", path.display(), e);
+            }
+        }
+
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        memory.put(key.to_string(), cached);
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+struct CacheFiller {
+    cache: Arc<ResponseCache>,
+    key: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    oversized: bool,
+}
+
+impl BodyObserver for CacheFiller {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        if self.oversized {
+            return;
+        }
+        if self.body.len() + chunk.len() > MAX_CACHED_BODY {
+            self.body = Vec::new();
+            self.oversized = true;
+            return;
+        }
+        self.body.extend_from_slice(chunk);
+    }
+
+    fn on_end(&mut self, complete: bool) {
+        if !complete || self.oversized {
+            return;
+        }
+        let body = STANDARD.encode(std::mem::take(&mut self.body));
+        debug!("Caching response: {}", self.key);
+        self.cache.insert(
+            &self.key,
+            CachedResponse {
+                stored_at: now_secs(),
+                status: self.status,
+                headers: std::mem::take(&mut self.headers),
+                body,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(max_entries: usize, directory: Option<PathBuf>) -> Arc<ResponseCache> {
+        Arc::new(ResponseCache::from_config(&CacheConfig {
+            ttl_seconds: Some(60),
+            max_entries: Some(max_entries),
+            directory: directory.map(|directory| directory.display().to_string()),
+            deterministic_only: None,
+        }))
+    }
+
+    async fn fill(cache: &Arc<ResponseCache>, key: &str, body: impl Into<Body>) {
+        let response = Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .body(body.into())
+            .unwrap();
+        let response = cache.store_on_completion(key.to_string(), response);
+        assert_eq!(response.headers()[CACHE_HEADER], "MISS");
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    }
+
+    #[test]
+    fn test_key_requires_deterministic_json() {
+        let cache = cache(10, None);
+        let headers = HeaderMap::new();
+        let a = cache.key("dev", "/v1/messages", None, &headers, br#"{"model":"m","temperature":0,"messages":[]}"#);
+        let b = cache.key("dev", "/v1/messages", None, &headers, br#"{"messages":[],"temperature":0,"model":"m"}"#);
+        assert!(a.is_some());
+        assert_eq!(a, b);
+
+        assert_ne!(a, cache.key("prod", "/v1/messages", None, &headers, br#"{"model":"m","temperature":0,"messages":[]}"#));
+        assert!(cache.key("dev", "/v1/messages", None, &headers, br#"{"model":"m","temperature":0.7}"#).is_none());
+        assert!(cache.key("dev", "/v1/messages", None, &headers, br#"{"model":"m"}"#).is_none());
+        assert!(cache.key("dev", "/v1/messages", None, &headers, b"not json").is_none());
+    }
+
+    #[test]
+    fn test_key_separates_callers_versions_and_encodings() {
+        let cache = cache(10, None);
+        let body = br#"{"model":"m","temperature":0,"messages":[]}"#;
+        let key = |query: Option<&str>, headers: &[(&'static str, &'static str)]| {
+            let headers = headers.iter()
+                .map(|(name, value)| (axum::http::HeaderName::from_static(name), HeaderValue::from_static(value)))
+                .collect::<HeaderMap>();
+            cache.key("dev", "/v1/messages", query, &headers, body).unwrap()
+        };
+
+        let base = key(None, &[("x-api-key", "sk-one"), ("anthropic-version", "2023-06-01")]);
+        assert_eq!(base, key(None, &[("x-api-key", "sk-one"), ("anthropic-version", "2023-06-01"), ("user-agent", "sdk")]));
+        assert_ne!(base, key(None, &[("x-api-key", "sk-two"), ("anthropic-version", "2023-06-01")]));
+        assert_ne!(base, key(None, &[("anthropic-version", "2023-06-01")]));
+        assert_ne!(base, key(Some("beta=true"), &[("x-api-key", "sk-one"), ("anthropic-version", "2023-06-01")]));
+        assert_ne!(base, key(None, &[("x-api-key", "sk-one"), ("anthropic-version", "2023-06-01"), ("anthropic-beta", "tools")]));
+        assert_ne!(base, key(None, &[("x-api-key", "sk-one"), ("anthropic-version", "2023-06-01"), ("accept-encoding", "gzip")]));
+    }
+
+    #[test]
+    fn test_cache_directives() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(CacheDirectives::from_headers(&headers), CacheDirectives::default());
+
+        headers.insert("cache-control", HeaderValue::from_static("No-Cache, no-store"));
+        let directives = CacheDirectives::from_headers(&headers);
+        assert!(directives.no_cache);
+        assert!(directives.no_store);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_replayed_verbatim() {
+        let cache = cache(10, None);
+        let stream = "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        fill(&cache, "k1", stream).await;
+
+        let hit = cache.get("k1").await.unwrap();
+        assert_eq!(hit.headers()[CACHE_HEADER], "HIT");
+        assert_eq!(hit.headers()["content-type"], "text/event-stream");
+        let body = axum::body::to_bytes(hit.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], stream.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_binary_bodies_are_cached_without_upstream_ids() {
+        let cache = cache(10, None);
+        let gzip: &'static [u8] = b"\x1f\x8b\x08\x00\xff\xfe";
+        let response = Response::builder()
+            .status(200)
+            .header("content-encoding", "gzip")
+            .header(UPSTREAM_REQUEST_ID_HEADER, "req_upstream")
+            .body(Body::from(gzip))
+            .unwrap();
+        let response = cache.store_on_completion("k1".to_string(), response);
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let hit = cache.get("k1").await.unwrap();
+        assert_eq!(hit.headers()["content-encoding"], "gzip");
+        assert!(hit.headers().get(UPSTREAM_REQUEST_ID_HEADER).is_none());
+        let body = axum::body::to_bytes(hit.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], gzip);
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction_and_error_responses() {
+        let cache = cache(1, None);
+        fill(&cache, "k1", "one").await;
+        fill(&cache, "k2", "two").await;
+        assert!(cache.get("k1").await.is_none());
+        assert!(cache.get("k2").await.is_some());
+
+        let error = Response::builder().status(529).body(Body::from("overloaded")).unwrap();
+        let error = cache.store_on_completion("k3".to_string(), error);
+        axum::body::to_bytes(error.into_body(), usize::MAX).await.unwrap();
+        assert!(cache.get("k3").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_tier_survives_restart() {
+        let directory = std::env::temp_dir().join(format!("cache-test-{}", std::process::id()));
+        fill(&cache(10, Some(directory.clone())), "k1", "persisted").await;
+
+        let restarted = cache(10, Some(directory.clone()));
+        let hit = restarted.get("k1").await.unwrap();
+        let body = axum::body::to_bytes(hit.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"persisted");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_dropped() {
+        let cache = cache(10, None);
+        cache.insert(
+            "old",
+            CachedResponse {
+                stored_at: now_secs() - 120,
+                status: 200,
+                headers: Vec::new(),
+                body: STANDARD.encode("stale"),
+            },
+        );
+        assert!(cache.get("old").await.is_none());
+    }
+}
@@ -0,0 +1,390 @@
+use axum::{body::Bytes, http::HeaderMap, response::Response};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info};
+
+use crate::access_log::DEFAULT_LOG_DIRECTORY;
+use crate::body::{self, BodyObserver};
+use crate::config::Config;
+use crate::jsonl::JsonlWriter;
+use crate::redact::Redactor;
+use crate::sse;
+
+/// Bodies larger than this are recorded as `null` with `truncated` set.
+const MAX_CAPTURED_BODY: usize = 16 * 1024 * 1024;
+
+/// Writes sampled request/response exchanges to rotating JSONL files for the
+/// endpoints that opt in with `[endpoints.<name>.capture]`.
+pub struct Capture {
+    writer: Arc<JsonlWriter>,
+    endpoints: HashMap<String, EndpointCapture>,
+}
+
+struct EndpointCapture {
+    sample_rate: f64,
+    redactor: Arc<Redactor>,
+}
+
+/// One captured exchange, as written to (and read back from) the capture file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub time: String,
+    pub request_id: String,
+    pub endpoint: String,
+    pub method: String,
+    /// Client path, e.g. `/v1/messages`, without the endpoint prefix.
+    pub path: String,
+    #[serde(default)]
+    pub query: Option<String>,
+    pub request: CapturedRequest,
+    pub response: Option<CapturedResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedRequest {
+    pub headers: Map<String, Value>,
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapturedResponse {
+    pub status: u16,
+    pub headers: Map<String, Value>,
+    /// Streamed responses are reassembled into the equivalent single message.
+    pub body: Value,
+    #[serde(default)]
+    pub streamed: bool,
+    #[serde(default)]
+    pub truncated: bool,
+    pub latency_ms: u64,
+}
+
+impl Capture {
+    /// Returns `None` when no endpoint has capture enabled.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let mut endpoints = HashMap::new();
+        for (name, endpoint) in &config.endpoints {
+            let Some(capture) = &endpoint.capture else {
+                continue;
+            };
+            if !capture.enabled.unwrap_or(true) {
+                continue;
+            }
+
+            let redactor = Redactor::new(
+                &capture.redact_headers,
+                &capture.redact_fields,
+                &capture.redact_patterns,
+            )?;
+            endpoints.insert(
+                name.clone(),
+                EndpointCapture {
+                    sample_rate: capture.sample_rate.unwrap_or(1.0).clamp(0.0, 1.0),
+                    redactor: Arc::new(redactor),
+                },
+            );
+        }
+
+        if endpoints.is_empty() {
+            return Ok(None);
+        }
+
+        let output = config.capture.as_ref();
+        let directory = output
+            .and_then(|output| output.directory.as_deref())
+            .unwrap_or(DEFAULT_LOG_DIRECTORY);
+        info!("Capturing traffic for {} endpoints to {}", endpoints.len(), directory);
+
+        let writer = JsonlWriter::rolling(
+            directory,
+            "capture",
+            "jsonl",
+            output.and_then(|output| output.rotation).unwrap_or_default(),
+            output.and_then(|output| output.max_files),
+        )?;
+
+        Ok(Some(Self::with_writer(writer, endpoints)))
+    }
+
+    fn with_writer(writer: JsonlWriter, endpoints: HashMap<String, EndpointCapture>) -> Self {
+        Self {
+            writer: Arc::new(writer),
+            endpoints,
+        }
+    }
+
+    pub fn captures(&self, endpoint: &str) -> bool {
+        self.endpoints.contains_key(endpoint)
+    }
+
+    /// Decides whether this request is sampled and, if so, records the
+    /// (redacted) request half of the exchange.
+    #[allow(clippy::too_many_arguments)]
+    pub fn begin(
+        &self,
+        endpoint: &str,
+        request_id: &str,
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Option<PendingCapture> {
+        let capture = self.endpoints.get(endpoint)?;
+        if capture.sample_rate < 1.0 && rand::random::<f64>() >= capture.sample_rate {
+            return None;
+        }
+
+        let request = CapturedRequest {
+            headers: capture.redactor.headers(headers),
+            body: if body.is_empty() {
+                Value::Null
+            } else {
+                capture.redactor.body(body)
+            },
+        };
+
+        Some(PendingCapture {
+            writer: self.writer.clone(),
+            redactor: capture.redactor.clone(),
+            started: Instant::now(),
+            record: CaptureRecord {
+                time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                request_id: request_id.to_string(),
+                endpoint: endpoint.to_string(),
+                method: method.to_string(),
+                path: path.to_string(),
+                query: query.map(str::to_string),
+                request,
+                response: None,
+            },
+        })
+    }
+}
+
+/// A sampled exchange waiting for its response.
+pub struct PendingCapture {
+    writer: Arc<JsonlWriter>,
+    redactor: Arc<Redactor>,
+    started: Instant,
+    record: CaptureRecord,
+}
+
+impl PendingCapture {
+    /// Records the response as it streams to the client and writes the
+    /// exchange once the body is complete.
+    pub fn attach(self, response: Response) -> Response {
+        let streamed = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .map(|content_type| content_type.starts_with("text/event-stream"))
+            .unwrap_or(false);
+        let observer = CaptureObserver {
+            status: response.status().as_u16(),
+            headers: self.redactor.headers(response.headers()),
+            streamed,
+            body: Vec::new(),
+            truncated: false,
+            pending: Some(self),
+        };
+
+        body::observe(response, observer)
+    }
+
+    /// Writes the exchange without a response, e.g. when the upstream was unreachable.
+    pub fn finish_without_response(self) {
+        self.writer.write(&self.record);
+    }
+}
+
+struct CaptureObserver {
+    status: u16,
+    headers: Map<String, Value>,
+    streamed: bool,
+    body: Vec<u8>,
+    truncated: bool,
+    pending: Option<PendingCapture>,
+}
+
+impl BodyObserver for CaptureObserver {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        if self.truncated {
+            return;
+        }
+        if self.body.len() + chunk.len() > MAX_CAPTURED_BODY {
+            self.body = Vec::new();
+            self.truncated = true;
+            return;
+        }
+        self.body.extend_from_slice(chunk);
+    }
+
+    fn on_end(&mut self, _complete: bool) {
+        let Some(mut pending) = self.pending.take() else {
+            return;
+        };
+
+        let body = if self.truncated || self.body.is_empty() {
+            Value::Null
+        } else if self.streamed {
+            match sse::assemble(&sse::parse_all(&self.body)) {
+                Some(mut message) => {
+                    pending.redactor.json(&mut message);
+                    message
+                }
+                None => pending.redactor.body(&self.body),
+            }
+        } else {
+            pending.redactor.body(&self.body)
+        };
+
+        pending.record.response = Some(CapturedResponse {
+            status: self.status,
+            headers: std::mem::take(&mut self.headers),
+            body,
+            streamed: self.streamed,
+            truncated: self.truncated,
+            latency_ms: pending.started.elapsed().as_millis() as u64,
+        });
+
+        debug!("Captured exchange {}", pending.record.request_id);
+        pending.writer.write(&pending.record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture_with_buffer(sample_rate: f64) -> (Capture, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        let redactor = Redactor::new(
+            &[],
+            &["$.metadata.user_id".to_string()],
+            &["sk-[a-z0-9]{6,}".to_string()],
+        )
+        .unwrap();
+        let mut endpoints = HashMap::new();
+        endpoints.insert(
+            "dev".to_string(),
+            EndpointCapture {
+                sample_rate,
+                redactor: Arc::new(redactor),
+            },
+        );
+        let capture = Capture::with_writer(JsonlWriter::new(Box::new(buffer.clone())), endpoints);
+        (capture, buffer)
+    }
+
+    fn request_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-ant-secret"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer sk-secret"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers
+    }
+
+    fn written_records(buffer: &SharedBuffer) -> Vec<CaptureRecord> {
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        written.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_capture_redacts_and_writes_exchange() {
+        let (capture, buffer) = capture_with_buffer(1.0);
+        let request_body = json!({
+            "model": "claude",
+            "metadata": { "user_id": "u-1" },
+            "messages": [{ "role": "user", "content": "key sk-abcdef123" }]
+        });
+
+        let pending = capture
+            .begin("dev", "req_1", "POST", "/v1/messages", Some("beta=true"), &request_headers(), request_body.to_string().as_bytes())
+            .unwrap();
+        let response = Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"content":[{"type":"text","text":"ok"}]}"#))
+            .unwrap();
+        let response = pending.attach(response);
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(!written.contains("sk-ant-secret"));
+        assert!(!written.contains("sk-secret"));
+        assert!(!written.contains("sk-abcdef123"));
+
+        let records = written_records(&buffer);
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.path, "/v1/messages");
+        assert_eq!(record.query.as_deref(), Some("beta=true"));
+        assert_eq!(record.request.headers["x-api-key"], "[REDACTED]");
+        assert_eq!(record.request.body["metadata"]["user_id"], "[REDACTED]");
+        let response = record.response.as_ref().unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["content"][0]["text"], "ok");
+        assert!(!response.streamed);
+    }
+
+    #[tokio::test]
+    async fn test_capture_reassembles_streamed_response() {
+        let (capture, buffer) = capture_with_buffer(1.0);
+        let pending = capture
+            .begin("dev", "req_2", "POST", "/v1/messages", None, &request_headers(), b"{}")
+            .unwrap();
+
+        let stream = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"content\":[],\"usage\":{\"input_tokens\":1}}}\n\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"streamed\"}}\n\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        );
+        let response = Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .body(Body::from(stream))
+            .unwrap();
+        let response = pending.attach(response);
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let records = written_records(&buffer);
+        let response = records[0].response.as_ref().unwrap();
+        assert!(response.streamed);
+        assert_eq!(response.body["id"], "msg_1");
+        assert_eq!(response.body["content"][0]["text"], "streamed");
+    }
+
+    #[test]
+    fn test_capture_sampling_and_unknown_endpoint() {
+        let (capture, _) = capture_with_buffer(0.0);
+        assert!(capture.begin("dev", "req", "GET", "/v1/models", None, &HeaderMap::new(), b"").is_none());
+
+        let (capture, _) = capture_with_buffer(1.0);
+        assert!(capture.begin("other", "req", "GET", "/v1/models", None, &HeaderMap::new(), b"").is_none());
+    }
+}
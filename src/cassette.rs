@@ -0,0 +1,404 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{SecondsFormat, Utc};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+use crate::body::{self, BodyObserver};
+use crate::config::{CassetteConfig, EndpointMode};
+use crate::fingerprint::{canonical_json, Fingerprint};
+use crate::redact::{Redactor, ALWAYS_REDACTED_HEADERS};
+
+/// Response header telling clients whether a cassette was involved.
+pub const CASSETTE_HEADER: &str = "x-proxy-cassette";
+
+/// Headers that change between otherwise identical calls and so never take
+/// part in the cassette key.
+const VOLATILE_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "user-agent",
+    "x-request-id",
+    "traceparent",
+    "tracestate",
+    "date",
+];
+
+/// Header prefixes for SDK telemetry (retry counts, OS, runtime versions).
+const VOLATILE_HEADER_PREFIXES: &[&str] = &["x-stainless-"];
+
+/// Records upstream exchanges to, or serves them from, a directory of JSON
+/// files keyed by a normalised request hash.
+pub struct Cassette {
+    mode: EndpointMode,
+    directory: PathBuf,
+    preserve_timing: bool,
+    ignore_headers: HashSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteEntry {
+    key: String,
+    recorded_at: String,
+    request: CassetteRequest,
+    response: CassetteResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteRequest {
+    method: String,
+    path: String,
+    headers: Map<String, Value>,
+    body: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<CassetteChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CassetteChunk {
+    /// Time since the previous chunk (or since the request, for the first one).
+    delay_ms: u64,
+    /// The chunk's bytes in base64, so binary frames, compressed bodies and
+    /// characters split across chunks replay exactly as received.
+    data: String,
+}
+
+impl Cassette {
+    /// Returns `None` for passthrough endpoints.
+    pub fn from_config(endpoint: &str, mode: EndpointMode, config: Option<&CassetteConfig>) -> Option<Self> {
+        if mode == EndpointMode::Passthrough {
+            return None;
+        }
+
+        let directory = config
+            .and_then(|config| config.directory.clone())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("cassettes").join(endpoint));
+        info!("Endpoint '{}' in {:?} mode using cassettes in {}", endpoint, mode, directory.display());
+
+        let ignore_headers = ALWAYS_REDACTED_HEADERS
+            .iter()
+            .chain(VOLATILE_HEADERS)
+            .map(|header| header.to_string())
+            .chain(
+                config
+                    .into_iter()
+                    .flat_map(|config| config.ignore_headers.iter())
+                    .map(|header| header.to_ascii_lowercase()),
+            )
+            .collect();
+
+        Some(Self {
+            mode,
+            directory,
+            preserve_timing: config.and_then(|config| config.preserve_timing).unwrap_or(false),
+            ignore_headers,
+        })
+    }
+
+    pub fn mode(&self) -> EndpointMode {
+        self.mode
+    }
+
+    /// Hash of method, path, query, non-volatile headers and canonical JSON body.
+    pub fn key(&self, method: &str, path: &str, query: Option<&str>, headers: &HeaderMap, body: &[u8]) -> String {
+        let mut names: Vec<&str> = headers
+            .keys()
+            .map(|name| name.as_str())
+            .filter(|name| !self.is_ignored_header(name))
+            .collect();
+        names.sort_unstable();
+
+        let mut fingerprint = Fingerprint::new()
+            .update("method", method.as_bytes())
+            .update("path", path.as_bytes())
+            .update("query", query.unwrap_or("").as_bytes());
+        for name in names {
+            for value in headers.get_all(name) {
+                fingerprint = fingerprint.update(name, value.as_bytes());
+            }
+        }
+        fingerprint.update("body", &canonical_json(body)).finish()
+    }
+
+    fn is_ignored_header(&self, name: &str) -> bool {
+        self.ignore_headers.contains(name)
+            || VOLATILE_HEADER_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", key))
+    }
+
+    /// Serves a recorded response, or a clear error when nothing was recorded.
+    pub fn replay(&self, key: &str, method: &str, path: &str) -> Response {
+        let entry_path = self.entry_path(key);
+        let entry = match std::fs::read(&entry_path) {
+            Ok(bytes) => serde_json::from_slice::<CassetteEntry>(&bytes).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let chunks = entry.and_then(|entry| {
+            let chunks = entry.response.chunks.iter()
+                .map(|chunk| {
+                    let data = STANDARD.decode(&chunk.data).map_err(|e| format!("invalid chunk: {}", e))?;
+                    Ok((Duration::from_millis(chunk.delay_ms), Bytes::from(data)))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok((entry, chunks))
+        });
+
+        let (entry, chunks) = match chunks {
+            Ok(entry) => entry,
+            Err(e) => {
+                debug!("Cassette miss for {}: {}", entry_path.display(), e);
+                return cassette_miss(method, path, key, &self.directory);
+            }
+        };
+
+        debug!("Cassette hit: {}", entry_path.display());
+        let mut builder = Response::builder().status(entry.response.status);
+        for (name, value) in &entry.response.headers {
+            if !matches!(name.as_str(), "transfer-encoding" | "connection") {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+        }
+        builder = builder.header(CASSETTE_HEADER, "HIT");
+
+        let body = if self.preserve_timing {
+            Body::from_stream(stream::iter(chunks).then(|(delay, data)| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, std::io::Error>(data)
+            }))
+        } else {
+            let data: Vec<u8> = chunks.into_iter().flat_map(|(_, data)| data).collect();
+            Body::from(data)
+        };
+
+        builder.body(body).unwrap_or_else(|_| {
+            cassette_miss(method, path, key, &self.directory)
+        })
+    }
+
+    /// Tees the upstream response into a cassette file as it streams to the client.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        key: String,
+        method: &str,
+        path: &str,
+        request_headers: &HeaderMap,
+        request_body: &[u8],
+        started: Instant,
+        mut response: Response,
+    ) -> Response {
+        let redactor = Redactor::default();
+        let request = CassetteRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: redactor.headers(request_headers),
+            body: if request_body.is_empty() {
+                Value::Null
+            } else {
+                redactor.body(request_body)
+            },
+        };
+        let headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+            .collect();
+        let status = response.status().as_u16();
+
+        response
+            .headers_mut()
+            .insert(CASSETTE_HEADER, HeaderValue::from_static("RECORDED"));
+
+        body::observe(
+            response,
+            Recorder {
+                path: self.entry_path(&key),
+                entry: Some(CassetteEntry {
+                    key,
+                    recorded_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    request,
+                    response: CassetteResponse {
+                        status,
+                        headers,
+                        chunks: Vec::new(),
+                    },
+                }),
+                last_chunk: started,
+            },
+        )
+    }
+}
+
+fn cassette_miss(method: &str, path: &str, key: &str, directory: &std::path::Path) -> Response {
+    let message = format!(
+        "No cassette entry for {} {} (key {}) in {}; record it first with mode = \"record\"",
+        method,
+        path,
+        key,
+        directory.display()
+    );
+    let body = json!({
+        "type": "error",
+        "error": { "type": "cassette_miss", "message": message }
+    });
+
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("content-type", "application/json")
+        .header(CASSETTE_HEADER, "MISS")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+struct Recorder {
+    path: PathBuf,
+    entry: Option<CassetteEntry>,
+    last_chunk: Instant,
+}
+
+impl BodyObserver for Recorder {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        let now = Instant::now();
+        if let Some(entry) = self.entry.as_mut() {
+            entry.response.chunks.push(CassetteChunk {
+                delay_ms: now.duration_since(self.last_chunk).as_millis() as u64,
+                data: STANDARD.encode(chunk),
+            });
+        }
+        self.last_chunk = now;
+    }
+
+    fn on_end(&mut self, complete: bool) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        // A truncated recording would replay as a broken response
+        if !complete {
+            debug!("Not recording incomplete response for {}", self.path.display());
+            return;
+        }
+
+        let result = self
+            .path
+            .parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| {
+                let bytes = serde_json::to_vec_pretty(&entry).map_err(std::io::Error::other)?;
+                std::fs::write(&self.path, bytes)
+            });
+
+        match result {
+            Ok(()) => debug!("Recorded cassette {}", self.path.display()),
+            Err(e) => error!("Failed to write cassette {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cassette(mode: EndpointMode, directory: &std::path::Path) -> Cassette {
+        let config = CassetteConfig {
+            directory: Some(directory.display().to_string()),
+            preserve_timing: Some(true),
+            ignore_headers: vec!["X-Trace".to_string()],
+        };
+        Cassette::from_config("dev", mode, Some(&config)).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cassette-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_passthrough_has_no_cassette() {
+        assert!(Cassette::from_config("dev", EndpointMode::Passthrough, None).is_none());
+    }
+
+    #[test]
+    fn test_key_ignores_volatile_headers_and_key_order() {
+        let cassette = cassette(EndpointMode::Replay, &temp_dir("key"));
+
+        let mut a = HeaderMap::new();
+        a.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        a.insert("x-api-key", HeaderValue::from_static("sk-one"));
+        a.insert("x-stainless-retry-count", HeaderValue::from_static("0"));
+        a.insert("x-trace", HeaderValue::from_static("abc"));
+
+        let mut b = HeaderMap::new();
+        b.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        b.insert("x-api-key", HeaderValue::from_static("sk-two"));
+        b.insert("x-request-id", HeaderValue::from_static("req_2"));
+
+        let key_a = cassette.key("POST", "/v1/messages", None, &a, br#"{"model":"m","max_tokens":5}"#);
+        let key_b = cassette.key("POST", "/v1/messages", None, &b, br#"{"max_tokens": 5, "model": "m"}"#);
+        assert_eq!(key_a, key_b);
+
+        b.insert("anthropic-version", HeaderValue::from_static("2024-01-01"));
+        assert_ne!(key_a, cassette.key("POST", "/v1/messages", None, &b, br#"{"model":"m","max_tokens":5}"#));
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let directory = temp_dir("roundtrip");
+        let recorder = cassette(EndpointMode::Record, &directory);
+        let key = recorder.key("POST", "/v1/messages", None, &HeaderMap::new(), b"{}");
+
+        let upstream = Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .body(Body::from_stream(stream::iter(vec![
+                // A euro sign split across chunks, then bytes that are not UTF-8
+                Ok::<_, std::io::Error>(Bytes::from_static(b"data: one \xe2")),
+                Ok(Bytes::from_static(b"\x82\xac\n\ndata: two\n\n\x00\xff")),
+            ])))
+            .unwrap();
+        let recorded = recorder.record(key.clone(), "POST", "/v1/messages", &HeaderMap::new(), b"{}", Instant::now(), upstream);
+        assert_eq!(recorded.headers()[CASSETTE_HEADER], "RECORDED");
+        axum::body::to_bytes(recorded.into_body(), usize::MAX).await.unwrap();
+
+        let replayer = cassette(EndpointMode::Replay, &directory);
+        let replayed = replayer.replay(&key, "POST", "/v1/messages");
+        assert_eq!(replayed.status(), StatusCode::OK);
+        assert_eq!(replayed.headers()[CASSETTE_HEADER], "HIT");
+        assert_eq!(replayed.headers()["content-type"], "text/event-stream");
+        let body = axum::body::to_bytes(replayed.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"data: one \xe2\x82\xac\n\ndata: two\n\n\x00\xff");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_miss_is_clear_error() {
+        let cassette = cassette(EndpointMode::Replay, &temp_dir("miss"));
+        let response = cassette.replay("deadbeef", "POST", "/v1/messages");
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CASSETTE_HEADER], "MISS");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "cassette_miss");
+        assert!(body["error"]["message"].as_str().unwrap().contains("POST /v1/messages"));
+    }
+}
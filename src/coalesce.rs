@@ -0,0 +1,353 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::debug;
+
+use crate::access_log::key_fingerprint;
+use crate::fingerprint::{canonical_json, Fingerprint};
+
+/// Set on responses that were shared from another caller's upstream call.
+pub const COALESCED_HEADER: &str = "x-proxy-coalesced";
+
+/// Single-flight deduplication: while a request is in flight upstream, later
+/// identical requests wait for it and receive a copy of its response, with
+/// streamed bodies broadcast chunk by chunk to every waiter.
+#[derive(Default)]
+pub struct Coalescer {
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+#[derive(Default)]
+struct Flight {
+    state: Mutex<FlightState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct FlightState {
+    head: Option<(StatusCode, HeaderMap)>,
+    /// Every chunk so far, so late joiners still see the whole body.
+    chunks: Vec<Bytes>,
+    /// `Some(complete)` once the leader's body has ended.
+    finished: Option<bool>,
+}
+
+pub enum Joined {
+    /// First caller: sends the request upstream and publishes the response.
+    Leader(FlightLeader),
+    /// Identical request already in flight: wait for its response.
+    Follower(FlightFollower),
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Key for a request, or `None` for requests without a body. The caller's
+    /// credential is part of the key so responses are never shared between keys,
+    /// as are the headers that select an API version or a content encoding.
+    pub fn key(
+        endpoint: &str,
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Option<String> {
+        if body.is_empty() {
+            return None;
+        }
+
+        Some(
+            Fingerprint::new()
+                .update("endpoint", endpoint.as_bytes())
+                .update("method", method.as_bytes())
+                .update("path", path.as_bytes())
+                .update("query", query.unwrap_or("").as_bytes())
+                .update("key", key_fingerprint(headers).unwrap_or_default().as_bytes())
+                .keyed_headers(headers)
+                .update("body", &canonical_json(body))
+                .finish(),
+        )
+    }
+
+    pub fn join(self: &Arc<Self>, key: String) -> Joined {
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(flight) = flights.get(&key) {
+            debug!("Joining in-flight request {}", key);
+            return Joined::Follower(FlightFollower {
+                flight: flight.clone(),
+            });
+        }
+
+        let flight = Arc::new(Flight::default());
+        flights.insert(key.clone(), flight.clone());
+        Joined::Leader(FlightLeader {
+            coalescer: self.clone(),
+            key,
+            flight,
+            published: false,
+        })
+    }
+
+    fn land(&self, key: &str, flight: &Arc<Flight>) {
+        let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+        if flights.get(key).is_some_and(|current| Arc::ptr_eq(current, flight)) {
+            flights.remove(key);
+        }
+    }
+}
+
+pub struct FlightLeader {
+    coalescer: Arc<Coalescer>,
+    key: String,
+    flight: Arc<Flight>,
+    published: bool,
+}
+
+impl FlightLeader {
+    /// Shares the response head with waiting followers and drives the
+    /// upstream body in its own task, so every caller, the leader's own
+    /// client included, reads the same chunks at its own pace. A client that
+    /// goes away, the leader's included, does not cut off the others.
+    pub fn publish(mut self, response: Response) -> Response {
+        self.published = true;
+        let (parts, body) = response.into_parts();
+        {
+            let mut state = self.flight.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.head = Some((parts.status, parts.headers.clone()));
+        }
+        self.flight.notify.notify_waiters();
+
+        let coalescer = self.coalescer.clone();
+        let key = std::mem::take(&mut self.key);
+        let flight = self.flight.clone();
+        tokio::spawn(async move {
+            let mut body = body.into_data_stream();
+            let complete = loop {
+                match body.next().await {
+                    Some(Ok(chunk)) => {
+                        {
+                            let mut state = flight.state.lock().unwrap_or_else(|e| e.into_inner());
+                            state.chunks.push(chunk);
+                        }
+                        flight.notify.notify_waiters();
+                    }
+                    Some(Err(e)) => {
+                        debug!("Coalesced upstream response failed: {}", e);
+                        break false;
+                    }
+                    None => break true,
+                }
+            };
+            coalescer.land(&key, &flight);
+            flight.finish(complete);
+        });
+
+        Response::from_parts(parts, self.flight.clone().body())
+    }
+}
+
+impl Drop for FlightLeader {
+    /// A leader that never got a response (e.g. upstream unreachable) fails
+    /// its followers instead of leaving them waiting.
+    fn drop(&mut self) {
+        if self.published {
+            return;
+        }
+        self.coalescer.land(&self.key, &self.flight);
+        self.flight.finish(false);
+    }
+}
+
+impl Flight {
+    fn finish(&self, complete: bool) {
+        {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.finished = Some(complete);
+        }
+        self.notify.notify_waiters();
+    }
+
+    /// The flight's body from its first chunk, ending as the upstream body did.
+    fn body(self: Arc<Self>) -> Body {
+        let chunks = futures::stream::unfold((Some(self), 0), |(flight, index)| async move {
+            let flight = flight?;
+            loop {
+                let notified = flight.notify.notified();
+                let next = {
+                    let state = flight.state.lock().unwrap_or_else(|e| e.into_inner());
+                    match (state.chunks.get(index), state.finished) {
+                        (Some(chunk), _) => Some(Some(Ok(chunk.clone()))),
+                        (None, Some(true)) => Some(None),
+                        (None, Some(false)) => Some(Some(Err(std::io::Error::other(
+                            "coalesced upstream response was interrupted",
+                        )))),
+                        (None, None) => None,
+                    }
+                };
+                match next {
+                    Some(Some(Ok(chunk))) => {
+                        drop(notified);
+                        return Some((Ok(chunk), (Some(flight), index + 1)));
+                    }
+                    Some(Some(Err(error))) => return Some((Err(error), (None, index))),
+                    Some(None) => return None,
+                    None => notified.await,
+                }
+            }
+        });
+        Body::from_stream(chunks)
+    }
+}
+
+pub struct FlightFollower {
+    flight: Arc<Flight>,
+}
+
+impl FlightFollower {
+    /// Waits for the leader's response, or `None` if the leader failed
+    /// before receiving one.
+    pub async fn response(self) -> Option<Response> {
+        let (status, headers) = loop {
+            let notified = self.flight.notify.notified();
+            {
+                let state = self.flight.state.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(head) = &state.head {
+                    break head.clone();
+                }
+                if state.finished.is_some() {
+                    return None;
+                }
+            }
+            notified.await;
+        };
+
+        let mut response = Response::new(self.flight.body());
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        response
+            .headers_mut()
+            .insert(COALESCED_HEADER, HeaderValue::from_static("true"));
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn expect_leader(joined: Joined) -> FlightLeader {
+        match joined {
+            Joined::Leader(leader) => leader,
+            Joined::Follower(_) => panic!("expected leader"),
+        }
+    }
+
+    fn expect_follower(joined: Joined) -> FlightFollower {
+        match joined {
+            Joined::Follower(follower) => follower,
+            Joined::Leader(_) => panic!("expected follower"),
+        }
+    }
+
+    #[test]
+    fn test_key_depends_on_body_and_credential() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-one"));
+        let a = Coalescer::key("dev", "POST", "/v1/messages", None, &headers, br#"{"a":1,"b":2}"#);
+        let b = Coalescer::key("dev", "POST", "/v1/messages", None, &headers, br#"{"b":2, "a":1}"#);
+        assert!(a.is_some());
+        assert_eq!(a, b);
+
+        headers.insert("x-api-key", HeaderValue::from_static("sk-two"));
+        let c = Coalescer::key("dev", "POST", "/v1/messages", None, &headers, br#"{"a":1,"b":2}"#);
+        assert_ne!(a, c);
+        assert!(Coalescer::key("dev", "GET", "/v1/models", None, &headers, b"").is_none());
+
+        // A caller that did not ask for gzip must not get a gzip leader's body
+        let gzip = [("x-api-key", "sk-two"), ("accept-encoding", "gzip")].into_iter()
+            .map(|(name, value)| (axum::http::HeaderName::from_static(name), HeaderValue::from_static(value)))
+            .collect::<HeaderMap>();
+        assert_ne!(c, Coalescer::key("dev", "POST", "/v1/messages", None, &gzip, br#"{"a":1,"b":2}"#));
+    }
+
+    #[tokio::test]
+    async fn test_followers_receive_broadcast_stream() {
+        let coalescer = Arc::new(Coalescer::new());
+        let leader = expect_leader(coalescer.join("k".to_string()));
+        let early = expect_follower(coalescer.join("k".to_string()));
+
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<Bytes, std::io::Error>>();
+        let response = Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .body(Body::from_stream(receiver))
+            .unwrap();
+        let response = leader.publish(response);
+
+        sender.unbounded_send(Ok(Bytes::from("data: 1\n\n"))).unwrap();
+        let early = early.response().await.unwrap();
+        assert_eq!(early.headers()[COALESCED_HEADER], "true");
+        assert_eq!(early.headers()["content-type"], "text/event-stream");
+
+        let mut leader_body = response.into_body().into_data_stream();
+        assert_eq!(leader_body.next().await.unwrap().unwrap(), "data: 1\n\n");
+
+        // Joining mid-stream still yields the whole body
+        let late = expect_follower(coalescer.join("k".to_string())).response().await.unwrap();
+
+        sender.unbounded_send(Ok(Bytes::from("data: 2\n\n"))).unwrap();
+        drop(sender);
+        while leader_body.next().await.is_some() {}
+
+        for response in [early, late] {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&body[..], b"data: 1\n\ndata: 2\n\n");
+        }
+
+        // The flight has landed, so the next caller leads a new one
+        expect_leader(coalescer.join("k".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_followers_outlive_departed_leader() {
+        let coalescer = Arc::new(Coalescer::new());
+        let leader = expect_leader(coalescer.join("k".to_string()));
+        let follower = expect_follower(coalescer.join("k".to_string()));
+
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<Bytes, std::io::Error>>();
+        let response = leader.publish(Response::new(Body::from_stream(receiver)));
+        sender.unbounded_send(Ok(Bytes::from("data: 1\n\n"))).unwrap();
+        let mut leader_body = response.into_body().into_data_stream();
+        assert_eq!(leader_body.next().await.unwrap().unwrap(), "data: 1\n\n");
+
+        // The leader's client goes away mid-stream; upstream keeps flowing
+        drop(leader_body);
+        sender.unbounded_send(Ok(Bytes::from("data: 2\n\n"))).unwrap();
+        drop(sender);
+
+        let follower = follower.response().await.unwrap();
+        let body = axum::body::to_bytes(follower.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"data: 1\n\ndata: 2\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_failed_leader_releases_followers() {
+        let coalescer = Arc::new(Coalescer::new());
+        let leader = expect_leader(coalescer.join("k".to_string()));
+        let waiting = expect_follower(coalescer.join("k".to_string()));
+
+        drop(leader);
+        assert!(waiting.response().await.is_none());
+        assert!(matches!(coalescer.join("k".to_string()), Joined::Leader(_)));
+    }
+}
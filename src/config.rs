@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
@@ -7,6 +7,22 @@ use std::path::Path;
 pub struct Config {
     pub server: ServerConfig,
     pub endpoints: HashMap<String, EndpointConfig>,
+    pub access_log: Option<AccessLogConfig>,
+    pub capture: Option<CaptureConfig>,
+    /// Model names or glob patterns → endpoint, for the unprefixed `/v1` route
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    /// Model aliases such as `fast` → concrete model, for every endpoint
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Settings for callers by `key_id`, the key fingerprint in the access log
+    #[serde(default)]
+    pub keys: HashMap<String, KeyConfig>,
+    pub prompts: Option<PromptsConfig>,
+    /// Context windows and output limits by model name or glob pattern,
+    /// adding to the built-in table
+    #[serde(default)]
+    pub model_capabilities: HashMap<String, ModelCapabilities>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -15,10 +31,427 @@ pub struct ServerConfig {
     pub target_base: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct EndpointConfig {
     pub proxy_url: Option<String>,
     pub target_base: Option<String>,
+    /// Protocol the upstream speaks (default: "anthropic")
+    pub api_type: Option<ApiType>,
+    /// Protocol clients speak to this endpoint (default: same as `api_type`)
+    pub client_api: Option<ApiType>,
+    pub capture: Option<EndpointCaptureConfig>,
+    /// "passthrough" (default), "record" or "replay"
+    pub mode: Option<EndpointMode>,
+    pub cassette: Option<CassetteConfig>,
+    pub cache: Option<CacheConfig>,
+    /// Share one upstream call between identical concurrent requests (default: false)
+    pub coalesce: Option<bool>,
+    /// Credentials and Vertex AI location for `api_type = "gemini"`
+    pub gemini: Option<GeminiConfig>,
+    /// Region and credentials for `api_type = "bedrock"`
+    pub bedrock: Option<BedrockConfig>,
+    /// Deployments and API version for `api_type = "azure"`
+    pub azure: Option<AzureConfig>,
+    /// Model name mapping for `api_type = "ollama"` and `"llamacpp"`
+    pub local: Option<LocalModelConfig>,
+    /// Model aliases for this endpoint, overriding the global `[aliases]`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Return the alias rather than the resolved model in responses (default: false)
+    pub restore_alias: Option<bool>,
+    /// Models, limits and features clients of this endpoint may use
+    pub policy: Option<PolicyConfig>,
+    /// Body rewrites, applied in order
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRule>,
+    /// Request and response header rules
+    pub headers: Option<HeaderRulesConfig>,
+    /// System prompt added to every request
+    pub system_prompt: Option<SystemPromptConfig>,
+    /// PII and secret redaction of outbound prompt text
+    pub redaction: Option<RedactionConfig>,
+    /// PII and secret redaction of completions, streamed or not
+    pub output_redaction: Option<RedactionConfig>,
+    /// External classifier consulted before and after upstream calls
+    pub guardrail: Option<GuardrailConfig>,
+    /// What to do with requests too large for their model's context window
+    pub context_guard: Option<ContextGuardConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AccessLogConfig {
+    pub enabled: Option<bool>,
+    pub output: Option<AccessLogOutput>,
+    /// Directory for `output = "file"` (default: /var/log/anthropic-http-proxy)
+    pub directory: Option<String>,
+    pub rotation: Option<LogRotation>,
+    /// Number of rotated files to keep (default: unlimited)
+    pub max_files: Option<usize>,
+}
+
+/// Where captured exchanges are written; capture itself is enabled per endpoint.
+#[derive(Debug, Default, Deserialize)]
+pub struct CaptureConfig {
+    /// Directory for capture files (default: /var/log/anthropic-http-proxy)
+    pub directory: Option<String>,
+    pub rotation: Option<LogRotation>,
+    pub max_files: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct EndpointCaptureConfig {
+    pub enabled: Option<bool>,
+    /// Fraction of requests to capture, from 0.0 to 1.0 (default: 1.0)
+    pub sample_rate: Option<f64>,
+    /// Extra headers to mask; credential headers are always masked
+    #[serde(default)]
+    pub redact_headers: Vec<String>,
+    /// JSONPath field masks applied to request and response bodies
+    #[serde(default)]
+    pub redact_fields: Vec<String>,
+    /// Regex masks applied to every string in headers and bodies
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogOutput {
+    #[default]
+    Stdout,
+    File,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiType {
+    /// Anthropic Messages API (`/v1/messages`)
+    #[default]
+    Anthropic,
+    /// OpenAI Chat Completions API (`/v1/chat/completions`)
+    OpenAI,
+    /// Google Gemini `generateContent`, on the Gemini API or Vertex AI
+    Gemini,
+    /// Claude on AWS Bedrock (`/model/{modelId}/invoke`), signed with SigV4
+    Bedrock,
+    /// Azure OpenAI (`/openai/deployments/{deployment}/chat/completions`)
+    Azure,
+    /// Ollama's native chat API (`/api/chat`)
+    Ollama,
+    /// llama.cpp server, vLLM and other local OpenAI-compatible servers
+    #[serde(alias = "vllm")]
+    LlamaCpp,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndpointMode {
+    #[default]
+    Passthrough,
+    /// Forward upstream and save every exchange to the cassette directory
+    Record,
+    /// Serve responses from the cassette directory without contacting upstream
+    Replay,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CassetteConfig {
+    /// Directory holding one JSON file per exchange (default: cassettes/<endpoint>)
+    pub directory: Option<String>,
+    /// Replay streamed chunks with their recorded delays (default: false)
+    pub preserve_timing: Option<bool>,
+    /// Extra headers left out of the request hash
+    #[serde(default)]
+    pub ignore_headers: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CacheConfig {
+    /// How long entries stay fresh (default: 3600)
+    pub ttl_seconds: Option<u64>,
+    /// In-memory LRU capacity (default: 1000)
+    pub max_entries: Option<usize>,
+    /// Optional directory for a persistent second tier
+    pub directory: Option<String>,
+    /// Only cache requests with `temperature = 0` (default: true)
+    pub deterministic_only: Option<bool>,
+}
+
+/// A virtual key: settings that follow one caller across endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub struct KeyConfig {
+    #[serde(flatten)]
+    pub policy: PolicyConfig,
+    /// System prompt added to this caller's requests, inside the endpoint's
+    pub system_prompt: Option<SystemPromptConfig>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct ModelCapabilities {
+    /// Prompt and output tokens together
+    pub context_window: u64,
+    pub max_output: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ContextGuardConfig {
+    /// What to do with a request that does not fit (default: "reject")
+    pub action: Option<ContextAction>,
+    /// Larger-context models to reroute to for `upgrade`, in order of preference
+    #[serde(default)]
+    pub upgrade_models: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextAction {
+    #[default]
+    Reject,
+    /// Drop the oldest turns, keeping system prompts and the latest message
+    Truncate,
+    /// Switch to the first of `upgrade_models` the request fits
+    Upgrade,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PromptsConfig {
+    /// Directory of named templates, one file per template named by its stem
+    pub directory: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SystemPromptConfig {
+    /// The prompt itself; exactly one of `text` and `template` is required
+    pub text: Option<String>,
+    /// A template from the prompts directory
+    pub template: Option<String>,
+    /// Values for the template's `{{name}}` placeholders; `text` is used verbatim
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// "prepend" (default) or "append" to the client's system prompt
+    pub position: Option<PromptPosition>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptPosition {
+    #[default]
+    Prepend,
+    Append,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PolicyConfig {
+    /// Model names or glob patterns clients may use (default: any)
+    pub allowed_models: Option<Vec<String>>,
+    /// Model names or glob patterns clients may not use
+    #[serde(default)]
+    pub denied_models: Vec<String>,
+    pub max_tokens: Option<u64>,
+    /// What to do with requests above `max_tokens` (default: "reject")
+    pub max_tokens_action: Option<LimitAction>,
+    pub max_thinking_budget: Option<u64>,
+    /// `anthropic-beta` flags clients may send (default: any)
+    pub allowed_betas: Option<Vec<String>>,
+    /// Whether requests may declare tools (default: true)
+    pub allow_tools: Option<bool>,
+    /// Whether requests may include images (default: true)
+    pub allow_images: Option<bool>,
+    /// Tool names or glob patterns that may be declared and called (default: any)
+    pub allowed_tools: Option<Vec<String>>,
+    /// Tool names or glob patterns that may not be declared or called
+    #[serde(default)]
+    pub denied_tools: Vec<String>,
+    /// What to do with a response calling a tool that is not allowed (default: "block")
+    pub tool_call_action: Option<ToolCallAction>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolCallAction {
+    /// Answer with an error instead; a stream is cut short with an error event
+    #[default]
+    Block,
+    /// Replace the call with a text message saying it was blocked
+    Rewrite,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    #[default]
+    Reject,
+    /// Lower the request's value to the limit and forward it
+    Clamp,
+}
+
+/// Header values may reference environment variables as `${NAME}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct HeaderRulesConfig {
+    /// Request headers added when the client sent none, e.g. `anthropic-version`
+    #[serde(default)]
+    pub default: HashMap<String, String>,
+    /// Request headers that replace the client's, e.g. a pinned `anthropic-version`
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    /// Request headers dropped before forwarding
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// `anthropic-beta` values passed upstream; others are dropped (default: all)
+    pub keep_betas: Option<Vec<String>>,
+    /// `anthropic-beta` values dropped before forwarding
+    #[serde(default)]
+    pub strip_betas: Vec<String>,
+    /// Upstream response headers dropped before they reach clients
+    #[serde(default)]
+    pub remove_response: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RedactionConfig {
+    /// Built-in detectors to run: email, phone, credit_card, aws_access_key,
+    /// private_key (default: all)
+    pub detectors: Option<Vec<String>>,
+    /// Custom detectors, by name, as regular expressions
+    #[serde(default)]
+    pub patterns: HashMap<String, String>,
+    /// What to do with a match (default: mask); responses cannot be rejected
+    pub action: Option<RedactionAction>,
+    /// Characters of streamed response text held back so values split
+    /// across deltas are caught (default: 256; 0 holds nothing back)
+    pub stream_window: Option<usize>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionAction {
+    /// Replace the match with its detector's name, e.g. `[EMAIL]`
+    #[default]
+    Mask,
+    /// Replace the match with a stable token, e.g. `[EMAIL_1f2e3d4c]`
+    Tokenize,
+    /// Refuse the request
+    Reject,
+}
+
+/// Header values may reference environment variables as `${NAME}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct GuardrailConfig {
+    /// Classifier URL; prompts and completions are POSTed here as JSON
+    pub url: String,
+    /// What to classify (default: both)
+    pub stages: Option<Vec<GuardrailStage>>,
+    /// Timeout for each classifier call (default: 2000)
+    pub timeout_ms: Option<u64>,
+    /// What to do when the classifier fails or times out (default: open)
+    pub on_error: Option<FailurePolicy>,
+    /// Headers sent to the classifier, e.g. its credentials
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardrailStage {
+    /// The prompt, before it is forwarded
+    Request,
+    /// The completion, before it reaches the client; streams are held until they end
+    Response,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Let the call through
+    #[default]
+    Open,
+    /// Block the call
+    Closed,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct RewriteRule {
+    pub op: RewriteOp,
+    /// JSON pointer into the body, e.g. `/metadata/user_id`
+    pub pointer: String,
+    /// Required for `set` and `default`
+    pub value: Option<serde_json::Value>,
+    /// "request" (default) or "response"; responses are rewritten only when not streamed
+    pub target: Option<RewriteTarget>,
+    /// Only for client paths matching this glob, e.g. `/v1/chat/*`
+    pub match_path: Option<String>,
+    /// Only for models matching this glob
+    pub match_model: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteOp {
+    /// Replace the value, creating missing parent objects
+    #[default]
+    Set,
+    /// Set the value only if it is absent
+    Default,
+    Remove,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteTarget {
+    #[default]
+    Request,
+    Response,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GeminiConfig {
+    /// Sent as `x-goog-api-key` in place of the client's credentials
+    pub api_key: Option<String>,
+    /// Service account JSON key, exchanged for OAuth access tokens
+    pub service_account_file: Option<String>,
+    /// Vertex AI project; with `location`, requests use Vertex AI paths
+    pub project: Option<String>,
+    pub location: Option<String>,
+}
+
+/// Unset credentials fall back to the `AWS_*` environment variables, then to
+/// the shared credentials file.
+#[derive(Debug, Default, Deserialize)]
+pub struct BedrockConfig {
+    /// AWS region (default: `AWS_REGION`, `AWS_DEFAULT_REGION` or the profile's region)
+    pub region: Option<String>,
+    /// Profile in the shared credentials and config files (default: `AWS_PROFILE` or "default")
+    pub profile: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AzureConfig {
+    /// `api-version` query parameter (default: "2024-10-21")
+    pub api_version: Option<String>,
+    /// Model name → deployment name; unlisted models use their own name
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LocalModelConfig {
+    /// Model names clients send → names the local server knows
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    /// Used for models missing from `models` (default: pass the name through)
+    pub default_model: Option<String>,
 }
 
 impl Config {
@@ -58,6 +491,13 @@ impl Default for Config {
                 target_base: Some("https://api.anthropic.com".to_string()),
             },
             endpoints,
+            access_log: None,
+            capture: None,
+            models: HashMap::new(),
+            aliases: HashMap::new(),
+            keys: HashMap::new(),
+            prompts: None,
+            model_capabilities: HashMap::new(),
         }
     }
 }
\ No newline at end of file
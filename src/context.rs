@@ -0,0 +1,268 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::{ApiType, ModelCapabilities};
+use crate::routing::ModelRouter;
+
+/// Response header saying how an oversize request was made to fit.
+pub const CONTEXT_HEADER: &str = "x-proxy-context";
+
+/// Rough cost of one image, whatever its size.
+const IMAGE_TOKENS: u64 = 1600;
+
+/// Framing tokens each message costs beyond its text.
+const MESSAGE_TOKENS: u64 = 4;
+
+/// Context windows and output limits of well-known models, by name or glob
+/// pattern; `[model_capabilities]` adds to and overrides these.
+const BUILTIN: &[(&str, u64, u64)] = &[
+    ("*claude-3-haiku*", 200_000, 4_096),
+    ("*claude-3-opus*", 200_000, 4_096),
+    ("*claude-3-5-haiku*", 200_000, 8_192),
+    ("*claude-3-5-sonnet*", 200_000, 8_192),
+    ("*claude-3-7-sonnet*", 200_000, 64_000),
+    ("*claude-sonnet-4*", 200_000, 64_000),
+    ("*claude-opus-4*", 200_000, 32_000),
+    ("gpt-3.5-turbo*", 16_385, 4_096),
+    ("gpt-4", 8_192, 8_192),
+    ("gpt-4-turbo*", 128_000, 4_096),
+    ("gpt-4o*", 128_000, 16_384),
+    ("gpt-4.1*", 1_047_576, 32_768),
+    ("o1*", 200_000, 100_000),
+    ("o3*", 200_000, 100_000),
+    ("gemini-1.5-flash*", 1_048_576, 8_192),
+    ("gemini-1.5-pro*", 2_097_152, 8_192),
+    ("gemini-2.0-flash*", 1_048_576, 8_192),
+    ("gemini-2.5-*", 1_048_576, 65_536),
+];
+
+/// What each model can take in and give back, looked up like `[models]`:
+/// exact names first, then the most specific pattern.
+#[derive(Debug, Default)]
+pub struct CapabilityRegistry {
+    patterns: ModelRouter,
+    capabilities: HashMap<String, ModelCapabilities>,
+}
+
+impl CapabilityRegistry {
+    pub fn new(configured: &HashMap<String, ModelCapabilities>) -> Self {
+        let mut capabilities: HashMap<String, ModelCapabilities> = BUILTIN
+            .iter()
+            .map(|(pattern, context_window, max_output)| {
+                let capability = ModelCapabilities {
+                    context_window: *context_window,
+                    max_output: Some(*max_output),
+                };
+                (pattern.to_string(), capability)
+            })
+            .collect();
+        capabilities.extend(configured.iter().map(|(pattern, capability)| (pattern.clone(), capability.clone())));
+        let patterns = capabilities.keys().map(|pattern| (pattern.clone(), pattern.clone())).collect();
+
+        Self {
+            patterns: ModelRouter::new(&patterns),
+            capabilities,
+        }
+    }
+
+    pub fn get(&self, model: &str) -> Option<&ModelCapabilities> {
+        self.patterns.endpoint(model).and_then(|pattern| self.capabilities.get(pattern))
+    }
+}
+
+/// A local estimate of a request's prompt tokens: about four characters a
+/// token over its text, tool definitions and structure, plus a fixed cost
+/// per image and per message. Meant to catch requests that clearly do not
+/// fit, not to match the provider's count.
+pub fn estimate_tokens(body: &Value) -> u64 {
+    let mut chars = 0;
+    let mut images = 0;
+    count(body, &mut chars, &mut images);
+    let messages = ["messages", "contents"]
+        .iter()
+        .filter_map(|field| body.get(field)?.as_array())
+        .map(|messages| messages.len() as u64)
+        .sum::<u64>();
+    chars.div_ceil(4) + images * IMAGE_TOKENS + messages * MESSAGE_TOKENS
+}
+
+fn count(value: &Value, chars: &mut u64, images: &mut u64) {
+    match value {
+        Value::String(text) => *chars += text.chars().count() as u64,
+        Value::Array(items) => items.iter().for_each(|item| count(item, chars, images)),
+        Value::Object(object) => {
+            // Base64 image data would count for far more than the image costs
+            let is_image = matches!(object.get("type").and_then(Value::as_str), Some("image" | "image_url"))
+                || object.contains_key("inlineData");
+            if is_image {
+                *images += 1;
+                return;
+            }
+            for (key, field) in object {
+                if key != "model" {
+                    *chars += key.len() as u64;
+                    count(field, chars, images);
+                }
+            }
+        }
+        Value::Number(_) | Value::Bool(_) | Value::Null => *chars += 1,
+    }
+}
+
+/// The output tokens a request reserves: its own limit, capped at the model's.
+pub fn requested_output(body: &Value, capabilities: &ModelCapabilities) -> u64 {
+    let requested = ["/max_tokens", "/max_completion_tokens", "/generationConfig/maxOutputTokens", "/options/num_predict"]
+        .iter()
+        .find_map(|pointer| body.pointer(pointer)?.as_u64())
+        .unwrap_or(0);
+    capabilities.max_output.map_or(requested, |max_output| requested.min(max_output))
+}
+
+/// Whether a request fits `capabilities`' context window with its output.
+pub fn fits(body: &Value, capabilities: &ModelCapabilities) -> bool {
+    estimate_tokens(body) + requested_output(body, capabilities) <= capabilities.context_window
+}
+
+/// Drops the oldest turns until the request fits, keeping system prompts and
+/// the latest message. Anthropic and Gemini conversations still start with a
+/// user turn and never with a tool result, and OpenAI ones never with an
+/// orphaned tool message. Returns how many messages were dropped, or `None`
+/// if no valid tail of the conversation fits: even the latest message alone
+/// is too long, or what would be left cannot open a conversation.
+pub fn truncate(client: ApiType, body: &mut Value, capabilities: &ModelCapabilities) -> Option<usize> {
+    let field = match client {
+        ApiType::Gemini => "contents",
+        _ => "messages",
+    };
+    let protected = |message: &Value| matches!(message["role"].as_str(), Some("system" | "developer"));
+    let mut dropped = 0;
+    while !fits(body, capabilities) {
+        let messages = body.get_mut(field)?.as_array_mut()?;
+        if messages.iter().filter(|message| !protected(message)).count() <= 1 {
+            return None;
+        }
+        let oldest = messages.iter().position(|message| !protected(message))?;
+        messages.remove(oldest);
+        dropped += 1;
+
+        // Turns that cannot open a conversation go with the one before them
+        while let Some(first) = messages.iter().position(|message| !protected(message)) {
+            if starts_conversation(client, &messages[first]) {
+                break;
+            }
+            if messages.iter().filter(|message| !protected(message)).count() <= 1 {
+                return None;
+            }
+            messages.remove(first);
+            dropped += 1;
+        }
+    }
+    Some(dropped)
+}
+
+fn starts_conversation(client: ApiType, message: &Value) -> bool {
+    match client {
+        ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp | ApiType::Ollama => message["role"] != "tool",
+        ApiType::Anthropic | ApiType::Bedrock => {
+            let tool_result = message["content"].as_array().is_some_and(|blocks| blocks.iter().any(|block| block["type"] == "tool_result"));
+            message["role"] == "user" && !tool_result
+        }
+        ApiType::Gemini => {
+            let function_response = message["parts"].as_array().is_some_and(|parts| parts.iter().any(|part| part.get("functionResponse").is_some()));
+            message["role"] == "user" && !function_response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn small(context_window: u64) -> ModelCapabilities {
+        ModelCapabilities {
+            context_window,
+            max_output: Some(100),
+        }
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        let configured = [
+            ("gpt-4o*".to_string(), small(64_000)),
+            ("acme-local".to_string(), small(4_096)),
+        ]
+        .into();
+        let registry = CapabilityRegistry::new(&configured);
+
+        assert_eq!(registry.get("claude-3-5-sonnet-latest").unwrap().context_window, 200_000);
+        assert_eq!(registry.get("anthropic.claude-3-5-haiku-20241022-v1:0").unwrap().max_output, Some(8_192));
+        assert_eq!(registry.get("gpt-4o-mini").unwrap().context_window, 64_000);
+        assert_eq!(registry.get("gpt-4").unwrap().context_window, 8_192);
+        assert_eq!(registry.get("acme-local").unwrap().context_window, 4_096);
+        assert!(registry.get("mystery-model").is_none());
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        let text = "word ".repeat(400);
+        let body = json!({ "model": "claude-3-5-sonnet-latest", "max_tokens": 4096, "messages": [{ "role": "user", "content": text }] });
+        let estimate = estimate_tokens(&body);
+        assert!((500..560).contains(&estimate), "estimate was {}", estimate);
+        assert_eq!(requested_output(&body, &small(1_000)), 100);
+
+        let image = json!({ "messages": [{ "role": "user", "content": [
+            { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "A".repeat(100_000) } }
+        ] }] });
+        assert!(estimate_tokens(&image) < IMAGE_TOKENS + 50);
+    }
+
+    #[test]
+    fn test_truncate_keeps_system_and_latest() {
+        let turn = |role: &str, text: &str| json!({ "role": role, "content": text.repeat(200) });
+        let mut body = json!({ "messages": [
+            { "role": "system", "content": "Be brief." },
+            turn("user", "a"), turn("assistant", "b"), turn("tool", "c"), turn("user", "d"), turn("assistant", "e"), turn("user", "f"),
+        ] });
+        let capabilities = small(100);
+        assert_eq!(truncate(ApiType::OpenAI, &mut body, &capabilities), Some(5));
+        let roles: Vec<_> = body["messages"].as_array().unwrap().iter().map(|m| m["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, ["system", "user"]);
+        assert!(body["messages"][1]["content"].as_str().unwrap().starts_with('f'));
+
+        let mut body = json!({ "system": "Be brief.", "messages": [
+            turn("user", "a"),
+            { "role": "assistant", "content": [{ "type": "tool_use", "id": "t", "name": "x", "input": {} }] },
+            { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "t", "content": "c".repeat(200) }] },
+            turn("assistant", "d"),
+            turn("user", "e"),
+        ] });
+        assert_eq!(truncate(ApiType::Anthropic, &mut body, &small(120)), Some(4));
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["system"], "Be brief.");
+
+        let mut body = json!({ "messages": [turn("user", "x".repeat(20).as_str())] });
+        assert_eq!(truncate(ApiType::Anthropic, &mut body, &small(200)), None);
+    }
+
+    #[test]
+    fn test_truncate_refuses_conversations_that_cannot_start() {
+        // Dropping the long tool call would leave only its result
+        let mut body = json!({ "messages": [
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "a".repeat(400) },
+            { "role": "assistant", "content": null, "tool_calls": [
+                { "id": "c", "type": "function", "function": { "name": "x", "arguments": "b".repeat(400) } }
+            ] },
+            { "role": "tool", "tool_call_id": "c", "content": "ok" },
+        ] });
+        assert_eq!(truncate(ApiType::OpenAI, &mut body, &small(100)), None);
+
+        let mut body = json!({ "messages": [
+            { "role": "user", "content": "a".repeat(400) },
+            { "role": "assistant", "content": [{ "type": "tool_use", "id": "t", "name": "x", "input": { "q": "b".repeat(400) } }] },
+            { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "t", "content": "ok" }] },
+        ] });
+        assert_eq!(truncate(ApiType::Anthropic, &mut body, &small(100)), None);
+    }
+}
@@ -0,0 +1,74 @@
+use axum::http::HeaderMap;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Request headers that change what the provider returns, or how it is
+/// encoded; a response is only shared between requests that agree on them.
+pub const KEYED_HEADERS: &[&str] = &["anthropic-version", "anthropic-beta", "accept-encoding"];
+
+/// Re-serialises a JSON body with sorted keys and no insignificant whitespace,
+/// so logically identical requests produce identical bytes. Non-JSON bodies
+/// are returned unchanged.
+pub fn canonical_json(body: &[u8]) -> Vec<u8> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(value) => value.to_string().into_bytes(),
+        Err(_) => body.to_vec(),
+    }
+}
+
+/// Builds a stable SHA-256 key from labelled request parts.
+#[derive(Default)]
+pub struct Fingerprint {
+    hasher: Sha256,
+}
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a labelled part; lengths are included so parts cannot run together.
+    pub fn update(mut self, label: &str, value: &[u8]) -> Self {
+        for part in [label.as_bytes(), value] {
+            self.hasher.update((part.len() as u64).to_be_bytes());
+            self.hasher.update(part);
+        }
+        self
+    }
+
+    /// Adds every value of the [`KEYED_HEADERS`] the request carries.
+    pub fn keyed_headers(mut self, headers: &HeaderMap) -> Self {
+        for name in KEYED_HEADERS {
+            for value in headers.get_all(*name) {
+                self = self.update(name, value.as_bytes());
+            }
+        }
+        self
+    }
+
+    pub fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_json_ignores_key_order_and_whitespace() {
+        let a = canonical_json(br#"{"model": "claude", "temperature": 0, "messages": []}"#);
+        let b = canonical_json(br#"{"messages":[],"temperature":0,"model":"claude"}"#);
+        assert_eq!(a, b);
+
+        assert_eq!(canonical_json(b"not json"), b"not json".to_vec());
+    }
+
+    #[test]
+    fn test_fingerprint_parts_do_not_run_together() {
+        let a = Fingerprint::new().update("path", b"/v1/ab").update("body", b"c").finish();
+        let b = Fingerprint::new().update("path", b"/v1/a").update("body", b"bc").finish();
+        assert_ne!(a, b);
+        assert_eq!(a, Fingerprint::new().update("path", b"/v1/ab").update("body", b"c").finish());
+    }
+}
@@ -0,0 +1,209 @@
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::config::GeminiConfig;
+
+pub const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+/// Lifetime requested for the signed assertion.
+const ASSERTION_LIFETIME_SECS: u64 = 3600;
+/// Tokens are refreshed this long before Google says they expire.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Credentials the proxy presents to Gemini on behalf of its clients.
+pub struct GoogleAuth {
+    credential: Credential,
+    token: Mutex<Option<AccessToken>>,
+}
+
+enum Credential {
+    ApiKey(String),
+    ServiceAccount(ServiceAccountKey),
+}
+
+/// The fields of a service account JSON key that the token exchange needs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default)]
+    pub private_key_id: Option<String>,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    DEFAULT_TOKEN_URI.to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    iss: String,
+    scope: String,
+    aud: String,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default = "default_expires_in")]
+    expires_in: u64,
+}
+
+fn default_expires_in() -> u64 {
+    ASSERTION_LIFETIME_SECS
+}
+
+struct AccessToken {
+    value: String,
+    refresh_at: Instant,
+}
+
+impl GoogleAuth {
+    /// `None` when the endpoint leaves credentials to its clients.
+    pub fn from_config(config: &GeminiConfig) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let credential = if let Some(path) = &config.service_account_file {
+            let key = std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read service account key {}: {}", path, e))?;
+            let key: ServiceAccountKey = serde_json::from_str(&key)
+                .map_err(|e| format!("invalid service account key {}: {}", path, e))?;
+            // Fail at startup rather than on the first request
+            EncodingKey::from_rsa_pem(key.private_key.as_bytes())?;
+            Credential::ServiceAccount(key)
+        } else if let Some(api_key) = &config.api_key {
+            Credential::ApiKey(api_key.clone())
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self::new(credential)))
+    }
+
+    fn new(credential: Credential) -> Self {
+        Self {
+            credential,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// The credential header to send upstream, exchanging the service
+    /// account assertion for an access token when none is cached.
+    pub async fn header(&self, client: &reqwest::Client) -> Result<(&'static str, String), Box<dyn std::error::Error + Send + Sync>> {
+        match &self.credential {
+            Credential::ApiKey(key) => Ok(("x-goog-api-key", key.clone())),
+            Credential::ServiceAccount(key) => {
+                let token = self.access_token(client, key).await?;
+                Ok(("authorization", format!("Bearer {}", token)))
+            }
+        }
+    }
+
+    async fn access_token(
+        &self,
+        client: &reqwest::Client,
+        key: &ServiceAccountKey,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        // Held across the exchange so concurrent requests share one refresh
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref().filter(|token| Instant::now() < token.refresh_at) {
+            return Ok(token.value.clone());
+        }
+
+        debug!("Exchanging service account assertion at {}", key.token_uri);
+        let response = client
+            .post(&key.token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", &assertion(key, now_secs())?)])
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("token exchange failed with {}: {}", status, body).into());
+        }
+
+        let token: TokenResponse = response.json().await?;
+        let lifetime = Duration::from_secs(token.expires_in).saturating_sub(REFRESH_MARGIN);
+        *cached = Some(AccessToken {
+            value: token.access_token.clone(),
+            refresh_at: Instant::now() + lifetime,
+        });
+        Ok(token.access_token)
+    }
+}
+
+/// Signs the RS256 JWT that Google's token endpoint exchanges for an access token.
+fn assertion(key: &ServiceAccountKey, now: u64) -> Result<String, jsonwebtoken::errors::Error> {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = key.private_key_id.clone();
+    let claims = Claims {
+        iss: key.client_email.clone(),
+        scope: CLOUD_PLATFORM_SCOPE.to_string(),
+        aud: key.token_uri.clone(),
+        iat: now,
+        exp: now + ASSERTION_LIFETIME_SECS,
+    };
+    jsonwebtoken::encode(&header, &claims, &EncodingKey::from_rsa_pem(key.private_key.as_bytes())?)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+
+    const PRIVATE_KEY: &str = include_str!("../tests/fixtures/service_account.key.pem");
+    const PUBLIC_KEY: &str = include_str!("../tests/fixtures/service_account.pub.pem");
+
+    fn key() -> ServiceAccountKey {
+        ServiceAccountKey {
+            client_email: "proxy@acme.iam.gserviceaccount.com".to_string(),
+            private_key: PRIVATE_KEY.to_string(),
+            private_key_id: Some("key-1".to_string()),
+            token_uri: "http://127.0.0.1:1/token".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_assertion_is_signed_for_token_endpoint() {
+        let token = assertion(&key(), now_secs()).unwrap();
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&["http://127.0.0.1:1/token"]);
+        validation.set_issuer(&["proxy@acme.iam.gserviceaccount.com"]);
+        let decoded = jsonwebtoken::decode::<Claims>(
+            &token,
+            &DecodingKey::from_rsa_pem(PUBLIC_KEY.as_bytes()).unwrap(),
+            &validation,
+        )
+        .unwrap();
+
+        assert_eq!(decoded.header.kid.as_deref(), Some("key-1"));
+        assert_eq!(decoded.claims.scope, CLOUD_PLATFORM_SCOPE);
+        assert_eq!(decoded.claims.exp - decoded.claims.iat, ASSERTION_LIFETIME_SECS);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_header() {
+        let config = GeminiConfig {
+            api_key: Some("AIza-test".to_string()),
+            ..Default::default()
+        };
+        let auth = GoogleAuth::from_config(&config).unwrap().unwrap();
+        let header = auth.header(&reqwest::Client::new()).await.unwrap();
+        assert_eq!(header, ("x-goog-api-key", "AIza-test".to_string()));
+
+        assert!(GoogleAuth::from_config(&GeminiConfig::default()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::{FailurePolicy, GuardrailConfig, GuardrailStage};
+use crate::headers::expand_env;
+use crate::pii;
+use crate::sse::{self, SseEvent};
+use crate::translate::ollama::NDJSON_CONTENT_TYPE;
+
+/// Response header naming a guardrail verdict other than `pass`.
+pub const GUARDRAIL_HEADER: &str = "x-proxy-guardrail";
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Pass,
+    /// Let the call through, but mark it for review
+    Flag,
+    Block,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Flag => "flag",
+            Verdict::Block => "block",
+        }
+    }
+}
+
+/// The classifier's answer: `{"verdict": "block", "reason": "..."}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Classification {
+    pub verdict: Verdict,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// What the classifier is sent.
+#[derive(Debug, Serialize)]
+pub struct ClassifyRequest<'a> {
+    pub stage: GuardrailStage,
+    pub endpoint: &'a str,
+    pub model: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+    pub text: String,
+}
+
+/// An endpoint's external classifier.
+#[derive(Debug)]
+pub struct Guardrail {
+    client: reqwest::Client,
+    url: String,
+    stages: Vec<GuardrailStage>,
+    timeout: Duration,
+    on_error: FailurePolicy,
+    headers: Vec<(String, String)>,
+}
+
+impl Guardrail {
+    pub fn from_config(config: &GuardrailConfig, client: reqwest::Client) -> Result<Self, Box<dyn std::error::Error>> {
+        reqwest::Url::parse(&config.url).map_err(|e| format!("guardrail url '{}': {}", config.url, e))?;
+        let headers = config.headers.iter()
+            .map(|(name, value)| Ok((name.clone(), expand_env(value)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            stages: config.stages.clone().unwrap_or_else(|| vec![GuardrailStage::Request, GuardrailStage::Response]),
+            timeout: Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)),
+            on_error: config.on_error.unwrap_or_default(),
+            headers,
+        })
+    }
+
+    pub fn checks(&self, stage: GuardrailStage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    /// Asks the classifier for a verdict. A failed, slow or garbled answer
+    /// becomes `pass` or `block` according to the failure policy.
+    pub async fn classify(&self, request: &ClassifyRequest<'_>) -> Classification {
+        match self.call(request).await {
+            Ok(classification) => classification,
+            Err(e) => {
+                warn!("Guardrail classifier failed (failing {:?}): {}", self.on_error, e);
+                self.fallback("guardrail classifier unavailable")
+            }
+        }
+    }
+
+    /// The verdict for a call that could not be classified: `pass` when
+    /// failing open, `block` with `reason` when failing closed.
+    pub fn fallback(&self, reason: &str) -> Classification {
+        match self.on_error {
+            FailurePolicy::Open => Classification { verdict: Verdict::Pass, reason: None },
+            FailurePolicy::Closed => Classification {
+                verdict: Verdict::Block,
+                reason: Some(reason.to_string()),
+            },
+        }
+    }
+
+    async fn call(&self, request: &ClassifyRequest<'_>) -> Result<Classification, reqwest::Error> {
+        let mut builder = self.client.post(&self.url);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        builder
+            .timeout(self.timeout)
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+/// The completion text of a response body: a buffered JSON message, or the
+/// deltas of an event stream or NDJSON stream joined back up. `None` for
+/// bodies the proxy cannot read, such as Bedrock's binary event streams.
+pub fn response_text(content_type: &str, body: &[u8]) -> Option<String> {
+    if content_type.starts_with("application/json") {
+        let body = serde_json::from_slice::<Value>(body).ok()?;
+        return Some(pii::message_texts(&body).join("\n\n"));
+    }
+    let chunks: Vec<Value> = if content_type.starts_with("text/event-stream") {
+        sse::parse_all(body).iter().filter_map(SseEvent::json_data).collect()
+    } else if content_type.starts_with(NDJSON_CONTENT_TYPE) {
+        body.split(|byte| *byte == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<_, _>>()
+            .ok()?
+    } else {
+        return None;
+    };
+    Some(chunks.iter().flat_map(pii::message_texts).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+    use tokio::net::TcpListener;
+
+    async fn classifier(answer: &'static str, delay: Duration) -> String {
+        let app = Router::new().route(
+            "/classify",
+            post(move |Json(request): Json<serde_json::Value>| async move {
+                tokio::time::sleep(delay).await;
+                let answer = if request["text"].as_str().unwrap().contains("attack") { answer } else { r#"{"verdict":"pass"}"# };
+                ([("content-type", "application/json")], answer)
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/classify", addr)
+    }
+
+    fn guardrail(url: String, on_error: FailurePolicy) -> Guardrail {
+        let config = GuardrailConfig {
+            url,
+            stages: Some(vec![GuardrailStage::Request]),
+            timeout_ms: Some(100),
+            on_error: Some(on_error),
+            ..Default::default()
+        };
+        Guardrail::from_config(&config, reqwest::Client::new()).unwrap()
+    }
+
+    fn request(text: &str) -> ClassifyRequest<'_> {
+        ClassifyRequest {
+            stage: GuardrailStage::Request,
+            endpoint: "api",
+            model: Some("claude-3-5-sonnet-latest"),
+            request_id: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verdicts() {
+        let url = classifier(r#"{"verdict":"block","reason":"prompt injection"}"#, Duration::ZERO).await;
+        let guardrail = guardrail(url, FailurePolicy::Open);
+        assert!(guardrail.checks(GuardrailStage::Request));
+        assert!(!guardrail.checks(GuardrailStage::Response));
+
+        assert_eq!(guardrail.classify(&request("hello")).await.verdict, Verdict::Pass);
+        let blocked = guardrail.classify(&request("an attack")).await;
+        assert_eq!(blocked.verdict, Verdict::Block);
+        assert_eq!(blocked.reason.as_deref(), Some("prompt injection"));
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() {
+        let url = classifier(r#"{"verdict":"flag"}"#, Duration::from_millis(500)).await;
+        assert_eq!(guardrail(url.clone(), FailurePolicy::Open).classify(&request("attack")).await.verdict, Verdict::Pass);
+        assert_eq!(guardrail(url, FailurePolicy::Closed).classify(&request("attack")).await.verdict, Verdict::Block);
+
+        let url = classifier("not json", Duration::ZERO).await;
+        assert_eq!(guardrail(url, FailurePolicy::Closed).classify(&request("attack")).await.verdict, Verdict::Block);
+    }
+
+    #[test]
+    fn test_response_text() {
+        let json = br#"{"content":[{"type":"text","text":"Hello there"}]}"#;
+        assert_eq!(response_text("application/json", json).as_deref(), Some("Hello there"));
+
+        let anthropic = concat!(
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+            "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"lo\"}}\n\n",
+        );
+        assert_eq!(response_text("text/event-stream", anthropic.as_bytes()).as_deref(), Some("Hello"));
+
+        let openai = "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: [DONE]\n\n";
+        assert_eq!(response_text("text/event-stream; charset=utf-8", openai.as_bytes()).as_deref(), Some("Hi"));
+
+        let ollama = "{\"message\":{\"role\":\"assistant\",\"content\":\"Hi \"}}\n{\"message\":{\"role\":\"assistant\",\"content\":\"there\"},\"done\":true}\n";
+        assert_eq!(response_text(NDJSON_CONTENT_TYPE, ollama.as_bytes()).as_deref(), Some("Hi there"));
+
+        assert_eq!(response_text("application/json", b"{\"content\":").as_deref(), None);
+        assert_eq!(response_text("application/vnd.amazon.eventstream", b"\x00\x00").as_deref(), None);
+    }
+}
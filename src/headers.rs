@@ -0,0 +1,173 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::config::HeaderRulesConfig;
+
+const ANTHROPIC_BETA: &str = "anthropic-beta";
+
+/// An endpoint's header rules, parsed and with environment variables
+/// expanded once at startup.
+#[derive(Debug, Default)]
+pub struct HeaderRules {
+    default: Vec<(HeaderName, HeaderValue)>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    remove: Vec<HeaderName>,
+    keep_betas: Option<Vec<String>>,
+    strip_betas: Vec<String>,
+    remove_response: Vec<HeaderName>,
+}
+
+impl HeaderRules {
+    pub fn from_config(config: &HeaderRulesConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let values = |headers: &std::collections::HashMap<String, String>| {
+            headers
+                .iter()
+                .map(|(name, value)| Ok((HeaderName::try_from(name.as_str())?, HeaderValue::try_from(expand_env(value)?)?)))
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()
+        };
+        let names = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| HeaderName::try_from(name.as_str()))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            default: values(&config.default)?,
+            set: values(&config.set)?,
+            remove: names(&config.remove)?,
+            keep_betas: config.keep_betas.clone(),
+            strip_betas: config.strip_betas.clone(),
+            remove_response: names(&config.remove_response)?,
+        })
+    }
+
+    /// Removals first, then `anthropic-beta` filtering, then defaults and
+    /// overrides, so a rule can replace a header it also removes.
+    pub fn apply_request(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        self.filter_betas(headers);
+        for (name, value) in &self.default {
+            if !headers.contains_key(name) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+
+    pub fn apply_response(&self, headers: &mut HeaderMap) {
+        for name in &self.remove_response {
+            headers.remove(name);
+        }
+    }
+
+    fn filter_betas(&self, headers: &mut HeaderMap) {
+        if self.keep_betas.is_none() && self.strip_betas.is_empty() {
+            return;
+        }
+        let kept: Vec<String> = headers
+            .get_all(ANTHROPIC_BETA)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|beta| !beta.is_empty())
+            .filter(|beta| self.keep_betas.as_ref().is_none_or(|keep| keep.iter().any(|kept| kept == beta)))
+            .filter(|beta| !self.strip_betas.iter().any(|stripped| stripped == beta))
+            .map(str::to_string)
+            .collect();
+
+        headers.remove(ANTHROPIC_BETA);
+        if kept.is_empty() {
+            return;
+        }
+        if let Ok(value) = HeaderValue::try_from(kept.join(",")) {
+            headers.insert(ANTHROPIC_BETA, value);
+        }
+    }
+}
+
+/// Replaces each `${NAME}` with the environment variable's value.
+pub fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed '${{' in '{}'", value))?;
+        let name = &rest[start + 2..start + end];
+        let variable = std::env::var(name).map_err(|_| format!("environment variable '{}' is not set", name))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&variable);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(config: HeaderRulesConfig) -> HeaderRules {
+        HeaderRules::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_request_rules() {
+        let rules = rules(HeaderRulesConfig {
+            default: [("anthropic-version".to_string(), "2023-06-01".to_string())].into(),
+            set: [("user-agent".to_string(), "gateway/1.0".to_string())].into(),
+            remove: vec!["x-debug".to_string()],
+            strip_betas: vec!["computer-use-2024-10-22".to_string()],
+            ..Default::default()
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", HeaderValue::from_static("curl/8"));
+        headers.insert("x-debug", HeaderValue::from_static("1"));
+        headers.insert(ANTHROPIC_BETA, HeaderValue::from_static("prompt-caching-2024-07-31, computer-use-2024-10-22"));
+        rules.apply_request(&mut headers);
+
+        assert_eq!(headers["anthropic-version"], "2023-06-01");
+        assert_eq!(headers["user-agent"], "gateway/1.0");
+        assert!(!headers.contains_key("x-debug"));
+        assert_eq!(headers[ANTHROPIC_BETA], "prompt-caching-2024-07-31");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("anthropic-version", HeaderValue::from_static("2024-01-01"));
+        rules.apply_request(&mut headers);
+        assert_eq!(headers["anthropic-version"], "2024-01-01");
+    }
+
+    #[test]
+    fn test_keep_betas_drops_the_rest() {
+        let rules = rules(HeaderRulesConfig {
+            keep_betas: Some(vec!["prompt-caching-2024-07-31".to_string()]),
+            remove_response: vec!["cf-ray".to_string()],
+            ..Default::default()
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ANTHROPIC_BETA, HeaderValue::from_static("max-tokens-3-5-sonnet-2024-07-15"));
+        rules.apply_request(&mut headers);
+        assert!(!headers.contains_key(ANTHROPIC_BETA));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-ray", HeaderValue::from_static("8f1a"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        rules.apply_response(&mut headers);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_expand_env() {
+        temp_env::with_var("HEADER_RULES_TEST_TOKEN", Some("s3cret"), || {
+            assert_eq!(expand_env("Bearer ${HEADER_RULES_TEST_TOKEN}").unwrap(), "Bearer s3cret");
+            assert_eq!(expand_env("plain").unwrap(), "plain");
+            assert!(expand_env("${HEADER_RULES_TEST_MISSING}").is_err());
+            assert!(expand_env("${HEADER_RULES_TEST_TOKEN").is_err());
+        });
+    }
+}
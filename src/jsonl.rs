@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Mutex;
+use tracing::error;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+use crate::config::LogRotation;
+
+/// Appends one JSON document per line to stdout or a rotating file.
+pub struct JsonlWriter {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonlWriter {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+
+    /// Writes `{prefix}.{date}.{suffix}` files under `directory`, keeping at most
+    /// `max_files` of them when set.
+    pub fn rolling(
+        directory: &str,
+        prefix: &str,
+        suffix: &str,
+        rotation: LogRotation,
+        max_files: Option<usize>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rotation = match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(prefix)
+            .filename_suffix(suffix);
+        if let Some(max_files) = max_files {
+            builder = builder.max_log_files(max_files);
+        }
+
+        Ok(Self::new(Box::new(builder.build(directory)?)))
+    }
+
+    pub fn write<T: Serialize>(&self, record: &T) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize JSONL record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writer.write_all(&line).and_then(|_| writer.flush()) {
+            error!("Failed to write JSONL record: {}", e);
+        }
+    }
+}
@@ -1,5 +1,31 @@
+pub mod access_log;
+pub mod alias;
+pub mod aws;
+pub mod body;
+pub mod cache;
+pub mod capture;
+pub mod cassette;
+pub mod coalesce;
 pub mod config;
+pub mod context;
+pub mod fingerprint;
+pub mod google;
+pub mod guardrail;
+pub mod headers;
+pub mod jsonl;
+pub mod pii;
+pub mod policy;
+pub mod prompts;
 pub mod proxy;
+pub mod redact;
+pub mod replay;
+pub mod request_id;
+pub mod rewrite;
+pub mod routing;
+pub mod sse;
+pub mod tools;
+pub mod translate;
+pub mod usage;
 
 pub use config::Config;
 pub use proxy::ProxyService;
\ No newline at end of file
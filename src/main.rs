@@ -1,63 +1,116 @@
 use axum::{
-    extract::{Path, Request},
+    extract::{Path, Request, State},
     http::StatusCode,
     response::Response,
     routing::any,
     Router,
 };
+use std::collections::HashMap;
 use std::env;
-use tracing::info;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info};
 
-mod config;
-mod proxy;
-use config::Config;
-use proxy::ProxyService;
+use anthropic_http_proxy::replay::{self, ReplayOptions};
+use anthropic_http_proxy::{Config, ProxyService};
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
     
-    // Load configuration
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("replay") => run_replay(&args[1..]).await,
+        _ => run_server().await,
+    }
+}
+
+fn load_config() -> Config {
     let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
-    let config = match Config::from_file(&config_path) {
+    match Config::from_file(&config_path) {
         Ok(config) => config,
         Err(e) => {
             info!("Failed to load config from {}: {}, using defaults", config_path, e);
             Config::default()
         }
+    }
+}
+
+async fn build_proxy_service(config: Config) -> Arc<ProxyService> {
+    match ProxyService::new_with_config(config).await {
+        Ok(proxy_service) => Arc::new(proxy_service),
+        Err(e) => {
+            error!("Failed to initialise proxy: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_replay(args: &[String]) {
+    let options = match ReplayOptions::from_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, replay::USAGE);
+            std::process::exit(2);
+        }
     };
+
+    let proxy_service = build_proxy_service(load_config()).await;
+    match replay::run(proxy_service, &options).await {
+        Ok(summary) => info!(
+            "Replayed {} requests: {} status changes, {} errors",
+            summary.total, summary.status_changed, summary.errors
+        ),
+        Err(e) => {
+            error!("Replay failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_server() {
+    // Load configuration
+    let config = load_config();
     
     let port = config.server.port.unwrap_or(8811);
     let addr = format!("0.0.0.0:{}", port);
     
     info!("Starting Anthropic HTTP proxy on {}", addr);
     info!("Loaded configuration with {} endpoints", config.endpoints.len());
+
+    // The service holds shared state (clients, access log), so build it once
+    let proxy_service = build_proxy_service(config).await;
     
     let app = Router::new()
         .route("/:prefix/v1/*path", any(proxy_handler))
         .route("/:prefix/v1", any(proxy_handler))
+        .route("/:prefix/v1beta/*path", any(proxy_handler))
+        .route("/v1/*path", any(routed_handler))
         .fallback(|| async { 
             (StatusCode::NOT_FOUND, "Not Found") 
-        });
+        })
+        .with_state(proxy_service);
     
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
 async fn proxy_handler(
-    Path(prefix): Path<String>,
+    State(proxy_service): State<Arc<ProxyService>>,
+    Path(params): Path<HashMap<String, String>>,
     request: Request,
 ) -> Result<Response, StatusCode> {
-    // Load configuration for each request (in production, you'd want to cache this)
-    let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
-    let config = match Config::from_file(&config_path) {
-        Ok(config) => config,
-        Err(_) => Config::default(),
-    };
-    
-    let proxy_service = ProxyService::new_with_config(config).await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let prefix = params.get("prefix").cloned().unwrap_or_default();
     
     proxy_service.handle_request(prefix, request).await
-}
\ No newline at end of file
+}
+
+async fn routed_handler(
+    State(proxy_service): State<Arc<ProxyService>>,
+    request: Request,
+) -> Result<Response, StatusCode> {
+    proxy_service.handle_routed(request).await
+}
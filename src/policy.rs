@@ -0,0 +1,259 @@
+use axum::http::{HeaderMap, StatusCode};
+use serde_json::Value;
+
+use crate::config::{ApiType, LimitAction, PolicyConfig};
+use crate::routing::glob_match;
+
+/// A request a policy refuses, answered without contacting upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl Violation {
+    fn forbidden(message: String) -> Self {
+        Self { status: StatusCode::FORBIDDEN, message }
+    }
+
+    fn invalid(message: String) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, message }
+    }
+}
+
+/// Whether `policy`'s allowed and denied patterns admit `model`.
+pub fn model_allowed(policy: &PolicyConfig, model: &str) -> bool {
+    policy.allowed_models.as_ref().is_none_or(|allowed| allowed.iter().any(|pattern| glob_match(pattern, model)))
+        && !policy.denied_models.iter().any(|pattern| glob_match(pattern, model))
+}
+
+/// Checks a request against `policy`, lowering `max_tokens` where the policy
+/// clamps rather than rejects. `body` is in the client's protocol, and `model`
+/// comes from the body or, for Gemini, the path. Returns whether the body
+/// was changed.
+pub fn enforce(
+    policy: &PolicyConfig,
+    client: ApiType,
+    model: Option<&str>,
+    headers: &HeaderMap,
+    body: Option<&mut Value>,
+) -> Result<bool, Violation> {
+    if let Some(model) = model.filter(|model| !model_allowed(policy, model)) {
+        return Err(Violation::forbidden(format!("model '{}' is not allowed", model)));
+    }
+
+    if let Some(allowed) = &policy.allowed_betas {
+        let betas = headers.get_all("anthropic-beta").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|beta| !beta.is_empty());
+        for beta in betas {
+            if !allowed.iter().any(|allowed| allowed == beta) {
+                return Err(Violation::forbidden(format!("anthropic-beta '{}' is not allowed", beta)));
+            }
+        }
+    }
+
+    let Some(body) = body else {
+        return Ok(false);
+    };
+
+    if policy.allow_tools == Some(false) && declares_tools(body) {
+        return Err(Violation::forbidden("tools are not allowed".to_string()));
+    }
+    if policy.allow_images == Some(false) && contains_image(body) {
+        return Err(Violation::forbidden("images are not allowed".to_string()));
+    }
+    if let Some(tool) = declared_tools(body).into_iter().find(|tool| !tool_allowed(policy, tool)) {
+        return Err(Violation::forbidden(format!("tool '{}' is not allowed", tool)));
+    }
+
+    if let Some(limit) = policy.max_thinking_budget {
+        let pointer = match client {
+            ApiType::Gemini => "/generationConfig/thinkingConfig/thinkingBudget",
+            _ => "/thinking/budget_tokens",
+        };
+        if let Some(budget) = body.pointer(pointer).and_then(Value::as_u64).filter(|budget| *budget > limit) {
+            return Err(Violation::invalid(format!(
+                "thinking budget of {} exceeds the limit of {}",
+                budget, limit
+            )));
+        }
+    }
+
+    let mut clamped = false;
+    if let Some(limit) = policy.max_tokens {
+        for pointer in max_tokens_pointers(client) {
+            let Some(value) = body.pointer_mut(pointer) else {
+                continue;
+            };
+            match value.as_u64() {
+                Some(requested) if requested > limit => match policy.max_tokens_action.unwrap_or_default() {
+                    LimitAction::Reject => {
+                        return Err(Violation::invalid(format!(
+                            "max_tokens of {} exceeds the limit of {}",
+                            requested, limit
+                        )));
+                    }
+                    LimitAction::Clamp => {
+                        *value = Value::from(limit);
+                        clamped = true;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+    Ok(clamped)
+}
+
+/// Where each protocol puts its output token limit.
+fn max_tokens_pointers(client: ApiType) -> &'static [&'static str] {
+    match client {
+        ApiType::Anthropic | ApiType::Bedrock => &["/max_tokens"],
+        ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp => &["/max_tokens", "/max_completion_tokens"],
+        ApiType::Gemini => &["/generationConfig/maxOutputTokens"],
+        ApiType::Ollama => &["/options/num_predict"],
+    }
+}
+
+fn declares_tools(body: &Value) -> bool {
+    ["tools", "functions"]
+        .iter()
+        .any(|field| body.get(field).and_then(Value::as_array).is_some_and(|tools| !tools.is_empty()))
+}
+
+/// Whether `policy` lets a tool be declared or called.
+pub fn tool_allowed(policy: &PolicyConfig, name: &str) -> bool {
+    let allowed = policy.allowed_tools.as_ref().is_none_or(|allowed| allowed.iter().any(|pattern| glob_match(pattern, name)));
+    allowed && !policy.denied_tools.iter().any(|pattern| glob_match(pattern, name))
+}
+
+/// Tool names from Anthropic `tools`, OpenAI `tools` and `functions`, and
+/// Gemini `functionDeclarations`.
+fn declared_tools(body: &Value) -> Vec<&str> {
+    let tools = ["tools", "functions"].into_iter().filter_map(|field| body.get(field)?.as_array()).flatten();
+    tools
+        .flat_map(|tool| match tool.get("functionDeclarations").and_then(Value::as_array) {
+            Some(declarations) => declarations.iter().collect(),
+            None => vec![tool.get("function").unwrap_or(tool)],
+        })
+        .filter_map(|tool| tool.get("name")?.as_str())
+        .collect()
+}
+
+/// Anthropic `image` blocks, OpenAI `image_url` parts, Gemini image
+/// `inlineData`/`fileData` parts and Ollama `images`, wherever they appear.
+fn contains_image(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => {
+            let typed_image = matches!(object.get("type").and_then(Value::as_str), Some("image" | "image_url"));
+            let gemini_image = ["inlineData", "fileData"].iter().any(|field| {
+                object.get(*field)
+                    .and_then(|data| data.get("mimeType"))
+                    .and_then(Value::as_str)
+                    .is_some_and(|mime| mime.starts_with("image/"))
+            });
+            let ollama_images = object.get("images").and_then(Value::as_array).is_some_and(|images| !images.is_empty());
+            typed_image || gemini_image || ollama_images || object.values().any(contains_image)
+        }
+        Value::Array(items) => items.iter().any(contains_image),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn test_models_and_betas() {
+        let policy = PolicyConfig {
+            allowed_models: Some(vec!["claude-3-5-*".to_string()]),
+            denied_models: vec!["claude-3-5-opus*".to_string()],
+            allowed_betas: Some(vec!["prompt-caching-2024-07-31".to_string()]),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        let check = |model, headers: &HeaderMap| enforce(&policy, ApiType::Anthropic, Some(model), headers, None);
+
+        assert_eq!(check("claude-3-5-sonnet-latest", &headers), Ok(false));
+        assert_eq!(check("claude-3-opus-latest", &headers).unwrap_err().status, StatusCode::FORBIDDEN);
+        assert!(check("claude-3-5-opus-latest", &headers).is_err());
+
+        headers.insert("anthropic-beta", HeaderValue::from_static("prompt-caching-2024-07-31, computer-use-2024-10-22"));
+        let violation = check("claude-3-5-sonnet-latest", &headers).unwrap_err();
+        assert_eq!(violation.message, "anthropic-beta 'computer-use-2024-10-22' is not allowed");
+    }
+
+    #[test]
+    fn test_max_tokens_reject_or_clamp() {
+        let mut policy = PolicyConfig {
+            max_tokens: Some(1024),
+            max_thinking_budget: Some(512),
+            ..Default::default()
+        };
+        let headers = HeaderMap::new();
+        let mut body = json!({ "max_tokens": 4096, "thinking": { "type": "enabled", "budget_tokens": 256 } });
+
+        let violation = enforce(&policy, ApiType::Anthropic, None, &headers, Some(&mut body)).unwrap_err();
+        assert_eq!(violation.status, StatusCode::BAD_REQUEST);
+        assert_eq!(violation.message, "max_tokens of 4096 exceeds the limit of 1024");
+
+        policy.max_tokens_action = Some(LimitAction::Clamp);
+        assert_eq!(enforce(&policy, ApiType::Anthropic, None, &headers, Some(&mut body)), Ok(true));
+        assert_eq!(body["max_tokens"], 1024);
+
+        let mut body = json!({ "generationConfig": { "maxOutputTokens": 8192, "thinkingConfig": { "thinkingBudget": 2048 } } });
+        let violation = enforce(&policy, ApiType::Gemini, None, &headers, Some(&mut body)).unwrap_err();
+        assert_eq!(violation.message, "thinking budget of 2048 exceeds the limit of 512");
+
+        let mut body = json!({ "max_completion_tokens": 2000 });
+        assert_eq!(enforce(&policy, ApiType::OpenAI, None, &headers, Some(&mut body)), Ok(true));
+        assert_eq!(body["max_completion_tokens"], 1024);
+    }
+
+    #[test]
+    fn test_tools_and_images() {
+        let policy = PolicyConfig {
+            allow_tools: Some(false),
+            allow_images: Some(false),
+            ..Default::default()
+        };
+        let headers = HeaderMap::new();
+        let check = |mut body: Value| enforce(&policy, ApiType::Anthropic, None, &headers, Some(&mut body));
+
+        assert_eq!(check(json!({ "tools": [] })), Ok(false));
+        assert_eq!(check(json!({ "tools": [{ "name": "search" }] })).unwrap_err().message, "tools are not allowed");
+        assert!(check(json!({ "messages": [{ "role": "user", "content": [
+            { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "" } }
+        ] }] }))
+        .is_err());
+        assert!(check(json!({ "messages": [{ "content": [{ "type": "image_url", "image_url": { "url": "" } }] }] })).is_err());
+        assert!(check(json!({ "contents": [{ "parts": [{ "inlineData": { "mimeType": "image/jpeg", "data": "" } }] }] })).is_err());
+        assert_eq!(check(json!({ "contents": [{ "parts": [{ "inlineData": { "mimeType": "audio/wav" } }] }] })), Ok(false));
+    }
+
+    #[test]
+    fn test_declared_tool_names() {
+        let policy = PolicyConfig {
+            allowed_tools: Some(vec!["search_*".to_string(), "calculator".to_string()]),
+            denied_tools: vec!["search_internal".to_string()],
+            ..Default::default()
+        };
+        let headers = HeaderMap::new();
+        let check = |mut body: Value| enforce(&policy, ApiType::Anthropic, None, &headers, Some(&mut body));
+
+        assert_eq!(check(json!({ "tools": [{ "name": "search_web" }, { "name": "calculator" }] })), Ok(false));
+        assert_eq!(
+            check(json!({ "tools": [{ "name": "search_internal" }] })).unwrap_err().message,
+            "tool 'search_internal' is not allowed"
+        );
+        assert!(check(json!({ "tools": [{ "type": "function", "function": { "name": "shell" } }] })).is_err());
+        assert!(check(json!({ "tools": [{ "functionDeclarations": [{ "name": "calculator" }, { "name": "shell" }] }] })).is_err());
+        assert!(!tool_allowed(&policy, "shell"));
+    }
+}
@@ -0,0 +1,235 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::{ApiType, PromptPosition, SystemPromptConfig};
+
+/// Body field through which clients ask for a named template as their
+/// system prompt; it is removed before the request is forwarded.
+pub const TEMPLATE_FIELD: &str = "prompt_template";
+
+/// Named prompt templates, loaded from the prompts directory at startup.
+#[derive(Debug, Default)]
+pub struct PromptLibrary {
+    templates: HashMap<String, String>,
+}
+
+impl PromptLibrary {
+    /// Every file in `directory` is a template named by its file stem, so
+    /// `compliance.md` is `compliance`.
+    pub fn load(directory: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut templates = HashMap::new();
+        for entry in std::fs::read_dir(directory)
+            .map_err(|e| format!("cannot read prompts directory {}: {}", directory.display(), e))?
+        {
+            let path = entry?.path();
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()).filter(|_| path.is_file()) else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            templates.insert(name.to_string(), std::fs::read_to_string(&path)?.trim_end().to_string());
+        }
+        Ok(Self { templates })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn render(&self, name: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+        let template = self.templates.get(name).ok_or_else(|| format!("unknown prompt template '{}'", name))?;
+        render(template, variables).map_err(|e| format!("prompt template '{}': {}", name, e))
+    }
+
+    /// The text a configured system prompt stands for.
+    pub fn system_prompt(&self, config: &SystemPromptConfig) -> Result<String, String> {
+        match (&config.text, &config.template) {
+            (Some(text), None) => Ok(text.clone()),
+            (None, Some(template)) => self.render(template, &config.variables),
+            _ => Err("a system prompt needs exactly one of 'text' and 'template'".to_string()),
+        }
+    }
+}
+
+/// Substitutes `{{name}}` placeholders; every placeholder needs a value.
+pub fn render(template: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..].find("}}").ok_or("unclosed '{{'")?;
+        let name = rest[start + 2..start + end].trim();
+        let value = variables.get(name).ok_or_else(|| format!("no value for '{{{{{}}}}}'", name))?;
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// A client's request for a named template, from its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateRequest {
+    pub name: String,
+    pub variables: HashMap<String, String>,
+}
+
+/// The template a client asked for in its body, if any, removing the field.
+pub fn take_template_request(body: &mut Value) -> Result<Option<TemplateRequest>, String> {
+    let Some(request) = body.as_object_mut().and_then(|body| body.remove(TEMPLATE_FIELD)) else {
+        return Ok(None);
+    };
+    let request = match request {
+        Value::String(name) => json!({ "name": name }),
+        request => request,
+    };
+    let name = request.get("name").and_then(Value::as_str).ok_or("'prompt_template.name' must be a string")?;
+    let mut variables = HashMap::new();
+    for (key, value) in request.get("variables").and_then(Value::as_object).into_iter().flatten() {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        variables.insert(key.clone(), value);
+    }
+    Ok(Some(TemplateRequest {
+        name: name.to_string(),
+        variables,
+    }))
+}
+
+/// Adds `text` to the system prompt of an Anthropic, OpenAI or Gemini
+/// request. Bodies without messages, such as embeddings, are left alone.
+pub fn inject(client: ApiType, body: &mut Value, text: &str, position: PromptPosition) -> bool {
+    match client {
+        ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp | ApiType::Ollama => inject_openai(body, text, position),
+        ApiType::Gemini => inject_gemini(body, text, position),
+        ApiType::Anthropic | ApiType::Bedrock => inject_anthropic(body, text, position),
+    }
+}
+
+fn inject_anthropic(body: &mut Value, text: &str, position: PromptPosition) -> bool {
+    if body.get("messages").is_none() {
+        return false;
+    }
+    let system = match body.get_mut("system") {
+        // Blocks keep their own `cache_control`, so the prompt gets a block of its own
+        Some(Value::Array(blocks)) => {
+            let block = json!({ "type": "text", "text": text });
+            match position {
+                PromptPosition::Prepend => blocks.insert(0, block),
+                PromptPosition::Append => blocks.push(block),
+            }
+            return true;
+        }
+        Some(Value::String(system)) if !system.is_empty() => match position {
+            PromptPosition::Prepend => format!("{}\n\n{}", text, system),
+            PromptPosition::Append => format!("{}\n\n{}", system, text),
+        },
+        _ => text.to_string(),
+    };
+    body["system"] = Value::String(system);
+    true
+}
+
+fn inject_openai(body: &mut Value, text: &str, position: PromptPosition) -> bool {
+    let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) else {
+        return false;
+    };
+    // Appended prompts follow the client's leading system messages
+    let index = match position {
+        PromptPosition::Prepend => 0,
+        PromptPosition::Append => messages
+            .iter()
+            .take_while(|message| matches!(message["role"].as_str(), Some("system" | "developer")))
+            .count(),
+    };
+    messages.insert(index, json!({ "role": "system", "content": text }));
+    true
+}
+
+fn inject_gemini(body: &mut Value, text: &str, position: PromptPosition) -> bool {
+    if body.get("contents").is_none() || !matches!(body.get("systemInstruction"), None | Some(Value::Object(_))) {
+        return false;
+    }
+    let parts = &mut body["systemInstruction"]["parts"];
+    if !parts.is_array() {
+        *parts = json!([]);
+    }
+    let Some(parts) = parts.as_array_mut() else {
+        return false;
+    };
+    let part = json!({ "text": text });
+    match position {
+        PromptPosition::Prepend => parts.insert(0, part),
+        PromptPosition::Append => parts.push(part),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = render("You support {{product}} for {{ team }}.", &variables(&[("product", "Acme"), ("team", "billing")]));
+        assert_eq!(rendered.unwrap(), "You support Acme for billing.");
+        assert_eq!(render("{{missing}}", &HashMap::new()).unwrap_err(), "no value for '{{missing}}'");
+        assert!(render("{{open", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_inject_anthropic() {
+        let mut body = json!({ "messages": [], "system": "Be brief." });
+        assert!(inject(ApiType::Anthropic, &mut body, "Follow policy.", PromptPosition::Prepend));
+        assert_eq!(body["system"], "Follow policy.\n\nBe brief.");
+
+        let mut body = json!({ "messages": [], "system": [{ "type": "text", "text": "Be brief.", "cache_control": { "type": "ephemeral" } }] });
+        inject(ApiType::Anthropic, &mut body, "Sign off.", PromptPosition::Append);
+        assert_eq!(body["system"][1], json!({ "type": "text", "text": "Sign off." }));
+        assert_eq!(body["system"][0]["cache_control"]["type"], "ephemeral");
+
+        let mut body = json!({ "messages": [] });
+        inject(ApiType::Anthropic, &mut body, "Follow policy.", PromptPosition::Append);
+        assert_eq!(body["system"], "Follow policy.");
+
+        assert!(!inject(ApiType::Anthropic, &mut json!({ "model": "m" }), "x", PromptPosition::Prepend));
+    }
+
+    #[test]
+    fn test_inject_openai_and_gemini() {
+        let mut body = json!({ "messages": [
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Hi" }
+        ] });
+        inject(ApiType::OpenAI, &mut body, "Sign off.", PromptPosition::Append);
+        inject(ApiType::OpenAI, &mut body, "Follow policy.", PromptPosition::Prepend);
+        let contents: Vec<_> = body["messages"].as_array().unwrap().iter().map(|m| m["content"].as_str().unwrap()).collect();
+        assert_eq!(contents, ["Follow policy.", "Be brief.", "Sign off.", "Hi"]);
+
+        let mut body = json!({ "contents": [] });
+        inject(ApiType::Gemini, &mut body, "Follow policy.", PromptPosition::Prepend);
+        assert_eq!(body["systemInstruction"], json!({ "parts": [{ "text": "Follow policy." }] }));
+    }
+
+    #[test]
+    fn test_take_template_request() {
+        let mut body = json!({ "model": "m", "prompt_template": { "name": "support", "variables": { "product": "Acme", "tier": 2 } } });
+        let template = take_template_request(&mut body).unwrap().unwrap();
+        assert_eq!(template.name, "support");
+        assert_eq!(template.variables["tier"], "2");
+        assert_eq!(body, json!({ "model": "m" }));
+
+        let mut body = json!({ "prompt_template": "support" });
+        assert_eq!(take_template_request(&mut body).unwrap().unwrap().name, "support");
+        assert!(take_template_request(&mut json!({ "prompt_template": 3 })).is_err());
+        assert!(take_template_request(&mut json!({})).unwrap().is_none());
+    }
+}
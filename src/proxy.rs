@@ -1,17 +1,77 @@
 use axum::{
-    body::to_bytes,
+    body::{to_bytes, Body},
     extract::Request,
-    http::{StatusCode, Uri},
+    http::{header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HOST}, HeaderValue, StatusCode, Uri},
     response::Response,
 };
+use futures::TryStreamExt;
+use chrono::Utc;
 use std::collections::HashMap;
-use tracing::{debug, error, info};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use url::Url;
 
-use crate::config::Config;
+use crate::access_log::{AccessLog, AccessLogEntry};
+use crate::alias::{self, ModelRewrite, MODEL_RESOLVED_HEADER};
+use crate::aws::{self, AwsCredentials, SigV4Signer};
+use crate::cache::{CacheDirectives, ResponseCache, CACHE_HEADER};
+use crate::capture::{Capture, PendingCapture};
+use crate::cassette::Cassette;
+use crate::coalesce::{Coalescer, Joined};
+use crate::context::{self, CapabilityRegistry, CONTEXT_HEADER};
+use crate::config::{
+    ApiType, BedrockConfig, Config, ContextAction, EndpointMode, GuardrailStage, PolicyConfig, PromptPosition, RedactionAction,
+    RewriteTarget, SystemPromptConfig,
+};
+use crate::google::GoogleAuth;
+use crate::guardrail::{self, Classification, ClassifyRequest, Guardrail, Verdict, GUARDRAIL_HEADER};
+use crate::headers::HeaderRules;
+use crate::pii::{self, PromptRedactor};
+use crate::policy;
+use crate::prompts::{self, PromptLibrary};
+use crate::request_id::{upstream_request_id, RequestId, REQUEST_ID_HEADER, UPSTREAM_REQUEST_ID_HEADER};
+use crate::rewrite::{self, RewriteContext};
+use crate::routing::{ModelRouter, ENDPOINT_HEADER};
+use crate::tools::{self, ToolFilter};
+use crate::translate::{self, bedrock, gemini, ollama, Translator};
+
+const DEFAULT_TARGET_BASE: &str = "https://api.anthropic.com";
+
+/// Client credentials dropped when the proxy supplies its own.
+const CREDENTIAL_HEADERS: &[&str] = &[
+    "authorization",
+    "x-api-key",
+    "x-goog-api-key",
+    "x-amz-date",
+    "x-amz-security-token",
+];
 
 pub struct ProxyService {
     pub clients: HashMap<String, reqwest::Client>,
     pub config: Config,
+    /// Target base used by endpoints that do not configure their own.
+    pub target_base: String,
+    pub access_log: Option<Arc<AccessLog>>,
+    pub capture: Option<Capture>,
+    pub cassettes: HashMap<String, Cassette>,
+    pub caches: HashMap<String, Arc<ResponseCache>>,
+    /// Endpoints that share one upstream call between identical concurrent requests.
+    pub coalescers: HashMap<String, Arc<Coalescer>>,
+    /// Gemini endpoints whose credentials the proxy supplies itself.
+    pub google_auth: HashMap<String, Arc<GoogleAuth>>,
+    /// Bedrock endpoints, which sign every request with SigV4.
+    pub signers: HashMap<String, Arc<SigV4Signer>>,
+    /// Endpoint choice for the unprefixed `/v1` route.
+    pub models: ModelRouter,
+    pub header_rules: HashMap<String, HeaderRules>,
+    pub prompts: PromptLibrary,
+    pub redactors: HashMap<String, PromptRedactor>,
+    pub output_redactors: HashMap<String, Arc<PromptRedactor>>,
+    pub guardrails: HashMap<String, Guardrail>,
+    /// Context windows and output limits, for endpoints with a context guard.
+    pub capabilities: CapabilityRegistry,
 }
 
 impl ProxyService {
@@ -20,13 +80,134 @@ impl ProxyService {
         let config = Config::default();
         Self::new_with_config(config).await
     }
+
+    pub async fn new_with_base(target_base: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = Config::default();
+        if let Some(target_base) = target_base {
+            config.server.target_base = Some(target_base.to_string());
+        }
+        Self::new_with_config(config).await
+    }
     
     pub async fn new_with_config(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
         let clients = Self::create_clients(&config)?;
+        let target_base = config.server.target_base.clone()
+            .unwrap_or_else(|| DEFAULT_TARGET_BASE.to_string());
+
+        let access_log = match &config.access_log {
+            Some(access_log) if access_log.enabled.unwrap_or(true) => {
+                Some(Arc::new(AccessLog::from_config(access_log)?))
+            }
+            _ => None,
+        };
+        let capture = Capture::from_config(&config)?;
+        let cassettes = config.endpoints.iter()
+            .filter_map(|(name, endpoint)| {
+                let mode = endpoint.mode.unwrap_or_default();
+                Cassette::from_config(name, mode, endpoint.cassette.as_ref())
+                    .map(|cassette| (name.clone(), cassette))
+            })
+            .collect();
+        let caches = config.endpoints.iter()
+            .filter_map(|(name, endpoint)| {
+                let cache = endpoint.cache.as_ref()?;
+                Some((name.clone(), Arc::new(ResponseCache::from_config(cache))))
+            })
+            .collect();
+        let coalescers = config.endpoints.iter()
+            .filter(|(_, endpoint)| endpoint.coalesce.unwrap_or(false))
+            .map(|(name, _)| (name.clone(), Arc::new(Coalescer::new())))
+            .collect();
+        let mut google_auth = HashMap::new();
+        for (name, endpoint) in &config.endpoints {
+            if let Some(auth) = endpoint.gemini.as_ref().map(GoogleAuth::from_config).transpose()?.flatten() {
+                google_auth.insert(name.clone(), Arc::new(auth));
+            }
+        }
+        let mut signers = HashMap::new();
+        let default_bedrock = BedrockConfig::default();
+        for (name, endpoint) in &config.endpoints {
+            if endpoint.api_type != Some(ApiType::Bedrock) {
+                continue;
+            }
+            let bedrock = endpoint.bedrock.as_ref().unwrap_or(&default_bedrock);
+            let region = aws::region(bedrock)
+                .ok_or_else(|| format!("endpoint '{}' needs an AWS region", name))?;
+            let credentials = AwsCredentials::load(bedrock)
+                .map_err(|e| format!("endpoint '{}': {}", name, e))?;
+            signers.insert(name.clone(), Arc::new(SigV4Signer::new(credentials, &region, "bedrock")));
+        }
+        let mut header_rules = HashMap::new();
+        let mut redactors = HashMap::new();
+        let mut output_redactors = HashMap::new();
+        let mut guardrails = HashMap::new();
+        for (name, endpoint) in &config.endpoints {
+            rewrite::validate(&endpoint.rewrites).map_err(|e| format!("endpoint '{}': {}", name, e))?;
+            if let Some(headers) = &endpoint.headers {
+                let rules = HeaderRules::from_config(headers).map_err(|e| format!("endpoint '{}' headers: {}", name, e))?;
+                header_rules.insert(name.clone(), rules);
+            }
+            if let Some(redaction) = &endpoint.redaction {
+                let redactor = PromptRedactor::from_config(redaction).map_err(|e| format!("endpoint '{}' redaction: {}", name, e))?;
+                redactors.insert(name.clone(), redactor);
+            }
+            if let Some(redaction) = &endpoint.output_redaction {
+                if redaction.action == Some(RedactionAction::Reject) {
+                    return Err(format!("endpoint '{}' output_redaction: responses cannot be rejected", name).into());
+                }
+                // Their streams are NDJSON or binary event streams, which redaction cannot read
+                if matches!(translate::client_api(endpoint), ApiType::Ollama | ApiType::Bedrock) {
+                    return Err(format!("endpoint '{}' output_redaction: not supported for Ollama or Bedrock clients", name).into());
+                }
+                let redactor = PromptRedactor::from_config(redaction).map_err(|e| format!("endpoint '{}' output_redaction: {}", name, e))?;
+                output_redactors.insert(name.clone(), Arc::new(redactor));
+            }
+            if let Some(guardrail) = &endpoint.guardrail {
+                let client = clients.get(name).unwrap_or(&clients["default"]).clone();
+                let guardrail = Guardrail::from_config(guardrail, client).map_err(|e| format!("endpoint '{}': {}", name, e))?;
+                guardrails.insert(name.clone(), guardrail);
+            }
+        }
+        let prompts = match &config.prompts {
+            Some(prompts) => PromptLibrary::load(Path::new(&prompts.directory))?,
+            None => PromptLibrary::default(),
+        };
+        let system_prompts = config.endpoints.iter()
+            .filter_map(|(name, endpoint)| Some((name, endpoint.system_prompt.as_ref()?)))
+            .chain(config.keys.iter().filter_map(|(name, key)| Some((name, key.system_prompt.as_ref()?))));
+        for (name, system_prompt) in system_prompts {
+            prompts.system_prompt(system_prompt).map_err(|e| format!("system prompt for '{}': {}", name, e))?;
+        }
+        let capabilities = CapabilityRegistry::new(&config.model_capabilities);
+        for (name, endpoint) in &config.endpoints {
+            let upgrades = endpoint.context_guard.iter().flat_map(|guard| &guard.upgrade_models);
+            if let Some(unknown) = upgrades.into_iter().find(|model| capabilities.get(model).is_none()) {
+                return Err(format!("endpoint '{}': no context window known for upgrade model '{}'", name, unknown).into());
+            }
+        }
+        let models = ModelRouter::new(&config.models);
+        if let Some(missing) = models.endpoints().find(|endpoint| !config.endpoints.contains_key(*endpoint)) {
+            return Err(format!("[models] routes to unknown endpoint '{}'", missing).into());
+        }
         
         Ok(Self {
             clients,
             config,
+            target_base,
+            access_log,
+            capture,
+            cassettes,
+            caches,
+            coalescers,
+            google_auth,
+            signers,
+            models,
+            header_rules,
+            prompts,
+            redactors,
+            output_redactors,
+            guardrails,
+            capabilities,
         })
     }
     
@@ -65,28 +246,721 @@ impl ProxyService {
     pub async fn handle_request(
         &self,
         prefix: String,
+        mut request: Request,
+    ) -> Result<Response, StatusCode> {
+        let started = Instant::now();
+        let request_id = RequestId::from_headers(request.headers());
+        request.extensions_mut().insert(request_id.clone());
+        let mut entry = AccessLogEntry::new(&prefix, &request);
+
+        // Every log and trace event for this call carries the request id
+        let span = info_span!("request", request_id = %request_id, endpoint = %prefix);
+        let result = async {
+            let (request, capture) = self.begin_capture(&prefix, request, &request_id).await?;
+            let result = self.forward_prepared(&prefix, request, &mut entry).await;
+            match (capture, result) {
+                (Some(capture), Ok(response)) => Ok(capture.attach(response)),
+                (Some(capture), Err(status)) => {
+                    capture.finish_without_response();
+                    Err(status)
+                }
+                (None, result) => result,
+            }
+        }
+        .instrument(span)
+        .await
+        .map(|mut response| {
+            if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+                response.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            response
+        });
+
+        match (&self.access_log, result) {
+            (Some(access_log), Ok(response)) => Ok(access_log.log_response(entry, started, response)),
+            (Some(access_log), Err(status)) => {
+                entry.status = status.as_u16();
+                entry.latency_ms = started.elapsed().as_millis() as u64;
+                access_log.write(&entry);
+                Err(status)
+            }
+            (None, result) => result,
+        }
+    }
+
+    /// Samples the request for capture as the client sent it, before any
+    /// alias, prompt, rewrite or translation changes it, so a replay goes
+    /// through the same pipeline as the original call.
+    async fn begin_capture(
+        &self,
+        prefix: &str,
+        request: Request,
+        request_id: &RequestId,
+    ) -> Result<(Request, Option<PendingCapture>), StatusCode> {
+        let Some(capture) = self.capture.as_ref().filter(|capture| capture.captures(prefix)) else {
+            return Ok((request, None));
+        };
+        let Ok(path) = self.extract_path(request.uri(), prefix) else {
+            return Ok((request, None));
+        };
+
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let pending = capture.begin(
+            prefix,
+            request_id.as_str(),
+            parts.method.as_str(),
+            &path,
+            parts.uri.query(),
+            &parts.headers,
+            &body_bytes,
+        );
+        Ok((Request::from_parts(parts, Body::from(body_bytes)), pending))
+    }
+
+    /// Unprefixed `/v1` requests go to the endpoint named by `x-proxy-endpoint`,
+    /// else the one the `[models]` registry picks for the body's model.
+    pub async fn handle_routed(&self, request: Request) -> Result<Response, StatusCode> {
+        let (mut parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let prefix = match parts.headers.remove(ENDPOINT_HEADER) {
+            Some(endpoint) => {
+                let endpoint = endpoint.to_str().map_err(|_| StatusCode::BAD_REQUEST)?;
+                if !self.config.endpoints.contains_key(endpoint) {
+                    info!("Unknown endpoint '{}' in {}", endpoint, ENDPOINT_HEADER);
+                    return Err(StatusCode::NOT_FOUND);
+                }
+                endpoint.to_string()
+            }
+            None => {
+                let model = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok()
+                    .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+                    .ok_or(StatusCode::BAD_REQUEST)?;
+                // A global alias routes like the model it stands for
+                let endpoint = self.models.endpoint(&model)
+                    .or_else(|| self.models.endpoint(self.config.aliases.get(&model)?));
+                let Some(endpoint) = endpoint else {
+                    info!("No endpoint routes model '{}'", model);
+                    return Err(StatusCode::NOT_FOUND);
+                };
+                debug!("Routing model '{}' to endpoint '{}'", model, endpoint);
+                endpoint.to_string()
+            }
+        };
+
+        let path_and_query = parts.uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        parts.uri = format!("/{}{}", prefix, path_and_query).parse()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        self.handle_request(prefix, Request::from_parts(parts, Body::from(body_bytes))).await
+    }
+
+    /// Header rules are applied, aliased models resolved, bodies rewritten,
+    /// prompts redacted, system prompts added, policies enforced, the context
+    /// window checked and the guardrail consulted before the request goes
+    /// anywhere. The completion is redacted before the guardrail sees it; the
+    /// response names the resolved model and, if asked, shows the alias again.
+    async fn forward_prepared(
+        &self,
+        prefix: &str,
+        mut request: Request,
+        entry: &mut AccessLogEntry,
+    ) -> Result<Response, StatusCode> {
+        let header_rules = self.header_rules.get(prefix);
+        if let Some(rules) = header_rules {
+            rules.apply_request(request.headers_mut());
+        }
+        let (request, alias) = self.resolve_model_alias(prefix, request).await?;
+        if let Some(alias) = &alias {
+            info!("Resolved model alias '{}' to '{}'", alias.alias, alias.resolved);
+            entry.model_alias = Some(alias.alias.clone());
+        }
+        let (request, rewrites) = self.rewrite_request(prefix, request).await?;
+        let request = match self.redact_prompt(prefix, request, entry).await? {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
+        let request = match self.inject_system_prompts(prefix, request, entry.key_id.as_deref()).await? {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
+        let request = match self.enforce_policies(prefix, request, entry.key_id.as_deref()).await? {
+            Ok(request) => request,
+            Err(response) => return Ok(response),
+        };
+        let (request, context_action) = match self.guard_context(prefix, request, entry.key_id.as_deref()).await? {
+            Ok(guarded) => guarded,
+            Err(response) => return Ok(response),
+        };
+        let (mut request, model) = match self.guard_request(prefix, request, entry).await? {
+            Ok(guarded) => guarded,
+            Err(response) => return Ok(response),
+        };
+
+        // Stages that read the completion cannot see through compression
+        let policies = self.policies(prefix, entry.key_id.as_deref());
+        if self.inspects_responses(prefix, &policies, alias.is_some()) {
+            request.headers_mut().remove(ACCEPT_ENCODING);
+        }
+
+        let mut response = self.forward_translated(prefix, request, entry).await?;
+        let client = self.config.endpoints.get(prefix).map(translate::client_api).unwrap_or_default();
+        response = if policies.is_empty() {
+            tools::log_response(response, client)
+        } else {
+            tools::filter_response(response, ToolFilter::new(&policies, client)).await
+        };
+        if let Some(redactor) = self.output_redactors.get(prefix) {
+            response = pii::redact_response(response, redactor.clone(), client).await;
+        }
+        response = self.guard_response(prefix, response, model.as_deref(), entry).await;
+        if let Some(action) = context_action {
+            response.headers_mut().insert(CONTEXT_HEADER, HeaderValue::from_static(action));
+        }
+        if let Some(rules) = header_rules {
+            rules.apply_response(response.headers_mut());
+        }
+        let endpoint = self.config.endpoints.get(prefix);
+        if let (Some(context), Some(endpoint)) = (rewrites, endpoint) {
+            response = rewrite::apply_to_response(response, &endpoint.rewrites, &context).await;
+        }
+        if let Some(alias) = alias {
+            if let Ok(value) = HeaderValue::from_str(&alias.resolved) {
+                response.headers_mut().insert(MODEL_RESOLVED_HEADER, value);
+            }
+            if endpoint.and_then(|endpoint| endpoint.restore_alias).unwrap_or(false) {
+                response = alias::restore_response(response, &alias.alias).await;
+            }
+        }
+        Ok(response)
+    }
+
+    /// Applies the endpoint's request rewrites. The returned context is kept
+    /// for response rewrites, if the endpoint has any.
+    async fn rewrite_request(
+        &self,
+        prefix: &str,
+        request: Request,
+    ) -> Result<(Request, Option<RewriteContext>), StatusCode> {
+        let rules = match self.config.endpoints.get(prefix) {
+            Some(endpoint) if !endpoint.rewrites.is_empty() => &endpoint.rewrites,
+            _ => return Ok((request, None)),
+        };
+
+        let path = self.extract_path(request.uri(), prefix)?;
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let mut body = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok();
+        let model = body.as_ref()
+            .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+            .or_else(|| gemini::path_model(&path).map(str::to_string));
+        let context = RewriteContext { path, model };
+
+        let rewritten = body.as_mut()
+            .is_some_and(|body| rewrite::apply(rules, RewriteTarget::Request, &context, body));
+        let body = match body.filter(|_| rewritten) {
+            Some(body) => {
+                debug!("Rewrote request body for {}", context.path);
+                Body::from(body.to_string())
+            }
+            None => Body::from(body_bytes),
+        };
+        let responses = rules.iter().any(|rule| rule.target == Some(RewriteTarget::Response));
+        Ok((Request::from_parts(parts, body), responses.then_some(context)))
+    }
+
+    /// Masks or tokenises sensitive values in the client's message text, or
+    /// refuses the request when the endpoint's action is `reject`. Runs
+    /// before system prompts are added, so only client text is scanned.
+    async fn redact_prompt(
+        &self,
+        prefix: &str,
+        request: Request,
+        entry: &mut AccessLogEntry,
+    ) -> Result<Result<Request, Response>, StatusCode> {
+        let Some(redactor) = self.redactors.get(prefix) else {
+            return Ok(Ok(request));
+        };
+
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&body_bytes) else {
+            return Ok(Ok(Request::from_parts(parts, Body::from(body_bytes))));
+        };
+
+        let findings = redactor.redact_body(&mut body);
+        if findings.total() == 0 {
+            return Ok(Ok(Request::from_parts(parts, Body::from(body_bytes))));
+        }
+        entry.redactions = Some(findings.total() as u64);
+        if redactor.action() == RedactionAction::Reject {
+            info!("Refused request containing sensitive data: {}", findings);
+            let client = self.config.endpoints.get(prefix).map(translate::client_api).unwrap_or_default();
+            let message = format!("request contains sensitive data ({})", findings);
+            return Ok(Err(translate::error_response(client, StatusCode::BAD_REQUEST, &message)));
+        }
+        info!("Redacted {} sensitive values: {}", findings.total(), findings);
+        Ok(Ok(Request::from_parts(parts, Body::from(body.to_string()))))
+    }
+
+    /// Sends the prompt text to the endpoint's guardrail classifier; a `block`
+    /// is answered in the client's protocol. Returns the model for the
+    /// response check.
+    async fn guard_request(
+        &self,
+        prefix: &str,
+        request: Request,
+        entry: &mut AccessLogEntry,
+    ) -> Result<Result<(Request, Option<String>), Response>, StatusCode> {
+        let Some(guardrail) = self.guardrails.get(prefix) else {
+            return Ok(Ok((request, None)));
+        };
+
+        let path = self.extract_path(request.uri(), prefix)?;
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let body = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok();
+        let model = body.as_ref()
+            .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+            .or_else(|| gemini::path_model(&path).map(str::to_string));
+
+        if let Some(body) = body.filter(|_| guardrail.checks(GuardrailStage::Request)) {
+            let classification = guardrail.classify(&ClassifyRequest {
+                stage: GuardrailStage::Request,
+                endpoint: prefix,
+                model: model.as_deref(),
+                request_id: entry.request_id.as_deref(),
+                text: pii::message_texts(&body).join("\n\n"),
+            }).await;
+            if let Some(response) = self.apply_verdict(prefix, GuardrailStage::Request, classification, entry) {
+                return Ok(Err(response));
+            }
+        }
+        Ok(Ok((Request::from_parts(parts, Body::from(body_bytes)), model)))
+    }
+
+    /// Sends a completion to the endpoint's guardrail classifier, holding a
+    /// streamed one back until it ends. A body the classifier cannot be given
+    /// is treated like a classifier failure. A `block` replaces the completion
+    /// with an error, and any non-`pass` verdict is named in a response header.
+    async fn guard_response(
+        &self,
+        prefix: &str,
+        response: Response,
+        model: Option<&str>,
+        entry: &mut AccessLogEntry,
+    ) -> Response {
+        let Some(guardrail) = self.guardrails.get(prefix) else {
+            return response;
+        };
+        if !guardrail.checks(GuardrailStage::Response) || !response.status().is_success() {
+            return self.flag_response(response, entry);
+        }
+
+        // Streams are held until they end, so nothing reaches the client unchecked
+        let content_type = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let (parts, body) = response.into_parts();
+        let body_bytes = match to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Guardrail could not read the response from {}: {}", prefix, e);
+                let client = self.config.endpoints.get(prefix).map(translate::client_api).unwrap_or_default();
+                let message = "upstream response could not be read for the guardrail";
+                return translate::error_response(client, StatusCode::BAD_GATEWAY, message);
+            }
+        };
+        let classification = match guardrail::response_text(&content_type, &body_bytes) {
+            Some(text) => guardrail.classify(&ClassifyRequest {
+                stage: GuardrailStage::Response,
+                endpoint: prefix,
+                model,
+                request_id: entry.request_id.as_deref(),
+                text,
+            }).await,
+            None => {
+                warn!("Guardrail cannot read the '{}' response from {}", content_type, prefix);
+                guardrail.fallback("response unreadable by the guardrail")
+            }
+        };
+        if let Some(response) = self.apply_verdict(prefix, GuardrailStage::Response, classification, entry) {
+            return self.flag_response(response, entry);
+        }
+        self.flag_response(Response::from_parts(parts, Body::from(body_bytes)), entry)
+    }
+
+    /// Records a non-`pass` verdict; a `block` becomes the error response.
+    /// A block outranks an earlier flag.
+    fn apply_verdict(
+        &self,
+        prefix: &str,
+        stage: GuardrailStage,
+        classification: Classification,
+        entry: &mut AccessLogEntry,
+    ) -> Option<Response> {
+        let reason = classification.reason.as_deref().unwrap_or("no reason given");
+        match classification.verdict {
+            Verdict::Pass => None,
+            Verdict::Flag => {
+                warn!("Guardrail flagged {:?}: {}", stage, reason);
+                entry.guardrail = Some(Verdict::Flag.as_str().to_string());
+                None
+            }
+            Verdict::Block => {
+                warn!("Guardrail blocked {:?}: {}", stage, reason);
+                entry.guardrail = Some(Verdict::Block.as_str().to_string());
+                let client = self.config.endpoints.get(prefix).map(translate::client_api).unwrap_or_default();
+                let subject = match stage {
+                    GuardrailStage::Request => "request",
+                    GuardrailStage::Response => "response",
+                };
+                let message = format!("{} blocked by guardrail: {}", subject, reason);
+                Some(translate::error_response(client, StatusCode::FORBIDDEN, &message))
+            }
+        }
+    }
+
+    fn flag_response(&self, mut response: Response, entry: &AccessLogEntry) -> Response {
+        if let Some(value) = entry.guardrail.as_deref().and_then(|verdict| HeaderValue::from_str(verdict).ok()) {
+            response.headers_mut().insert(GUARDRAIL_HEADER, value);
+        }
+        response
+    }
+
+    /// Renders a template the client asked for as the innermost system prompt,
+    /// then wraps the key's and the endpoint's configured prompts around it.
+    async fn inject_system_prompts(
+        &self,
+        prefix: &str,
+        request: Request,
+        key_id: Option<&str>,
+    ) -> Result<Result<Request, Response>, StatusCode> {
+        let endpoint = self.config.endpoints.get(prefix);
+        let configured: Vec<&SystemPromptConfig> = key_id.and_then(|key_id| self.config.keys.get(key_id))
+            .and_then(|key| key.system_prompt.as_ref())
+            .into_iter()
+            .chain(endpoint.and_then(|endpoint| endpoint.system_prompt.as_ref()))
+            .collect();
+        if configured.is_empty() && self.prompts.is_empty() {
+            return Ok(Ok(request));
+        }
+
+        let client = endpoint.map(translate::client_api).unwrap_or_default();
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&body_bytes) else {
+            return Ok(Ok(Request::from_parts(parts, Body::from(body_bytes))));
+        };
+
+        let template = prompts::take_template_request(&mut body)
+            .and_then(|template| template.map(|template| self.prompts.render(&template.name, &template.variables)).transpose());
+        let mut changed = match template {
+            Ok(Some(text)) => {
+                prompts::inject(client, &mut body, &text, PromptPosition::Prepend);
+                true
+            }
+            Ok(None) => false,
+            Err(message) => return Ok(Err(translate::error_response(client, StatusCode::BAD_REQUEST, &message))),
+        };
+        for system_prompt in configured {
+            // Validated at startup
+            let Ok(text) = self.prompts.system_prompt(system_prompt) else {
+                continue;
+            };
+            changed |= prompts::inject(client, &mut body, &text, system_prompt.position.unwrap_or_default());
+        }
+
+        let body = if changed { Body::from(body.to_string()) } else { Body::from(body_bytes) };
+        Ok(Ok(Request::from_parts(parts, body)))
+    }
+
+    /// Compares the request's estimated size with its model's context window.
+    /// One that does not fit is refused, has its oldest turns dropped or moves
+    /// to a larger model, per the endpoint's guard; the returned label says
+    /// which. Policies have already run, so a clamped `max_tokens` counts, and
+    /// an upgrade only picks models they allow. Models the registry does not
+    /// know pass unchecked.
+    async fn guard_context(
+        &self,
+        prefix: &str,
+        request: Request,
+        key_id: Option<&str>,
+    ) -> Result<Result<(Request, Option<&'static str>), Response>, StatusCode> {
+        let endpoint = self.config.endpoints.get(prefix);
+        let Some(guard) = endpoint.and_then(|endpoint| endpoint.context_guard.as_ref()) else {
+            return Ok(Ok((request, None)));
+        };
+
+        let path = self.extract_path(request.uri(), prefix)?;
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Ok(mut body) = serde_json::from_slice::<serde_json::Value>(&body_bytes) else {
+            return Ok(Ok((Request::from_parts(parts, Body::from(body_bytes)), None)));
+        };
+        let model = body.get("model").and_then(|model| model.as_str()).map(str::to_string)
+            .or_else(|| gemini::path_model(&path).map(str::to_string));
+        let capabilities = model.as_deref().and_then(|model| self.capabilities.get(model));
+        let (Some(model), Some(capabilities)) = (model, capabilities) else {
+            return Ok(Ok((Request::from_parts(parts, Body::from(body_bytes)), None)));
+        };
+        if context::fits(&body, capabilities) {
+            return Ok(Ok((Request::from_parts(parts, Body::from(body_bytes)), None)));
+        }
+
+        let client = endpoint.map(translate::client_api).unwrap_or_default();
+        let policies = self.policies(prefix, key_id);
+        let needed = context::estimate_tokens(&body) + context::requested_output(&body, capabilities);
+        let action = match guard.action.unwrap_or_default() {
+            ContextAction::Reject => None,
+            ContextAction::Truncate => context::truncate(client, &mut body, capabilities).map(|dropped| {
+                info!("Dropped {} oldest messages to fit the context window of '{}'", dropped, model);
+                "truncated"
+            }),
+            // A model named in the path, as Gemini does, cannot be swapped here
+            ContextAction::Upgrade if body.get("model").is_some() => guard.upgrade_models.iter()
+                .filter(|larger| policies.iter().all(|policy| policy::model_allowed(policy, larger)))
+                .find(|larger| self.capabilities.get(larger).is_some_and(|larger| context::fits(&body, larger)))
+                .cloned()
+                .map(|larger| {
+                    info!("Rerouted a request of about {} tokens from '{}' to '{}'", needed, model, larger);
+                    body["model"] = serde_json::Value::String(larger);
+                    "upgraded"
+                }),
+            ContextAction::Upgrade => None,
+        };
+
+        match action {
+            Some(action) => Ok(Ok((Request::from_parts(parts, Body::from(body.to_string())), Some(action)))),
+            None => {
+                let message = format!(
+                    "request needs about {} tokens but '{}' has a context window of {}",
+                    needed, model, capabilities.context_window
+                );
+                info!("Refused oversize request: {}", message);
+                Ok(Err(translate::error_response(client, StatusCode::BAD_REQUEST, &message)))
+            }
+        }
+    }
+
+    /// The endpoint's policy and the caller's key policy must both admit the
+    /// request; a refusal is answered in the client's protocol.
+    async fn enforce_policies(
+        &self,
+        prefix: &str,
+        request: Request,
+        key_id: Option<&str>,
+    ) -> Result<Result<Request, Response>, StatusCode> {
+        let endpoint = self.config.endpoints.get(prefix);
+        let policies = self.policies(prefix, key_id);
+        if policies.is_empty() {
+            return Ok(Ok(request));
+        }
+
+        let client = endpoint.map(translate::client_api).unwrap_or_default();
+        let path = self.extract_path(request.uri(), prefix)?;
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let mut body = serde_json::from_slice::<serde_json::Value>(&body_bytes).ok();
+        let model = body.as_ref()
+            .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+            .or_else(|| gemini::path_model(&path).map(str::to_string));
+
+        let mut changed = false;
+        for policy in policies {
+            match policy::enforce(policy, client, model.as_deref(), &parts.headers, body.as_mut()) {
+                Ok(clamped) => changed |= clamped,
+                Err(violation) => {
+                    info!("Policy refused request: {}", violation.message);
+                    return Ok(Err(translate::error_response(client, violation.status, &violation.message)));
+                }
+            }
+        }
+
+        let body = match body.filter(|_| changed) {
+            Some(body) => Body::from(body.to_string()),
+            None => Body::from(body_bytes),
+        };
+        Ok(Ok(Request::from_parts(parts, body)))
+    }
+
+    /// Whether any stage reads the response body: tool-call logging, tool
+    /// policies, output redaction, a response guardrail, response rewrites or
+    /// a restored alias.
+    fn inspects_responses(&self, prefix: &str, policies: &[&PolicyConfig], aliased: bool) -> bool {
+        if tools::logs_calls() {
+            return true;
+        }
+        let Some(endpoint) = self.config.endpoints.get(prefix) else {
+            return false;
+        };
+        !policies.is_empty()
+            || self.output_redactors.contains_key(prefix)
+            || self.guardrails.get(prefix).is_some_and(|guardrail| guardrail.checks(GuardrailStage::Response))
+            || endpoint.rewrites.iter().any(|rule| rule.target.unwrap_or_default() == RewriteTarget::Response)
+            || (aliased && endpoint.restore_alias.unwrap_or(false))
+    }
+
+    /// The endpoint's policy and the caller's key policy, where configured.
+    fn policies(&self, prefix: &str, key_id: Option<&str>) -> Vec<&PolicyConfig> {
+        self.config.endpoints.get(prefix)
+            .and_then(|endpoint| endpoint.policy.as_ref())
+            .into_iter()
+            .chain(key_id.and_then(|key_id| self.config.keys.get(key_id)).map(|key| &key.policy))
+            .collect()
+    }
+
+    /// Rewrites an aliased `model` in the body before anything else sees it.
+    async fn resolve_model_alias(
+        &self,
+        prefix: &str,
+        request: Request,
+    ) -> Result<(Request, Option<ModelRewrite>), StatusCode> {
+        let endpoint_aliases = self.config.endpoints.get(prefix).map(|endpoint| &endpoint.aliases);
+        if self.config.aliases.is_empty() && endpoint_aliases.is_none_or(HashMap::is_empty) {
+            return Ok((request, None));
+        }
+
+        let (parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let no_aliases = HashMap::new();
+        match alias::rewrite_request(&body_bytes, endpoint_aliases.unwrap_or(&no_aliases), &self.config.aliases) {
+            Some((body, rewrite)) => Ok((Request::from_parts(parts, Body::from(body)), Some(rewrite))),
+            None => Ok((Request::from_parts(parts, Body::from(body_bytes)), None)),
+        }
+    }
+
+    /// Requests from clients that speak a different protocol than the
+    /// endpoint's upstream are translated on the way in and out.
+    async fn forward_translated(
+        &self,
+        prefix: &str,
+        request: Request,
+        entry: &mut AccessLogEntry,
+    ) -> Result<Response, StatusCode> {
+        let path = self.extract_path(request.uri(), prefix)?;
+        let translator = self.config.endpoints.get(prefix)
+            .and_then(Translator::for_endpoint)
+            .filter(|translator| translator.translates(request.method(), &path));
+        let Some(translator) = translator else {
+            return self.forward_request(prefix, request, entry).await;
+        };
+
+        let (mut parts, body) = request.into_parts();
+        let body_bytes = to_bytes(body, usize::MAX).await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let client_body = match serde_json::from_slice::<serde_json::Value>(&body_bytes) {
+            Ok(body) => body,
+            Err(e) => return Ok(translator.error_response(StatusCode::BAD_REQUEST, &format!("invalid JSON body: {}", e))),
+        };
+        let (upstream_path, translated) = match translator.request(&client_body) {
+            Ok(translated) => translated,
+            Err(message) => return Ok(translator.error_response(StatusCode::BAD_REQUEST, &message)),
+        };
+        debug!("Translating {} to {}", path, upstream_path);
+
+        // Gemini paths carry their own query (`alt=sse`); the client's is appended
+        let separator = if upstream_path.contains('?') { '&' } else { '?' };
+        let query = parts.uri.query().map(|query| format!("{}{}", separator, query)).unwrap_or_default();
+        parts.uri = format!("/{}{}{}", prefix, upstream_path, query).parse()
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        translator.upstream_headers(&mut parts.headers);
+
+        let request = Request::from_parts(parts, Body::from(translated.to_string()));
+        let response = self.forward_request(prefix, request, entry).await?;
+        Ok(translator.response(response, &client_body).await)
+    }
+
+    async fn forward_request(
+        &self,
+        prefix: &str,
         request: Request,
+        entry: &mut AccessLogEntry,
     ) -> Result<Response, StatusCode> {
         let method = request.method().clone();
         let uri = request.uri().clone();
         let headers = request.headers().clone();
+        let request_id = request.extensions().get::<RequestId>().cloned()
+            .unwrap_or_else(RequestId::generate);
         
         debug!("Proxying {} request to {}", method, uri);
         
         // Extract the path after the prefix
-        let path = self.extract_path(&uri, &prefix)?;
-        let target_base = self.config.get_endpoint_target_base(&prefix)
-            .unwrap_or_else(|| "https://api.anthropic.com".to_string());
-        let target_url = format!("{}{}", target_base, path);
+        let path = self.extract_path(&uri, prefix)?;
+        let target_base = self.target_base_for(prefix);
+        let query = uri.query().map(|query| format!("?{}", query)).unwrap_or_default();
+        let target_url = format!("{}{}{}", target_base, path, query);
         
         debug!("Forwarding to: {}", target_url);
+        entry.upstream = Some(target_url.clone());
         
         // Get the appropriate client for this endpoint
-        let client = self.get_client_for_endpoint(&prefix);
+        let client = self.get_client_for_endpoint(prefix);
         
         // Convert axum Request to reqwest Request
         let body_bytes = to_bytes(request.into_body(), usize::MAX).await
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        // Gemini names the model in the path rather than the body
+        entry.model = serde_json::from_slice::<serde_json::Value>(&body_bytes)
+            .ok()
+            .and_then(|body| body.get("model")?.as_str().map(str::to_string))
+            .or_else(|| gemini::path_model(&path).map(str::to_string))
+            .or_else(|| bedrock::path_model(&path));
+
+        // Deterministic requests may be answered from the response cache;
+        // `cache-control: no-cache` refreshes the entry, `no-store` skips it
+        let mut cache_key = None;
+        if let Some(cache) = self.caches.get(prefix) {
+            let directives = CacheDirectives::from_headers(&headers);
+            match cache.key(prefix, &path, uri.query(), &headers, &body_bytes) {
+                Some(key) if !directives.no_cache => {
+                    if let Some(response) = cache.get(&key).await {
+                        return Ok(response);
+                    }
+                    cache_key = (!directives.no_store).then_some(key);
+                }
+                Some(key) if !directives.no_store => cache_key = Some(key),
+                _ => {}
+            }
+        }
+
+        // Cassette endpoints either answer from disk or record what upstream says
+        let cassette = self.cassettes.get(prefix)
+            .map(|cassette| (cassette, cassette.key(method.as_str(), &path, uri.query(), &headers, &body_bytes)));
+        if let Some((cassette, key)) = &cassette {
+            if cassette.mode() == EndpointMode::Replay {
+                return Ok(cassette.replay(key, method.as_str(), &path));
+            }
+        }
+
+        // Identical requests already in flight share that call's response
+        let flight_key = self.coalescers.get(prefix).and_then(|coalescer| {
+            let key = Coalescer::key(prefix, method.as_str(), &path, uri.query(), &headers, &body_bytes)?;
+            Some((coalescer, key))
+        });
+        let flight = match flight_key.map(|(coalescer, key)| coalescer.join(key)) {
+            Some(Joined::Follower(follower)) => {
+                let response = follower.response().await.ok_or(StatusCode::BAD_GATEWAY)?;
+                entry.upstream_request_id = response.headers().get(UPSTREAM_REQUEST_ID_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                return Ok(response);
+            }
+            Some(Joined::Leader(leader)) => Some(leader),
+            None => None,
+        };
+        let started = Instant::now();
         
         // Convert the method
         let reqwest_method = match method.as_str() {
@@ -100,18 +974,32 @@ impl ProxyService {
             _ => reqwest::Method::GET, // fallback
         };
         
+        // Endpoints that authenticate themselves replace the client's credentials
+        let credentials = match self.upstream_credentials(prefix, client, method.as_str(), &target_url, &body_bytes).await {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                error!("Failed to obtain upstream credentials: {}", e);
+                return Err(StatusCode::BAD_GATEWAY);
+            }
+        };
+
         let mut req_builder = client
             .request(reqwest_method, &target_url);
         
-        // Copy headers
+        // Copy headers; the body may have been rewritten, so reqwest sets its length
         for (name, value) in headers.iter() {
-            if name.as_str().to_lowercase() != "host" {
+            let replaced = !credentials.is_empty() && CREDENTIAL_HEADERS.contains(&name.as_str());
+            if name != HOST && name != CONTENT_LENGTH && name != REQUEST_ID_HEADER && !replaced {
                 req_builder = req_builder.header(
                     name.as_str(),
                     value.to_str().unwrap_or("")
                 );
             }
         }
+        req_builder = req_builder.header(REQUEST_ID_HEADER, request_id.as_str());
+        for (name, value) in credentials {
+            req_builder = req_builder.header(name, value);
+        }
         
         // Set the body
         let req_builder = if !body_bytes.is_empty() {
@@ -121,11 +1009,13 @@ impl ProxyService {
         };
         
         // Execute the request
-        let response = req_builder.send().await
-            .map_err(|e| {
+        let response = match req_builder.send().await {
+            Ok(response) => response,
+            Err(e) => {
                 error!("Request failed: {}", e);
-                StatusCode::BAD_GATEWAY
-            })?;
+                return Err(StatusCode::BAD_GATEWAY);
+            }
+        };
         
         // Convert reqwest Response to axum Response
         let status_code = axum::http::StatusCode::from_u16(response.status().as_u16()).unwrap();
@@ -137,27 +1027,97 @@ impl ProxyService {
             axum_response = axum_response.header(name.as_str(), value.to_str().unwrap_or(""));
         }
         
-        // Get response body
-        let response_body = response.bytes().await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // Stream the response body through as it arrives
+        let response_body = response.bytes_stream()
+            .map_err(std::io::Error::other);
         
-        Ok(axum_response
-            .body(axum::body::Body::from(response_body.to_vec()))
-            .unwrap())
+        let mut axum_response = axum_response
+            .body(Body::from_stream(response_body))
+            .unwrap();
+
+        // The provider's own x-request-id would clash with ours, so it is
+        // returned as x-upstream-request-id instead
+        let response_headers = axum_response.headers_mut();
+        entry.upstream_request_id = upstream_request_id(response_headers);
+        response_headers.remove(REQUEST_ID_HEADER);
+        if let Some(upstream_id) = &entry.upstream_request_id {
+            debug!("Upstream request id: {}", upstream_id);
+            if let Ok(value) = HeaderValue::from_str(upstream_id) {
+                response_headers.insert(UPSTREAM_REQUEST_ID_HEADER, value);
+            }
+        }
+
+        if let Some(cache) = self.caches.get(prefix) {
+            axum_response = match cache_key {
+                Some(key) => cache.store_on_completion(key, axum_response),
+                None => {
+                    axum_response.headers_mut().insert(CACHE_HEADER, HeaderValue::from_static("BYPASS"));
+                    axum_response
+                }
+            };
+        }
+
+        if let Some((cassette, key)) = cassette {
+            axum_response = cassette.record(key, method.as_str(), &path, &headers, &body_bytes, started, axum_response);
+        }
+
+        if let Some(flight) = flight {
+            axum_response = flight.publish(axum_response);
+        }
+
+        Ok(axum_response)
     }
     
+    /// Credential headers for endpoints where the proxy authenticates to the
+    /// upstream itself; empty when the client's credentials are forwarded.
+    async fn upstream_credentials(
+        &self,
+        prefix: &str,
+        client: &reqwest::Client,
+        method: &str,
+        url: &str,
+        body: &[u8],
+    ) -> Result<Vec<(&'static str, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(auth) = self.google_auth.get(prefix) {
+            return Ok(vec![auth.header(client).await?]);
+        }
+        if let Some(signer) = self.signers.get(prefix) {
+            return Ok(signer.sign(method, &Url::parse(url)?, body, Utc::now()));
+        }
+        Ok(Vec::new())
+    }
+
+    /// The endpoint's own target, else a provider default for Gemini and
+    /// Bedrock, else the server-wide target.
+    fn target_base_for(&self, prefix: &str) -> String {
+        let Some(endpoint) = self.config.endpoints.get(prefix) else {
+            return self.target_base.clone();
+        };
+        if let Some(target_base) = &endpoint.target_base {
+            return target_base.clone();
+        }
+        match (endpoint.api_type, endpoint.gemini.as_ref().and_then(|gemini| gemini.location.as_deref())) {
+            (Some(ApiType::Gemini), Some(location)) => gemini::vertex_base(location),
+            (Some(ApiType::Gemini), None) => gemini::DEFAULT_GEMINI_BASE.to_string(),
+            (Some(ApiType::Bedrock), _) => match self.signers.get(prefix) {
+                Some(signer) => bedrock::runtime_base(signer.region()),
+                None => self.target_base.clone(),
+            },
+            (Some(ApiType::Ollama), _) => ollama::DEFAULT_OLLAMA_BASE.to_string(),
+            (Some(ApiType::LlamaCpp), _) => ollama::DEFAULT_LLAMACPP_BASE.to_string(),
+            _ => self.target_base.clone(),
+        }
+    }
+
+    /// The path after the endpoint prefix. The router only admits `/v1` and
+    /// `/v1beta` client paths; translated requests may use any upstream path.
     fn extract_path(&self, uri: &Uri, prefix: &str) -> Result<String, StatusCode> {
         let path = uri.path();
-        let expected_prefix = format!("/{}/v1", prefix);
-        
-        if !path.starts_with(&expected_prefix) {
-            return Err(StatusCode::BAD_REQUEST);
-        }
-        
-        let remaining_path = path[expected_prefix.len()..].to_string();
-        let final_path = format!("/v1{}", remaining_path);
+        let remaining_path = path.strip_prefix(&format!("/{}", prefix))
+            .filter(|remaining| remaining.starts_with('/'))
+            .ok_or(StatusCode::BAD_REQUEST)?;
         
-        Ok(final_path)
+        Ok(remaining_path.to_string())
     }
 }
 
@@ -167,12 +1127,31 @@ mod tests {
     use axum::http::Uri;
     use std::collections::HashMap;
 
+    fn service_with_clients(clients: HashMap<String, reqwest::Client>) -> ProxyService {
+        ProxyService {
+            clients,
+            config: Config::default(),
+            target_base: DEFAULT_TARGET_BASE.to_string(),
+            access_log: None,
+            capture: None,
+            cassettes: HashMap::new(),
+            caches: HashMap::new(),
+            coalescers: HashMap::new(),
+            google_auth: HashMap::new(),
+            signers: HashMap::new(),
+            models: ModelRouter::default(),
+            header_rules: HashMap::new(),
+            prompts: PromptLibrary::default(),
+            redactors: HashMap::new(),
+            output_redactors: HashMap::new(),
+            guardrails: HashMap::new(),
+            capabilities: CapabilityRegistry::default(),
+        }
+    }
+
     #[test]
     fn test_extract_path_valid() {
-        let proxy_service = ProxyService {
-            clients: HashMap::new(),
-            config: Config::default(),
-        };
+        let proxy_service = service_with_clients(HashMap::new());
         
         let uri = "/test/v1/messages".parse::<Uri>().unwrap();
         let result = proxy_service.extract_path(&uri, "test");
@@ -183,10 +1162,7 @@ mod tests {
 
     #[test]
     fn test_extract_path_invalid_prefix() {
-        let proxy_service = ProxyService {
-            clients: HashMap::new(),
-            config: Config::default(),
-        };
+        let proxy_service = service_with_clients(HashMap::new());
         
         let uri = "/wrong/v1/messages".parse::<Uri>().unwrap();
         let result = proxy_service.extract_path(&uri, "test");
@@ -197,10 +1173,7 @@ mod tests {
 
     #[test]
     fn test_extract_path_empty_path() {
-        let proxy_service = ProxyService {
-            clients: HashMap::new(),
-            config: Config::default(),
-        };
+        let proxy_service = service_with_clients(HashMap::new());
         
         let uri = "/test/v1".parse::<Uri>().unwrap();
         let result = proxy_service.extract_path(&uri, "test");
@@ -216,10 +1189,7 @@ mod tests {
         clients.insert("test".to_string(), client.clone());
         clients.insert("default".to_string(), reqwest::Client::new()); // Different default client
         
-        let proxy_service = ProxyService {
-            clients,
-            config: Config::default(),
-        };
+        let proxy_service = service_with_clients(clients);
         
         // Just check that it returns a client without panicking
         let _result = proxy_service.get_client_for_endpoint("test");
@@ -231,10 +1201,7 @@ mod tests {
         let client = reqwest::Client::new();
         clients.insert("default".to_string(), client.clone());
         
-        let proxy_service = ProxyService {
-            clients,
-            config: Config::default(),
-        };
+        let proxy_service = service_with_clients(clients);
         
         // Just check that it returns a client without panicking
         let _result = proxy_service.get_client_for_endpoint("nonexistent");
@@ -254,6 +1221,7 @@ mod tests {
         endpoints.insert("test".to_string(), crate::config::EndpointConfig {
             proxy_url: Some("http://proxy.example.com:8080".to_string()),
             target_base: None,
+            ..Default::default()
         });
         config.endpoints = endpoints;
         
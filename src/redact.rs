@@ -0,0 +1,290 @@
+use axum::http::HeaderMap;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// Replacement written in place of redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Credential headers that are masked no matter what the configuration says.
+pub const ALWAYS_REDACTED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "cookie",
+    "set-cookie",
+];
+
+/// Masks secrets in headers and JSON bodies before they are written to disk.
+#[derive(Debug)]
+pub struct Redactor {
+    headers: HashSet<String>,
+    fields: Vec<JsonPath>,
+    patterns: Vec<Regex>,
+}
+
+impl Default for Redactor {
+    /// Masks only the credential headers.
+    fn default() -> Self {
+        Self {
+            headers: ALWAYS_REDACTED_HEADERS.iter().map(|header| header.to_string()).collect(),
+            fields: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Redactor {
+    pub fn new(
+        headers: &[String],
+        fields: &[String],
+        patterns: &[String],
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let headers = ALWAYS_REDACTED_HEADERS
+            .iter()
+            .map(|header| header.to_string())
+            .chain(headers.iter().map(|header| header.to_ascii_lowercase()))
+            .collect();
+        let fields = fields
+            .iter()
+            .map(|field| JsonPath::parse(field))
+            .collect::<Result<_, _>>()?;
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            headers,
+            fields,
+            patterns,
+        })
+    }
+
+    /// Converts headers to a JSON object with denylisted values masked.
+    pub fn headers(&self, headers: &HeaderMap) -> Map<String, Value> {
+        let mut redacted = Map::new();
+        for name in headers.keys() {
+            let value = if self.headers.contains(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                let values: Vec<&str> = headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| value.to_str().unwrap_or(""))
+                    .collect();
+                self.text(&values.join(", "))
+            };
+            redacted.insert(name.to_string(), Value::String(value));
+        }
+        redacted
+    }
+
+    /// Masks configured field paths, then applies the regex masks to every
+    /// remaining string value.
+    pub fn json(&self, value: &mut Value) {
+        for field in &self.fields {
+            field.apply(value, &mut |matched| *matched = Value::String(REDACTED.to_string()));
+        }
+        if !self.patterns.is_empty() {
+            self.mask_strings(value);
+        }
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for pattern in &self.patterns {
+            text = pattern.replace_all(&text, REDACTED).into_owned();
+        }
+        text
+    }
+
+    /// Redacts a body that may or may not be JSON.
+    pub fn body(&self, body: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(mut value) => {
+                self.json(&mut value);
+                value
+            }
+            Err(_) => Value::String(self.text(&String::from_utf8_lossy(body))),
+        }
+    }
+
+    fn mask_strings(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.text(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.mask_strings(item)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| self.mask_strings(field)),
+            _ => {}
+        }
+    }
+}
+
+/// The subset of JSONPath used for field masks: `$.a.b`, `$.a[0]`, `$.a[*].b`
+/// and `$.a.*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath(Vec<Segment>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let rest = path
+            .strip_prefix('$')
+            .ok_or_else(|| format!("JSON path '{}' must start with '$'", path))?;
+
+        let mut segments = Vec::new();
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    let mut key = String::new();
+                    while let Some(&next) = chars.peek() {
+                        if next == '.' || next == '[' {
+                            break;
+                        }
+                        key.push(next);
+                        chars.next();
+                    }
+                    segments.push(match key.as_str() {
+                        "" => return Err(format!("empty key in JSON path '{}'", path)),
+                        "*" => Segment::Wildcard,
+                        _ => Segment::Key(key),
+                    });
+                }
+                '[' => {
+                    let mut inner = String::new();
+                    for next in chars.by_ref() {
+                        if next == ']' {
+                            break;
+                        }
+                        inner.push(next);
+                    }
+                    let inner = inner.trim();
+                    segments.push(if inner == "*" {
+                        Segment::Wildcard
+                    } else if let Ok(index) = inner.parse() {
+                        Segment::Index(index)
+                    } else {
+                        Segment::Key(inner.trim_matches(|c| c == '\'' || c == '"').to_string())
+                    });
+                }
+                _ => return Err(format!("unexpected '{}' in JSON path '{}'", c, path)),
+            }
+        }
+
+        Ok(Self(segments))
+    }
+
+    /// Calls `f` on every value the path matches.
+    pub fn apply(&self, value: &mut Value, f: &mut dyn FnMut(&mut Value)) {
+        Self::walk(&self.0, value, f);
+    }
+
+    fn walk(segments: &[Segment], value: &mut Value, f: &mut dyn FnMut(&mut Value)) {
+        let Some((segment, rest)) = segments.split_first() else {
+            f(value);
+            return;
+        };
+
+        match (segment, value) {
+            (Segment::Key(key), Value::Object(fields)) => {
+                if let Some(field) = fields.get_mut(key) {
+                    Self::walk(rest, field, f);
+                }
+            }
+            (Segment::Index(index), Value::Array(items)) => {
+                if let Some(item) = items.get_mut(*index) {
+                    Self::walk(rest, item, f);
+                }
+            }
+            (Segment::Wildcard, Value::Array(items)) => {
+                items.iter_mut().for_each(|item| Self::walk(rest, item, f));
+            }
+            (Segment::Wildcard, Value::Object(fields)) => {
+                fields.values_mut().for_each(|field| Self::walk(rest, field, f));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    #[test]
+    fn test_credential_headers_always_redacted() {
+        let redactor = Redactor::new(&["X-Internal-Token".to_string()], &[], &[]).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-ant-secret"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer sk-secret"));
+        headers.insert("x-internal-token", HeaderValue::from_static("tok"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+
+        let redacted = redactor.headers(&headers);
+        assert_eq!(redacted["x-api-key"], REDACTED);
+        assert_eq!(redacted["authorization"], REDACTED);
+        assert_eq!(redacted["x-internal-token"], REDACTED);
+        assert_eq!(redacted["content-type"], "application/json");
+    }
+
+    #[test]
+    fn test_json_path_field_masks() {
+        let redactor = Redactor::new(
+            &[],
+            &["$.metadata.user_id".to_string(), "$.messages[*].content".to_string()],
+            &[],
+        )
+        .unwrap();
+
+        let mut body = json!({
+            "model": "claude",
+            "metadata": { "user_id": "u-42" },
+            "messages": [{ "role": "user", "content": "hi" }, { "role": "assistant", "content": "yo" }]
+        });
+        redactor.json(&mut body);
+
+        assert_eq!(body["metadata"]["user_id"], REDACTED);
+        assert_eq!(body["messages"][0]["content"], REDACTED);
+        assert_eq!(body["messages"][1]["content"], REDACTED);
+        assert_eq!(body["messages"][1]["role"], "assistant");
+        assert_eq!(body["model"], "claude");
+    }
+
+    #[test]
+    fn test_regex_masks_apply_to_nested_strings_and_text() {
+        let redactor = Redactor::new(&[], &[], &["sk-[A-Za-z0-9]{8,}".to_string()]).unwrap();
+
+        let mut body = json!({ "messages": [{ "content": "my key is sk-abcdefgh1234 ok" }] });
+        redactor.json(&mut body);
+        assert_eq!(body["messages"][0]["content"], "my key is [REDACTED] ok");
+
+        assert_eq!(redactor.body(b"raw sk-abcdefgh1234"), json!("raw [REDACTED]"));
+    }
+
+    #[test]
+    fn test_json_path_parse() {
+        assert_eq!(
+            JsonPath::parse("$.a[0]['b'].*").unwrap(),
+            JsonPath(vec![
+                Segment::Key("a".to_string()),
+                Segment::Index(0),
+                Segment::Key("b".to_string()),
+                Segment::Wildcard,
+            ])
+        );
+        assert!(JsonPath::parse("a.b").is_err());
+        assert!(JsonPath::parse("$..a").is_err());
+    }
+}
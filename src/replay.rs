@@ -0,0 +1,394 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::capture::CaptureRecord;
+use crate::jsonl::JsonlWriter;
+use crate::proxy::ProxyService;
+use crate::redact::REDACTED;
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::usage::{Usage, UsageTracker};
+
+pub const USAGE: &str = "\
+Usage: anthropic-http-proxy replay --input <capture.jsonl> [options]
+
+Re-sends captured requests through the proxy and reports differences.
+
+Options:
+  --input <path>          Capture file to replay (required)
+  --endpoint <name>       Endpoint to send to (default: each record's own endpoint)
+  --concurrency <n>       Requests in flight at once (default: 1)
+  --rate <n>              Maximum requests started per second
+  --limit <n>             Replay at most this many records
+  --report <path>         Write the JSONL report here (default: stdout)
+  --header <name:value>   Extra request header, e.g. credentials (repeatable)";
+
+/// Command line options for the `replay` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    pub input: String,
+    pub endpoint: Option<String>,
+    pub concurrency: usize,
+    pub rate: Option<f64>,
+    pub limit: Option<usize>,
+    pub report: Option<String>,
+    pub headers: Vec<(String, String)>,
+}
+
+/// Outcome of one side of the comparison.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExchangeResult {
+    pub status: Option<u16>,
+    pub latency_ms: Option<u64>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+/// One line of the replay report: the recorded exchange next to the replayed one.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayReportEntry {
+    pub request_id: String,
+    pub replay_request_id: Option<String>,
+    pub endpoint: String,
+    pub method: String,
+    pub path: String,
+    pub original: ExchangeResult,
+    pub replay: ExchangeResult,
+    pub status_changed: bool,
+    pub latency_delta_ms: Option<i64>,
+    pub output_tokens_delta: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub total: usize,
+    pub status_changed: usize,
+    pub errors: usize,
+}
+
+impl ReplayOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut input = None;
+        let mut options = Self {
+            input: String::new(),
+            endpoint: None,
+            concurrency: 1,
+            rate: None,
+            limit: None,
+            report: None,
+            headers: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--input" => input = Some(value()?),
+                "--endpoint" => options.endpoint = Some(value()?),
+                "--concurrency" => {
+                    options.concurrency = parse_number(arg, &value()?)?;
+                    if options.concurrency == 0 {
+                        return Err("--concurrency must be at least 1".to_string());
+                    }
+                }
+                "--rate" => {
+                    let rate: f64 = parse_number(arg, &value()?)?;
+                    if rate <= 0.0 {
+                        return Err("--rate must be positive".to_string());
+                    }
+                    options.rate = Some(rate);
+                }
+                "--limit" => options.limit = Some(parse_number(arg, &value()?)?),
+                "--report" => options.report = Some(value()?),
+                "--header" => {
+                    let header = value()?;
+                    let (name, value) = header
+                        .split_once(':')
+                        .ok_or_else(|| format!("invalid header '{}', expected name:value", header))?;
+                    options.headers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+                other => return Err(format!("unknown option '{}'", other)),
+            }
+        }
+
+        options.input = input.ok_or("--input is required")?;
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, option))
+}
+
+/// Reads capture records, skipping (and warning about) unparseable lines.
+pub fn read_records(path: &str) -> Result<Vec<CaptureRecord>, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<CaptureRecord>(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => warn!("Skipping line {} of {}: {}", number + 1, path, e),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Replays every record through `proxy_service` and writes one report line per record.
+pub async fn run(
+    proxy_service: Arc<ProxyService>,
+    options: &ReplayOptions,
+) -> Result<ReplaySummary, Box<dyn std::error::Error>> {
+    let mut records = read_records(&options.input)?;
+    if let Some(limit) = options.limit {
+        records.truncate(limit);
+    }
+
+    let report = match &options.report {
+        Some(path) => JsonlWriter::new(Box::new(File::create(path)?) as Box<dyn Write + Send>),
+        None => JsonlWriter::stdout(),
+    };
+
+    let pacer = options
+        .rate
+        .map(|rate| Arc::new(Mutex::new(tokio::time::interval(Duration::from_secs_f64(1.0 / rate)))));
+
+    let entries = stream::iter(records)
+        .then(|record| {
+            let pacer = pacer.clone();
+            async move {
+                if let Some(pacer) = pacer {
+                    pacer.lock().await.tick().await;
+                }
+                record
+            }
+        })
+        .map(|record| replay_one(&proxy_service, options, record))
+        .buffer_unordered(options.concurrency);
+    futures::pin_mut!(entries);
+
+    let mut summary = ReplaySummary::default();
+    while let Some(entry) = entries.next().await {
+        summary.total += 1;
+        if entry.status_changed {
+            summary.status_changed += 1;
+        }
+        if entry.error.is_some() {
+            summary.errors += 1;
+        }
+        report.write(&entry);
+    }
+
+    Ok(summary)
+}
+
+async fn replay_one(
+    proxy_service: &ProxyService,
+    options: &ReplayOptions,
+    record: CaptureRecord,
+) -> ReplayReportEntry {
+    let endpoint = options.endpoint.clone().unwrap_or_else(|| record.endpoint.clone());
+    let original = record
+        .response
+        .as_ref()
+        .map(|response| {
+            let mut usage = Usage::default();
+            usage.merge_from(&response.body);
+            ExchangeResult {
+                status: Some(response.status),
+                latency_ms: Some(response.latency_ms),
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            }
+        })
+        .unwrap_or_default();
+
+    let mut entry = ReplayReportEntry {
+        request_id: record.request_id.clone(),
+        replay_request_id: None,
+        endpoint: endpoint.clone(),
+        method: record.method.clone(),
+        path: record.path.clone(),
+        original,
+        replay: ExchangeResult::default(),
+        status_changed: false,
+        latency_delta_ms: None,
+        output_tokens_delta: None,
+        error: None,
+    };
+
+    let request = match build_request(&record, &endpoint, &options.headers) {
+        Ok(request) => request,
+        Err(e) => {
+            entry.error = Some(e);
+            return entry;
+        }
+    };
+
+    let started = Instant::now();
+    match proxy_service.handle_request(endpoint, request).await {
+        Ok(response) => {
+            entry.replay_request_id = response
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            entry.replay.status = Some(response.status().as_u16());
+
+            let mut usage = UsageTracker::new(
+                response
+                    .headers()
+                    .get("content-type")
+                    .and_then(|value| value.to_str().ok()),
+            );
+            match to_bytes(response.into_body(), usize::MAX).await {
+                Ok(body) => usage.observe(&body),
+                Err(e) => entry.error = Some(format!("failed to read response body: {}", e)),
+            }
+            let usage = usage.finish();
+            entry.replay.input_tokens = usage.input_tokens;
+            entry.replay.output_tokens = usage.output_tokens;
+        }
+        Err(status) => {
+            entry.replay.status = Some(status.as_u16());
+            entry.error = Some(format!("proxy returned {}", status));
+        }
+    }
+    entry.replay.latency_ms = Some(started.elapsed().as_millis() as u64);
+
+    entry.status_changed = entry.original.status != entry.replay.status;
+    entry.latency_delta_ms = delta(entry.original.latency_ms, entry.replay.latency_ms);
+    entry.output_tokens_delta = delta(entry.original.output_tokens, entry.replay.output_tokens);
+    entry
+}
+
+fn delta(original: Option<u64>, replay: Option<u64>) -> Option<i64> {
+    Some(replay? as i64 - original? as i64)
+}
+
+fn build_request(
+    record: &CaptureRecord,
+    endpoint: &str,
+    extra_headers: &[(String, String)],
+) -> Result<Request, String> {
+    let query = record.query.as_ref().map(|query| format!("?{}", query)).unwrap_or_default();
+    let uri = format!("/{}{}{}", endpoint, record.path, query);
+    let mut builder = Request::builder().method(record.method.as_str()).uri(uri);
+
+    for (name, value) in &record.request.headers {
+        let Some(value) = value.as_str() else {
+            continue;
+        };
+        // Masked values, framing headers and the original call's id are not
+        // replayed; each replay gets its own request id
+        let skipped = matches!(name.as_str(), "host" | "content-length" | "transfer-encoding" | REQUEST_ID_HEADER);
+        if value == REDACTED || skipped {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value);
+    }
+
+    let body = match &record.request.body {
+        Value::Null => Body::empty(),
+        Value::String(text) => Body::from(text.clone()),
+        body => Body::from(body.to_string()),
+    };
+    let mut request = builder.body(body).map_err(|e| e.to_string())?;
+
+    for (name, value) in extra_headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| e.to_string())?;
+        let value = HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+        request.headers_mut().insert(name, value);
+    }
+
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options_from_args() {
+        let options = ReplayOptions::from_args(&args(&[
+            "--input", "capture.jsonl",
+            "--endpoint", "anthropic_dev",
+            "--concurrency", "4",
+            "--rate", "2.5",
+            "--header", "x-api-key: sk-test",
+        ]))
+        .unwrap();
+
+        assert_eq!(options.input, "capture.jsonl");
+        assert_eq!(options.endpoint.as_deref(), Some("anthropic_dev"));
+        assert_eq!(options.concurrency, 4);
+        assert_eq!(options.rate, Some(2.5));
+        assert_eq!(options.headers, vec![("x-api-key".to_string(), "sk-test".to_string())]);
+    }
+
+    #[test]
+    fn test_options_errors() {
+        assert!(ReplayOptions::from_args(&args(&[])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--input"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--input", "a", "--concurrency", "0"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--input", "a", "--bogus"])).is_err());
+        assert!(ReplayOptions::from_args(&args(&["--input", "a", "--header", "novalue"])).is_err());
+    }
+
+    #[test]
+    fn test_build_request_drops_redacted_headers() {
+        let record: CaptureRecord = serde_json::from_value(serde_json::json!({
+            "time": "2026-01-01T00:00:00Z",
+            "request_id": "req_1",
+            "endpoint": "dev",
+            "method": "POST",
+            "path": "/v1/chat/completions",
+            "query": "api-version=2024-10-21",
+            "request": {
+                "headers": {
+                    "x-api-key": "[REDACTED]",
+                    "content-length": "2",
+                    "anthropic-version": "2023-06-01",
+                    "x-request-id": "req_1"
+                },
+                "body": { "model": "claude" }
+            },
+            "response": null
+        }))
+        .unwrap();
+
+        let request = build_request(&record, "prod", &[("x-api-key".to_string(), "sk-new".to_string())]).unwrap();
+        assert_eq!(request.uri(), "/prod/v1/chat/completions?api-version=2024-10-21");
+        assert_eq!(request.headers()["x-api-key"], "sk-new");
+        assert_eq!(request.headers()["anthropic-version"], "2023-06-01");
+        assert!(request.headers().get("content-length").is_none());
+        assert!(request.headers().get(REQUEST_ID_HEADER).is_none());
+    }
+}
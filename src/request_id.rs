@@ -0,0 +1,96 @@
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+/// Correlation header accepted from clients, sent upstream and returned to clients.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Response header carrying the provider's own request id.
+pub const UPSTREAM_REQUEST_ID_HEADER: &str = "x-upstream-request-id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Correlation id for a single proxied call, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Reuses the client's `x-request-id` when it is a sane token, otherwise
+    /// generates a fresh one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid(id))
+            .map(|id| Self(id.to_string()))
+            .unwrap_or_else(Self::generate)
+    }
+
+    pub fn generate() -> Self {
+        Self(format!("req_{}", Uuid::new_v4().simple()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Anthropic returns `request-id`, OpenAI returns `x-request-id`.
+pub fn upstream_request_id(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("request-id")
+        .or_else(|| headers.get(REQUEST_ID_HEADER))
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_request_id_reuses_client_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("ticket-4711"));
+
+        assert_eq!(RequestId::from_headers(&headers).as_str(), "ticket-4711");
+    }
+
+    #[test]
+    fn test_request_id_generated_when_missing_or_invalid() {
+        let generated = RequestId::from_headers(&HeaderMap::new());
+        assert!(generated.as_str().starts_with("req_"));
+        assert_ne!(generated, RequestId::from_headers(&HeaderMap::new()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("has spaces"));
+        assert!(RequestId::from_headers(&headers).as_str().starts_with("req_"));
+
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&too_long).unwrap());
+        assert!(RequestId::from_headers(&headers).as_str().starts_with("req_"));
+    }
+
+    #[test]
+    fn test_upstream_request_id_prefers_anthropic_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-request-id", HeaderValue::from_static("req_openai"));
+        assert_eq!(upstream_request_id(&headers), Some("req_openai".to_string()));
+
+        headers.insert("request-id", HeaderValue::from_static("req_anthropic"));
+        assert_eq!(upstream_request_id(&headers), Some("req_anthropic".to_string()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,221 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::{CONTENT_LENGTH, CONTENT_TYPE}, HeaderValue},
+    response::Response,
+};
+use serde_json::{Map, Value};
+
+use crate::config::{RewriteOp, RewriteRule, RewriteTarget};
+use crate::routing::glob_match;
+
+/// What a rule's conditions are matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteContext {
+    /// The client's path after the endpoint prefix, e.g. `/v1/messages`.
+    pub path: String,
+    /// The request's model, after aliases are resolved.
+    pub model: Option<String>,
+}
+
+/// Rejects rules that could never apply, so mistakes surface at startup.
+pub fn validate(rules: &[RewriteRule]) -> Result<(), String> {
+    for rule in rules {
+        if !rule.pointer.starts_with('/') {
+            return Err(format!("rewrite pointer '{}' must start with '/'", rule.pointer));
+        }
+        if rule.op != RewriteOp::Remove && rule.value.is_none() {
+            return Err(format!("rewrite of '{}' needs a value", rule.pointer));
+        }
+    }
+    Ok(())
+}
+
+/// Applies the matching rules for `target` in order; returns whether the body changed.
+pub fn apply(rules: &[RewriteRule], target: RewriteTarget, context: &RewriteContext, body: &mut Value) -> bool {
+    let mut changed = false;
+    for rule in rules.iter().filter(|rule| rule.target.unwrap_or_default() == target && matches(rule, context)) {
+        changed |= match rule.op {
+            RewriteOp::Set => set(body, &rule.pointer, rule.value.clone().unwrap_or_default(), true),
+            RewriteOp::Default => set(body, &rule.pointer, rule.value.clone().unwrap_or_default(), false),
+            RewriteOp::Remove => remove(body, &rule.pointer),
+        };
+    }
+    changed
+}
+
+/// Rewrites a buffered JSON response; streamed and other bodies pass untouched.
+pub async fn apply_to_response(response: Response, rules: &[RewriteRule], context: &RewriteContext) -> Response {
+    let is_json = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let body = match serde_json::from_slice::<Value>(&body) {
+        Ok(mut value) => {
+            if apply(rules, RewriteTarget::Response, context, &mut value) {
+                let rewritten = value.to_string();
+                parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(rewritten.len()));
+                rewritten.into()
+            } else {
+                body
+            }
+        }
+        Err(_) => body,
+    };
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn matches(rule: &RewriteRule, context: &RewriteContext) -> bool {
+    let path_matches = rule.match_path.as_ref().is_none_or(|pattern| glob_match(pattern, &context.path));
+    let model_matches = rule.match_model.as_ref().is_none_or(|pattern| {
+        context.model.as_deref().is_some_and(|model| glob_match(pattern, model))
+    });
+    path_matches && model_matches
+}
+
+/// Splits an RFC 6901 pointer into unescaped tokens.
+fn tokens(pointer: &str) -> Vec<String> {
+    pointer.split('/').skip(1).map(|token| token.replace("~1", "/").replace("~0", "~")).collect()
+}
+
+/// Creates missing parent objects; `-` appends to an array. With `overwrite`
+/// false, an existing value is kept.
+fn set(body: &mut Value, pointer: &str, value: Value, overwrite: bool) -> bool {
+    let tokens = tokens(pointer);
+    let Some((last, parents)) = tokens.split_last() else {
+        return false;
+    };
+
+    let mut current = body;
+    for token in parents {
+        current = match current {
+            Value::Array(items) => match token.parse::<usize>().ok().and_then(|index| items.get_mut(index)) {
+                Some(item) => item,
+                None => return false,
+            },
+            Value::Object(object) => object.entry(token.clone()).or_insert_with(|| Value::Object(Map::new())),
+            _ => return false,
+        };
+    }
+
+    match current {
+        Value::Object(object) => {
+            if !overwrite && object.contains_key(last) {
+                return false;
+            }
+            object.insert(last.clone(), value);
+            true
+        }
+        Value::Array(items) if last == "-" => {
+            items.push(value);
+            true
+        }
+        Value::Array(items) => match last.parse::<usize>().ok().and_then(|index| items.get_mut(index)) {
+            Some(item) if overwrite => {
+                *item = value;
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn remove(body: &mut Value, pointer: &str) -> bool {
+    let tokens = tokens(pointer);
+    let Some((last, parents)) = tokens.split_last() else {
+        return false;
+    };
+    let parent_pointer: String = parents.iter().map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1"))).collect();
+    match body.pointer_mut(&parent_pointer) {
+        Some(Value::Object(object)) => object.remove(last).is_some(),
+        Some(Value::Array(items)) => match last.parse::<usize>() {
+            Ok(index) if index < items.len() => {
+                items.remove(index);
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(op: RewriteOp, pointer: &str, value: Option<Value>) -> RewriteRule {
+        RewriteRule {
+            op,
+            pointer: pointer.to_string(),
+            value,
+            ..Default::default()
+        }
+    }
+
+    fn context(path: &str, model: &str) -> RewriteContext {
+        RewriteContext {
+            path: path.to_string(),
+            model: Some(model.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_set_remove_and_default() {
+        let rules = vec![
+            rule(RewriteOp::Default, "/temperature", Some(json!(0.2))),
+            rule(RewriteOp::Set, "/metadata/user_id", Some(json!("team-a"))),
+            rule(RewriteOp::Remove, "/top_k", None),
+            rule(RewriteOp::Set, "/stop_sequences/-", Some(json!("END"))),
+            rule(RewriteOp::Remove, "/messages/0", None),
+        ];
+        let mut body = json!({
+            "temperature": 1.0,
+            "top_k": 5,
+            "stop_sequences": [],
+            "messages": [{ "role": "user", "content": "drop me" }, { "role": "user", "content": "Hi" }]
+        });
+
+        assert!(apply(&rules, RewriteTarget::Request, &context("/v1/messages", "claude"), &mut body));
+        assert_eq!(body, json!({
+            "temperature": 1.0,
+            "metadata": { "user_id": "team-a" },
+            "stop_sequences": ["END"],
+            "messages": [{ "role": "user", "content": "Hi" }]
+        }));
+
+        let mut body = json!({});
+        apply(&rules[..1], RewriteTarget::Request, &context("/v1/messages", "claude"), &mut body);
+        assert_eq!(body["temperature"], 0.2);
+        assert!(!apply(&rules[..1], RewriteTarget::Response, &context("/v1/messages", "claude"), &mut body));
+    }
+
+    #[test]
+    fn test_conditions_on_path_and_model() {
+        let mut include_usage = rule(RewriteOp::Set, "/stream_options/include_usage", Some(json!(true)));
+        include_usage.match_path = Some("/v1/chat/*".to_string());
+        include_usage.match_model = Some("gpt-4*".to_string());
+        let rules = vec![include_usage];
+
+        let mut body = json!({});
+        assert!(!apply(&rules, RewriteTarget::Request, &context("/v1/messages", "gpt-4o"), &mut body));
+        assert!(!apply(&rules, RewriteTarget::Request, &context("/v1/chat/completions", "o1"), &mut body));
+        assert!(apply(&rules, RewriteTarget::Request, &context("/v1/chat/completions", "gpt-4o"), &mut body));
+        assert_eq!(body, json!({ "stream_options": { "include_usage": true } }));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[rule(RewriteOp::Remove, "/a~1b", None)]).is_ok());
+        assert!(validate(&[rule(RewriteOp::Remove, "temperature", None)]).is_err());
+        assert!(validate(&[rule(RewriteOp::Set, "/temperature", None)]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+/// Request header naming the endpoint for an unprefixed `/v1` call,
+/// overriding the `[models]` registry.
+pub const ENDPOINT_HEADER: &str = "x-proxy-endpoint";
+
+/// Picks an endpoint from a request's model name, for clients that call the
+/// unprefixed `/v1` route.
+#[derive(Debug, Default, Clone)]
+pub struct ModelRouter {
+    exact: HashMap<String, String>,
+    /// Glob patterns, most specific first.
+    patterns: Vec<(String, String)>,
+}
+
+impl ModelRouter {
+    /// `models` maps model names or glob patterns (`*`, `?`) to endpoint names.
+    pub fn new(models: &HashMap<String, String>) -> Self {
+        let mut router = Self::default();
+        for (model, endpoint) in models {
+            if model.contains(['*', '?']) {
+                router.patterns.push((model.clone(), endpoint.clone()));
+            } else {
+                router.exact.insert(model.clone(), endpoint.clone());
+            }
+        }
+        // More literal characters wins, so `claude-3-5-*` beats `claude-*` and `*`
+        router.patterns.sort_by(|(a, _), (b, _)| literal_len(b).cmp(&literal_len(a)).then_with(|| a.cmp(b)));
+        router
+    }
+
+    /// Exact names first, then the most specific matching pattern.
+    pub fn endpoint(&self, model: &str) -> Option<&str> {
+        if let Some(endpoint) = self.exact.get(model) {
+            return Some(endpoint);
+        }
+        self.patterns
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, model))
+            .map(|(_, endpoint)| endpoint.as_str())
+    }
+
+    /// Every endpoint the registry routes to.
+    pub fn endpoints(&self) -> impl Iterator<Item = &str> {
+        self.exact.values().chain(self.patterns.iter().map(|(_, endpoint)| endpoint)).map(String::as_str)
+    }
+}
+
+fn literal_len(pattern: &str) -> usize {
+    pattern.chars().filter(|c| !matches!(c, '*' | '?')).count()
+}
+
+/// Matches `*` (any run of characters) and `?` (one character).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("claude-*", "claude-3-5-sonnet-latest"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("gpt-4?", "gpt-4o"));
+        assert!(glob_match("*sonnet*", "claude-3-5-sonnet-20241022"));
+        assert!(!glob_match("gpt-4?", "gpt-4o-mini"));
+        assert!(!glob_match("claude-*", "gpt-4o"));
+    }
+
+    #[test]
+    fn test_exact_names_beat_specific_patterns() {
+        let models = [
+            ("claude-3-5-haiku-latest", "anthropic_cheap"),
+            ("claude-3-5-*", "anthropic_prod"),
+            ("claude-*", "bedrock"),
+            ("*", "default"),
+        ]
+        .iter()
+        .map(|(model, endpoint)| (model.to_string(), endpoint.to_string()))
+        .collect();
+        let router = ModelRouter::new(&models);
+
+        assert_eq!(router.endpoint("claude-3-5-haiku-latest"), Some("anthropic_cheap"));
+        assert_eq!(router.endpoint("claude-3-5-sonnet-latest"), Some("anthropic_prod"));
+        assert_eq!(router.endpoint("claude-3-opus"), Some("bedrock"));
+        assert_eq!(router.endpoint("llama3.1"), Some("default"));
+        assert_eq!(ModelRouter::default().endpoint("llama3.1"), None);
+    }
+}
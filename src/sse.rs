@@ -0,0 +1,355 @@
+use axum::body::Bytes;
+use serde_json::{json, Map, Value};
+
+/// A single server-sent event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    pub fn new(event: Option<&str>, data: impl Into<String>) -> Self {
+        Self {
+            event: event.map(str::to_string),
+            data: data.into(),
+        }
+    }
+
+    /// Event carrying a JSON payload, named after its `type` field as Anthropic does.
+    pub fn json(value: &Value) -> Self {
+        Self {
+            event: value.get("type").and_then(Value::as_str).map(str::to_string),
+            data: value.to_string(),
+        }
+    }
+
+    pub fn json_data(&self) -> Option<Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str("event: ");
+            out.push_str(event);
+            out.push('\n');
+        }
+        for line in self.data.split('\n') {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// Incremental `text/event-stream` parser that copes with events split
+/// across arbitrary chunk boundaries.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds a chunk and returns every event it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(event) = self.process_line(line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flushes an event left unterminated at the end of the stream.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = String::from_utf8_lossy(&rest);
+        if !rest.trim().is_empty() {
+            self.process_line(rest.trim_end_matches(['\n', '\r']));
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() && event.is_none() {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent { event, data })
+    }
+}
+
+/// Parses a complete event stream body.
+pub fn parse_all(body: &[u8]) -> Vec<SseEvent> {
+    let mut parser = SseParser::new();
+    let mut events = parser.push(body);
+    events.extend(parser.finish());
+    events
+}
+
+/// Reassembles a streamed Anthropic or OpenAI response into the single JSON
+/// message the equivalent non-streaming call would have returned.
+pub fn assemble(events: &[SseEvent]) -> Option<Value> {
+    let payloads: Vec<Value> = events.iter().filter_map(SseEvent::json_data).collect();
+    let first = payloads.first()?;
+
+    if payloads
+        .iter()
+        .any(|payload| payload.get("type").and_then(Value::as_str) == Some("message_start"))
+    {
+        Some(assemble_anthropic(&payloads))
+    } else if first.get("object").and_then(Value::as_str) == Some("chat.completion.chunk") {
+        Some(assemble_openai(&payloads))
+    } else {
+        None
+    }
+}
+
+fn assemble_anthropic(payloads: &[Value]) -> Value {
+    let mut message = json!({});
+    let mut content: Vec<Value> = Vec::new();
+    let mut partial_json: Vec<String> = Vec::new();
+
+    for payload in payloads {
+        let index = payload.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+        match payload.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                message = payload.get("message").cloned().unwrap_or_else(|| json!({}));
+            }
+            Some("content_block_start") => {
+                if content.len() <= index {
+                    content.resize(index + 1, Value::Null);
+                    partial_json.resize(index + 1, String::new());
+                }
+                content[index] = payload.get("content_block").cloned().unwrap_or(Value::Null);
+            }
+            Some("content_block_delta") => {
+                let (Some(block), Some(delta)) = (content.get_mut(index), payload.get("delta")) else {
+                    continue;
+                };
+                match delta.get("type").and_then(Value::as_str) {
+                    Some("text_delta") => append_str(block, "text", delta.get("text")),
+                    Some("thinking_delta") => append_str(block, "thinking", delta.get("thinking")),
+                    Some("signature_delta") => append_str(block, "signature", delta.get("signature")),
+                    Some("input_json_delta") => {
+                        if let Some(partial) = delta.get("partial_json").and_then(Value::as_str) {
+                            partial_json[index].push_str(partial);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_stop") => {
+                if let Some(block) = content.get_mut(index) {
+                    if !partial_json[index].is_empty() {
+                        block["input"] = serde_json::from_str(&partial_json[index])
+                            .unwrap_or_else(|_| Value::String(partial_json[index].clone()));
+                    }
+                }
+            }
+            Some("message_delta") => {
+                if let Some(delta) = payload.get("delta").and_then(Value::as_object) {
+                    for (key, value) in delta {
+                        message[key] = value.clone();
+                    }
+                }
+                if let Some(usage) = payload.get("usage").and_then(Value::as_object) {
+                    merge_object(&mut message, "usage", usage);
+                }
+            }
+            Some("error") => {
+                message["error"] = payload.get("error").cloned().unwrap_or(Value::Null);
+            }
+            _ => {}
+        }
+    }
+
+    message["content"] = Value::Array(content.into_iter().filter(|block| !block.is_null()).collect());
+    message
+}
+
+fn assemble_openai(payloads: &[Value]) -> Value {
+    let mut completion = json!({ "object": "chat.completion" });
+    let mut choices: Vec<Value> = Vec::new();
+
+    for payload in payloads {
+        for key in ["id", "created", "model", "system_fingerprint"] {
+            if let Some(value) = payload.get(key).filter(|value| !value.is_null()) {
+                completion[key] = value.clone();
+            }
+        }
+        if let Some(usage) = payload.get("usage").filter(|usage| usage.is_object()) {
+            completion["usage"] = usage.clone();
+        }
+
+        for choice in payload.get("choices").and_then(Value::as_array).into_iter().flatten() {
+            let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+            if choices.len() <= index {
+                choices.resize_with(index + 1, || json!({ "message": { "role": "assistant", "content": null } }));
+                for (i, choice) in choices.iter_mut().enumerate() {
+                    choice["index"] = json!(i);
+                }
+            }
+            let target = &mut choices[index];
+
+            if let Some(delta) = choice.get("delta") {
+                let message = &mut target["message"];
+                if let Some(role) = delta.get("role").filter(|role| role.is_string()) {
+                    message["role"] = role.clone();
+                }
+                append_str(message, "content", delta.get("content"));
+                append_str(message, "refusal", delta.get("refusal"));
+
+                for call in delta.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+                    let call_index = call.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+                    if !message["tool_calls"].is_array() {
+                        message["tool_calls"] = json!([]);
+                    }
+                    let calls = message["tool_calls"].as_array_mut().unwrap();
+                    if calls.len() <= call_index {
+                        calls.resize_with(call_index + 1, || {
+                            json!({ "type": "function", "function": { "name": "", "arguments": "" } })
+                        });
+                    }
+                    let target_call = &mut calls[call_index];
+                    if let Some(id) = call.get("id").filter(|id| id.is_string()) {
+                        target_call["id"] = id.clone();
+                    }
+                    if let Some(function) = call.get("function") {
+                        append_str(&mut target_call["function"], "name", function.get("name"));
+                        append_str(&mut target_call["function"], "arguments", function.get("arguments"));
+                    }
+                }
+            }
+            if let Some(reason) = choice.get("finish_reason").filter(|reason| !reason.is_null()) {
+                target["finish_reason"] = reason.clone();
+            }
+        }
+    }
+
+    completion["choices"] = Value::Array(choices);
+    completion
+}
+
+fn append_str(target: &mut Value, key: &str, addition: Option<&Value>) {
+    let Some(addition) = addition.and_then(Value::as_str) else {
+        return;
+    };
+    match target.get_mut(key) {
+        Some(Value::String(existing)) => existing.push_str(addition),
+        _ => target[key] = Value::String(addition.to_string()),
+    }
+}
+
+fn merge_object(target: &mut Value, key: &str, source: &Map<String, Value>) {
+    if !target[key].is_object() {
+        target[key] = json!({});
+    }
+    for (field, value) in source {
+        target[key][field] = value.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_handles_split_chunks_and_multiline_data() {
+        let mut parser = SseParser::new();
+        assert!(parser.push(b"event: ping\nda").is_empty());
+
+        let events = parser.push(b"ta: {}\n\n: comment\ndata: a\r\ndata: b\n\n");
+        assert_eq!(events, vec![SseEvent::new(Some("ping"), "{}"), SseEvent::new(None, "a\nb")]);
+
+        assert!(parser.push(b"data: [DONE]").is_empty());
+        assert_eq!(parser.finish(), Some(SseEvent::new(None, "[DONE]")));
+    }
+
+    #[test]
+    fn test_event_round_trip() {
+        let event = SseEvent::json(&json!({ "type": "message_stop" }));
+        assert_eq!(&event.to_bytes()[..], b"event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n");
+        assert_eq!(parse_all(&event.to_bytes()), vec![event]);
+    }
+
+    #[test]
+    fn test_assemble_anthropic_stream() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude\",\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"lo\"}}\n\n",
+            "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"tu_1\",\"name\":\"get_weather\",\"input\":{}}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"city\\\":\"}}\n\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"Paris\\\"}\"}}\n\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":12}}\n\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        );
+
+        let message = assemble(&parse_all(body.as_bytes())).unwrap();
+        assert_eq!(message["id"], "msg_1");
+        assert_eq!(message["content"][0]["text"], "Hello");
+        assert_eq!(message["content"][1]["input"], json!({ "city": "Paris" }));
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["usage"], json!({ "input_tokens": 10, "output_tokens": 12 }));
+    }
+
+    #[test]
+    fn test_assemble_openai_stream() {
+        let body = concat!(
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hi\"}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"lookup\",\"arguments\":\"{\\\"q\\\"\"}}]}}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\":1}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"model\":\"gpt-4o\",\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":4}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let completion = assemble(&parse_all(body.as_bytes())).unwrap();
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(completion["choices"][0]["message"]["content"], "Hi");
+        assert_eq!(completion["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"], "{\"q\":1}");
+        assert_eq!(completion["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(completion["usage"]["completion_tokens"], 4);
+    }
+}
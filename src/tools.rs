@@ -0,0 +1,560 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE}, HeaderValue, StatusCode},
+    response::Response,
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use tracing::{info, warn, Level};
+
+use crate::body::{self, BodyObserver};
+use crate::config::{ApiType, PolicyConfig, ToolCallAction};
+use crate::routing::glob_match;
+use crate::sse::{SseEvent, SseParser};
+use crate::translate::{self, ollama::{self, NdjsonDecoder}, stream::{self, EventDecoder, StreamTranscoder}};
+
+/// The tool rules of every policy a request falls under, kept to check the
+/// calls the model makes in its response.
+#[derive(Debug, Clone)]
+pub struct ToolFilter {
+    /// One allowlist per policy that has one; a call must match each
+    allowed: Vec<Vec<String>>,
+    denied: Vec<String>,
+    action: ToolCallAction,
+    client: ApiType,
+}
+
+impl ToolFilter {
+    /// The first policy to set `tool_call_action` decides what happens to a
+    /// call that is not allowed.
+    pub fn new(policies: &[&PolicyConfig], client: ApiType) -> Self {
+        Self {
+            allowed: policies.iter().filter_map(|policy| policy.allowed_tools.clone()).collect(),
+            denied: policies.iter().flat_map(|policy| policy.denied_tools.iter().cloned()).collect(),
+            action: policies.iter().find_map(|policy| policy.tool_call_action).unwrap_or_default(),
+            client,
+        }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        self.allowed.iter().all(|patterns| patterns.iter().any(|pattern| glob_match(pattern, name)))
+            && !self.denied.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Logs a call; `Ok(false)` means rewrite it, `Err` means block the response.
+    fn check(&self, name: &str) -> Result<bool, String> {
+        if self.allows(name) {
+            info!(tool = %name, "Model called tool '{}'", name);
+            return Ok(true);
+        }
+        warn!(tool = %name, action = ?self.action, "Model called tool '{}', which is not allowed", name);
+        match self.action {
+            ToolCallAction::Block => Err(format!("model called tool '{}', which is not allowed", name)),
+            ToolCallAction::Rewrite => Ok(false),
+        }
+    }
+
+    /// Checks the tool calls in an Anthropic, OpenAI, Gemini or Ollama response body,
+    /// replacing disallowed calls with text when rewriting. Returns whether
+    /// the body changed, or the message to block it with.
+    pub fn filter_body(&self, body: &mut Value) -> Result<bool, String> {
+        let mut changed = false;
+
+        if let Some(content) = body.get_mut("content").and_then(Value::as_array_mut) {
+            let mut kept = false;
+            for block in content.iter_mut().filter(|block| block["type"] == "tool_use") {
+                let name = block["name"].as_str().unwrap_or_default().to_string();
+                if self.check(&name)? {
+                    kept = true;
+                } else {
+                    *block = json!({ "type": "text", "text": blocked_message(&name) });
+                    changed = true;
+                }
+            }
+            if changed && !kept && body["stop_reason"] == "tool_use" {
+                body["stop_reason"] = json!("end_turn");
+            }
+        }
+
+        for choice in body.get_mut("choices").and_then(Value::as_array_mut).into_iter().flatten() {
+            let Some(calls_left) = choice.get_mut("message").map(|message| self.filter_message(message)).transpose()?.flatten() else {
+                continue;
+            };
+            changed = true;
+            if !calls_left && choice["finish_reason"] == "tool_calls" {
+                choice["finish_reason"] = json!("stop");
+            }
+        }
+
+        // Ollama's own chat responses carry a single top-level message
+        if let Some(message) = body.get_mut("message") {
+            changed |= self.filter_message(message)?.is_some();
+        }
+
+        for candidate in body.get_mut("candidates").and_then(Value::as_array_mut).into_iter().flatten() {
+            for part in candidate["content"]["parts"].as_array_mut().into_iter().flatten() {
+                let Some(name) = part.get("functionCall").map(|call| call["name"].as_str().unwrap_or_default().to_string()) else {
+                    continue;
+                };
+                if !self.check(&name)? {
+                    *part = json!({ "text": blocked_message(&name) });
+                    changed = true;
+                }
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Drops disallowed calls from an OpenAI or Ollama message and notes them
+    /// in its text. Returns `None` if nothing changed, otherwise whether any
+    /// call is left.
+    fn filter_message(&self, message: &mut Value) -> Result<Option<bool>, String> {
+        let Some(calls) = message.get_mut("tool_calls").and_then(Value::as_array_mut) else {
+            return Ok(None);
+        };
+        let mut messages = Vec::new();
+        for call in std::mem::take(calls) {
+            let name = call["function"]["name"].as_str().unwrap_or_default().to_string();
+            if self.check(&name)? {
+                calls.push(call);
+            } else {
+                messages.push(blocked_message(&name));
+            }
+        }
+        if messages.is_empty() {
+            return Ok(None);
+        }
+        let calls_left = !calls.is_empty();
+        let content = message["content"].as_str()
+            .filter(|content| !content.is_empty())
+            .map(str::to_string)
+            .into_iter()
+            .chain(messages)
+            .collect::<Vec<_>>();
+        message["content"] = json!(content.join("\n"));
+        if !calls_left {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("tool_calls");
+            }
+        }
+        Ok(Some(calls_left))
+    }
+
+    fn error_event(&self, message: &str) -> SseEvent {
+        let body = translate::error_body(self.client, StatusCode::FORBIDDEN, message);
+        match self.client {
+            ApiType::Anthropic | ApiType::Bedrock => SseEvent::json(&body),
+            _ => SseEvent::new(None, body.to_string()),
+        }
+    }
+}
+
+fn blocked_message(name: &str) -> String {
+    format!("[Tool call '{}' was blocked by policy.]", name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyFormat {
+    Json,
+    EventStream,
+    Ndjson,
+}
+
+/// The body format of a successful, uncompressed response tool calls can be
+/// read from.
+fn readable(response: &Response) -> Option<BodyFormat> {
+    if !response.status().is_success() || response.headers().contains_key(CONTENT_ENCODING) {
+        return None;
+    }
+    let content_type = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/event-stream") {
+        Some(BodyFormat::EventStream)
+    } else if content_type.starts_with(ollama::NDJSON_CONTENT_TYPE) {
+        Some(BodyFormat::Ndjson)
+    } else if content_type.starts_with("application/json") {
+        Some(BodyFormat::Json)
+    } else {
+        None
+    }
+}
+
+/// Checks the tool calls in a successful response; a blocked buffered
+/// response becomes a `403` in the client's protocol.
+pub async fn filter_response(response: Response, filter: ToolFilter) -> Response {
+    match readable(&response) {
+        Some(BodyFormat::EventStream) => return stream::transcode(response, Box::new(ToolCallStream::new(filter))),
+        Some(BodyFormat::Ndjson) => return stream::transcode_lines(response, Box::new(ToolCallStream::new(filter))),
+        Some(BodyFormat::Json) => {}
+        None => return response,
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+        return Response::from_parts(parts, Body::from(body));
+    };
+    match filter.filter_body(&mut value) {
+        Ok(false) => Response::from_parts(parts, Body::from(body)),
+        Ok(true) => {
+            let rewritten = value.to_string();
+            parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(rewritten.len()));
+            Response::from_parts(parts, Body::from(rewritten))
+        }
+        Err(message) => translate::error_response(filter.client, StatusCode::FORBIDDEN, &message),
+    }
+}
+
+/// Whether tool calls are logged, in which case every response is read.
+pub fn logs_calls() -> bool {
+    tracing::enabled!(Level::INFO)
+}
+
+/// Logs the tool calls in a response for endpoints without tool rules. The
+/// body is watched as it goes by, never buffered or changed.
+pub fn log_response(response: Response, client: ApiType) -> Response {
+    let decoder: Option<Box<dyn EventDecoder>> = match readable(&response) {
+        Some(BodyFormat::EventStream) => Some(Box::new(SseParser::new())),
+        Some(BodyFormat::Ndjson) => Some(Box::new(NdjsonDecoder::new())),
+        Some(BodyFormat::Json) => None,
+        None => return response,
+    };
+    let observer = ToolCallLog {
+        calls: ToolCallStream::new(ToolFilter::new(&[], client)),
+        decoder,
+        buffered: Vec::new(),
+    };
+    body::observe(response, observer)
+}
+
+/// Feeds a response to an allow-everything [`ToolCallStream`], whose checks
+/// log each call.
+struct ToolCallLog {
+    calls: ToolCallStream,
+    /// `None` for buffered JSON, which is read once it is complete
+    decoder: Option<Box<dyn EventDecoder>>,
+    buffered: Vec<u8>,
+}
+
+impl BodyObserver for ToolCallLog {
+    fn on_chunk(&mut self, chunk: &Bytes) {
+        match &mut self.decoder {
+            Some(decoder) => {
+                for event in decoder.push(chunk) {
+                    self.calls.event(event);
+                }
+            }
+            None => self.buffered.extend_from_slice(chunk),
+        }
+    }
+
+    fn on_end(&mut self, complete: bool) {
+        if !complete {
+            return;
+        }
+        match &mut self.decoder {
+            Some(decoder) => {
+                for event in decoder.finish() {
+                    self.calls.event(event);
+                }
+            }
+            None => {
+                if let Ok(mut body) = serde_json::from_slice::<Value>(&self.buffered) {
+                    let _ = self.calls.filter.filter_body(&mut body);
+                }
+            }
+        }
+    }
+}
+
+/// Checks tool calls as they stream. A rewritten Anthropic `tool_use` block
+/// becomes a text block and its input deltas are dropped; rewritten OpenAI
+/// calls are removed from the deltas; Gemini and Ollama chunks carry whole
+/// calls and are filtered like buffered bodies. A block ends the stream with
+/// an error event.
+pub struct ToolCallStream {
+    filter: ToolFilter,
+    stopped: bool,
+    /// Anthropic content blocks turned into text
+    rewritten_blocks: HashSet<u64>,
+    /// OpenAI (choice, tool call) indexes removed from the stream
+    rewritten_calls: HashSet<(u64, u64)>,
+    kept: bool,
+}
+
+impl ToolCallStream {
+    pub fn new(filter: ToolFilter) -> Self {
+        Self {
+            filter,
+            stopped: false,
+            rewritten_blocks: HashSet::new(),
+            rewritten_calls: HashSet::new(),
+            kept: false,
+        }
+    }
+
+    fn stop(&mut self, message: &str) -> Vec<SseEvent> {
+        self.stopped = true;
+        vec![self.filter.error_event(message)]
+    }
+
+    fn anthropic(&mut self, event: SseEvent, mut payload: Value) -> Vec<SseEvent> {
+        let index = payload["index"].as_u64().unwrap_or(0);
+        match payload["type"].as_str() {
+            Some("content_block_start") if payload["content_block"]["type"] == "tool_use" => {
+                let name = payload["content_block"]["name"].as_str().unwrap_or_default().to_string();
+                match self.filter.check(&name) {
+                    Ok(true) => {
+                        self.kept = true;
+                        vec![event]
+                    }
+                    Ok(false) => {
+                        self.rewritten_blocks.insert(index);
+                        vec![
+                            SseEvent::json(&json!({
+                                "type": "content_block_start",
+                                "index": index,
+                                "content_block": { "type": "text", "text": "" },
+                            })),
+                            SseEvent::json(&json!({
+                                "type": "content_block_delta",
+                                "index": index,
+                                "delta": { "type": "text_delta", "text": blocked_message(&name) },
+                            })),
+                        ]
+                    }
+                    Err(message) => self.stop(&message),
+                }
+            }
+            Some("content_block_delta") if self.rewritten_blocks.contains(&index) => Vec::new(),
+            Some("message_delta") if payload["delta"]["stop_reason"] == "tool_use" && !self.kept && !self.rewritten_blocks.is_empty() => {
+                payload["delta"]["stop_reason"] = json!("end_turn");
+                vec![SseEvent::json(&payload)]
+            }
+            _ => vec![event],
+        }
+    }
+
+    fn openai(&mut self, mut event: SseEvent, mut payload: Value) -> Vec<SseEvent> {
+        let mut changed = false;
+        for choice in payload["choices"].as_array_mut().into_iter().flatten() {
+            let index = choice["index"].as_u64().unwrap_or(0);
+            let mut messages = Vec::new();
+            if let Some(calls) = choice["delta"]["tool_calls"].as_array_mut() {
+                for call in std::mem::take(calls) {
+                    let key = (index, call["index"].as_u64().unwrap_or(0));
+                    if let Some(name) = call["function"]["name"].as_str() {
+                        match self.filter.check(name) {
+                            Ok(true) => self.kept = true,
+                            Ok(false) => {
+                                self.rewritten_calls.insert(key);
+                                messages.push(blocked_message(name));
+                            }
+                            Err(message) => return self.stop(&message),
+                        }
+                    }
+                    if self.rewritten_calls.contains(&key) {
+                        changed = true;
+                    } else {
+                        calls.push(call);
+                    }
+                }
+                if calls.is_empty() {
+                    if let Some(delta) = choice["delta"].as_object_mut() {
+                        delta.remove("tool_calls");
+                    }
+                }
+            }
+            if !messages.is_empty() {
+                let content = choice["delta"]["content"].as_str().into_iter().map(str::to_string).chain(messages).collect::<Vec<_>>();
+                choice["delta"]["content"] = json!(content.join("\n"));
+            }
+            let rewritten_here = self.rewritten_calls.iter().any(|(choice, _)| *choice == index);
+            if choice["finish_reason"] == "tool_calls" && rewritten_here && !self.kept {
+                choice["finish_reason"] = json!("stop");
+                changed = true;
+            }
+        }
+        if changed {
+            event.data = payload.to_string();
+        }
+        vec![event]
+    }
+
+    fn whole_calls(&mut self, mut event: SseEvent, mut payload: Value) -> Vec<SseEvent> {
+        match self.filter.filter_body(&mut payload) {
+            Ok(true) => {
+                event.data = payload.to_string();
+                vec![event]
+            }
+            Ok(false) => vec![event],
+            Err(message) => self.stop(&message),
+        }
+    }
+}
+
+impl StreamTranscoder for ToolCallStream {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if self.stopped {
+            return Vec::new();
+        }
+        let Some(payload) = event.json_data() else {
+            return vec![event];
+        };
+        if payload.get("type").is_some() {
+            self.anthropic(event, payload)
+        } else if payload.get("choices").is_some() {
+            self.openai(event, payload)
+        } else if payload.get("candidates").is_some() || payload.get("message").is_some() {
+            self.whole_calls(event, payload)
+        } else {
+            vec![event]
+        }
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(action: ToolCallAction, client: ApiType) -> ToolFilter {
+        let policy = PolicyConfig {
+            allowed_tools: Some(vec!["search_*".to_string()]),
+            tool_call_action: Some(action),
+            ..Default::default()
+        };
+        let key_policy = PolicyConfig {
+            denied_tools: vec!["search_internal".to_string()],
+            ..Default::default()
+        };
+        ToolFilter::new(&[&policy, &key_policy], client)
+    }
+
+    #[test]
+    fn test_filter_body() {
+        let filter = filter(ToolCallAction::Rewrite, ApiType::Anthropic);
+        assert!(filter.allows("search_web"));
+        assert!(!filter.allows("search_internal"));
+        assert!(!filter.allows("shell"));
+
+        let mut body = json!({
+            "content": [{ "type": "tool_use", "id": "t1", "name": "shell", "input": { "cmd": "ls" } }],
+            "stop_reason": "tool_use"
+        });
+        assert_eq!(filter.filter_body(&mut body), Ok(true));
+        assert_eq!(body["content"][0], json!({ "type": "text", "text": "[Tool call 'shell' was blocked by policy.]" }));
+        assert_eq!(body["stop_reason"], "end_turn");
+
+        let mut body = json!({ "choices": [{ "index": 0, "finish_reason": "tool_calls", "message": { "content": null, "tool_calls": [
+            { "id": "c1", "type": "function", "function": { "name": "search_web", "arguments": "{}" } },
+            { "id": "c2", "type": "function", "function": { "name": "shell", "arguments": "{}" } }
+        ] } }] });
+        assert_eq!(filter.filter_body(&mut body), Ok(true));
+        assert_eq!(body["choices"][0]["message"]["tool_calls"].as_array().unwrap().len(), 1);
+        assert_eq!(body["choices"][0]["message"]["content"], "[Tool call 'shell' was blocked by policy.]");
+        assert_eq!(body["choices"][0]["finish_reason"], "tool_calls");
+
+        let filter = self::filter(ToolCallAction::Block, ApiType::Gemini);
+        let mut body = json!({ "candidates": [{ "content": { "parts": [{ "functionCall": { "name": "search_internal", "args": {} } }] } }] });
+        assert_eq!(filter.filter_body(&mut body), Err("model called tool 'search_internal', which is not allowed".to_string()));
+    }
+
+    #[test]
+    fn test_filter_ollama_message() {
+        let filter = filter(ToolCallAction::Rewrite, ApiType::Ollama);
+        let mut body = json!({ "message": { "role": "assistant", "content": "", "tool_calls": [
+            { "function": { "name": "shell", "arguments": { "cmd": "ls" } } }
+        ] }, "done": true });
+        assert_eq!(filter.filter_body(&mut body), Ok(true));
+        assert_eq!(body["message"], json!({ "role": "assistant", "content": "[Tool call 'shell' was blocked by policy.]" }));
+    }
+
+    async fn ndjson(response: Response) -> Vec<Value> {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        body.split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect()
+    }
+
+    fn ollama_stream(tool: &str) -> Response {
+        let lines = [
+            json!({ "message": { "role": "assistant", "content": "Let me check." }, "done": false }),
+            json!({ "message": { "role": "assistant", "content": "", "tool_calls": [{ "function": { "name": tool, "arguments": {} } }] }, "done": false }),
+            json!({ "message": { "role": "assistant", "content": "" }, "done": true }),
+        ];
+        let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        Response::builder()
+            .header(CONTENT_TYPE, ollama::NDJSON_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_filter_ollama_stream() {
+        let lines = ndjson(filter_response(ollama_stream("shell"), filter(ToolCallAction::Rewrite, ApiType::Ollama)).await).await;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["message"]["content"], "[Tool call 'shell' was blocked by policy.]");
+        assert!(lines[1]["message"].get("tool_calls").is_none());
+
+        let lines = ndjson(filter_response(ollama_stream("shell"), filter(ToolCallAction::Block, ApiType::Ollama)).await).await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1], json!({ "error": "model called tool 'shell', which is not allowed" }));
+    }
+
+    #[tokio::test]
+    async fn test_log_response_leaves_body_alone() {
+        let stream = "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"tool_use\",\"name\":\"shell\"}}\n\n: keep-alive\n\n";
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "text/event-stream")
+            .body(Body::from(stream))
+            .unwrap();
+        let body = to_bytes(log_response(response, ApiType::Anthropic).into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, stream);
+
+        let original = ndjson(ollama_stream("shell")).await;
+        assert_eq!(ndjson(log_response(ollama_stream("shell"), ApiType::Ollama)).await, original);
+    }
+
+    #[test]
+    fn test_stream_rewrites_anthropic_tool_use() {
+        let mut transcoder = ToolCallStream::new(filter(ToolCallAction::Rewrite, ApiType::Anthropic));
+        let events = [
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "tool_use", "id": "t1", "name": "shell", "input": {} } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "input_json_delta", "partial_json": "{\"cmd\":" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "message_delta", "delta": { "stop_reason": "tool_use" } }),
+        ];
+        let out: Vec<Value> = events.iter()
+            .flat_map(|event| transcoder.event(SseEvent::json(event)))
+            .filter_map(|event| event.json_data())
+            .collect();
+        assert_eq!(out.len(), 4);
+        assert_eq!(out[0]["content_block"]["type"], "text");
+        assert_eq!(out[1]["delta"]["text"], "[Tool call 'shell' was blocked by policy.]");
+        assert_eq!(out[2]["type"], "content_block_stop");
+        assert_eq!(out[3]["delta"]["stop_reason"], "end_turn");
+    }
+
+    #[test]
+    fn test_stream_blocks_openai_tool_call() {
+        let mut transcoder = ToolCallStream::new(filter(ToolCallAction::Block, ApiType::OpenAI));
+        let chunk = |calls: Value| SseEvent::new(None, json!({ "choices": [{ "index": 0, "delta": { "tool_calls": calls }, "finish_reason": null }] }).to_string());
+
+        let allowed = transcoder.event(chunk(json!([{ "index": 0, "function": { "name": "search_web", "arguments": "" } }])));
+        assert_eq!(allowed[0].json_data().unwrap()["choices"][0]["delta"]["tool_calls"][0]["function"]["name"], "search_web");
+
+        let blocked = transcoder.event(chunk(json!([{ "index": 1, "function": { "name": "shell", "arguments": "" } }])));
+        assert_eq!(blocked[0].json_data().unwrap()["error"]["type"], "permission_error");
+        assert!(transcoder.event(SseEvent::new(None, "[DONE]")).is_empty());
+    }
+}
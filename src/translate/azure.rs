@@ -0,0 +1,72 @@
+//! Azure OpenAI speaks the OpenAI protocol, but names the deployment in the
+//! path, requires an `api-version` and authenticates with `api-key`.
+
+use percent_encoding::utf8_percent_encode;
+use std::collections::HashMap;
+
+use super::PATH_SEGMENT_ESCAPE;
+use crate::config::AzureConfig;
+
+pub const DEFAULT_API_VERSION: &str = "2024-10-21";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureTarget {
+    api_version: String,
+    deployments: HashMap<String, String>,
+}
+
+impl Default for AzureTarget {
+    fn default() -> Self {
+        Self {
+            api_version: DEFAULT_API_VERSION.to_string(),
+            deployments: HashMap::new(),
+        }
+    }
+}
+
+impl AzureTarget {
+    pub fn from_config(config: &AzureConfig) -> Self {
+        Self {
+            api_version: config.api_version.clone().unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+            deployments: config.deployments.clone(),
+        }
+    }
+
+    /// Upstream path and query for a chat completion with `model`. An
+    /// unmapped model names the deployment itself, so it is escaped to stay
+    /// within its path segment.
+    pub fn chat_path(&self, model: &str) -> String {
+        let deployment = self.deployments.get(model).map(String::as_str).unwrap_or(model);
+        format!(
+            "/openai/deployments/{}/chat/completions?api-version={}",
+            utf8_percent_encode(deployment, PATH_SEGMENT_ESCAPE),
+            self.api_version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_mapping() {
+        let config = AzureConfig {
+            api_version: Some("2024-06-01".to_string()),
+            deployments: HashMap::from([("gpt-4o".to_string(), "prod-gpt4o".to_string())]),
+        };
+        let target = AzureTarget::from_config(&config);
+        assert_eq!(
+            target.chat_path("gpt-4o"),
+            "/openai/deployments/prod-gpt4o/chat/completions?api-version=2024-06-01"
+        );
+        assert_eq!(
+            AzureTarget::default().chat_path("gpt-4o-mini"),
+            "/openai/deployments/gpt-4o-mini/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            AzureTarget::default().chat_path("../../other?api-version=1#x"),
+            "/openai/deployments/..%2F..%2Fother%3Fapi-version%3D1%23x/chat/completions?api-version=2024-10-21"
+        );
+    }
+}
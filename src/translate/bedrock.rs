@@ -0,0 +1,301 @@
+//! Claude on AWS Bedrock: Anthropic request bodies with the model moved into
+//! the path, and streams framed as `application/vnd.amazon.eventstream`.
+
+use axum::http::StatusCode;
+use base64::Engine;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use serde_json::{json, Value};
+
+use super::stream::EventDecoder;
+use super::PATH_SEGMENT_ESCAPE;
+use crate::sse::SseEvent;
+
+pub const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+pub const EVENT_STREAM_CONTENT_TYPE: &str = "application/vnd.amazon.eventstream";
+
+/// Frames larger than this are treated as corrupt rather than buffered.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// Total length, headers length and prelude CRC.
+const PRELUDE_LEN: usize = 12;
+
+pub fn runtime_base(region: &str) -> String {
+    format!("https://bedrock-runtime.{}.amazonaws.com", region)
+}
+
+/// Upstream path for a model ID or inference profile ARN, whose `:` and `/`
+/// are escaped.
+pub fn path(model: &str, stream: bool) -> String {
+    let method = if stream { "invoke-with-response-stream" } else { "invoke" };
+    format!("/model/{}/{}", utf8_percent_encode(model, PATH_SEGMENT_ESCAPE), method)
+}
+
+/// The model named in a `/model/{modelId}/...` path.
+pub fn path_model(path: &str) -> Option<String> {
+    let model = path.strip_prefix("/model/")?.split('/').next()?;
+    Some(percent_decode_str(model).decode_utf8_lossy().into_owned())
+}
+
+/// Bedrock takes the Messages body without `model` and `stream`, and with
+/// its own `anthropic_version`.
+pub fn messages_request_to_bedrock(request: &Value) -> Result<(String, bool, Value), String> {
+    let mut request = request
+        .as_object()
+        .cloned()
+        .ok_or("request body must be a JSON object")?;
+    let model = match request.remove("model") {
+        Some(Value::String(model)) => model,
+        _ => return Err("'model' is required".to_string()),
+    };
+    let stream = request.remove("stream").and_then(|stream| stream.as_bool()).unwrap_or(false);
+    request
+        .entry("anthropic_version")
+        .or_insert_with(|| json!(BEDROCK_ANTHROPIC_VERSION));
+    Ok((model, stream, Value::Object(request)))
+}
+
+/// Bedrock errors are `{"message": ...}`; the kind comes from the status.
+pub fn bedrock_error_to_anthropic(status: StatusCode, error: &Value) -> Value {
+    let kind = match status.as_u16() {
+        400 | 413 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        _ => "api_error",
+    };
+    let message = error
+        .get("message")
+        .or_else(|| error.get("Message"))
+        .and_then(Value::as_str)
+        .unwrap_or("upstream error");
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+fn exception_to_anthropic(exception: &str, payload: &Value) -> Value {
+    let status = match exception {
+        "validationException" => StatusCode::BAD_REQUEST,
+        "accessDeniedException" => StatusCode::FORBIDDEN,
+        "throttlingException" => StatusCode::TOO_MANY_REQUESTS,
+        "serviceUnavailableException" => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    bedrock_error_to_anthropic(status, payload)
+}
+
+/// Decodes `application/vnd.amazon.eventstream` frames into the Anthropic
+/// events carried base64-encoded in their payloads.
+#[derive(Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>,
+    failed: bool,
+}
+
+impl EventStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops decoding and reports the stream as broken to the client.
+    fn fail(&mut self, message: &str) -> SseEvent {
+        self.failed = true;
+        self.buffer.clear();
+        SseEvent::json(&json!({ "type": "error", "error": { "type": "api_error", "message": message } }))
+    }
+}
+
+impl EventDecoder for EventStreamDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        if self.failed {
+            return Vec::new();
+        }
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while self.buffer.len() >= PRELUDE_LEN {
+            let total_len = u32::from_be_bytes(self.buffer[0..4].try_into().unwrap()) as usize;
+            let headers_len = u32::from_be_bytes(self.buffer[4..8].try_into().unwrap()) as usize;
+            let prelude_crc = u32::from_be_bytes(self.buffer[8..12].try_into().unwrap());
+            if crc32fast::hash(&self.buffer[..8]) != prelude_crc
+                || total_len > MAX_FRAME_LEN
+                || total_len < PRELUDE_LEN + headers_len + 4
+            {
+                events.push(self.fail("malformed event stream frame from Bedrock"));
+                break;
+            }
+            if self.buffer.len() < total_len {
+                break;
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..total_len).collect();
+            let message_crc = u32::from_be_bytes(frame[total_len - 4..].try_into().unwrap());
+            if crc32fast::hash(&frame[..total_len - 4]) != message_crc {
+                events.push(self.fail("corrupt event stream frame from Bedrock"));
+                break;
+            }
+            let Some(headers) = frame_headers(&frame[PRELUDE_LEN..PRELUDE_LEN + headers_len]) else {
+                events.push(self.fail("malformed event stream headers from Bedrock"));
+                break;
+            };
+            let payload: Value = serde_json::from_slice(&frame[PRELUDE_LEN + headers_len..total_len - 4]).unwrap_or(Value::Null);
+            let header = |name: &str| headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+            match (header(":message-type"), header(":event-type"), header(":exception-type")) {
+                (Some("event"), Some("chunk"), _) => {
+                    let event = payload["bytes"]
+                        .as_str()
+                        .and_then(|bytes| base64::engine::general_purpose::STANDARD.decode(bytes).ok())
+                        .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+                    match event {
+                        Some(event) => events.push(SseEvent::json(&event)),
+                        None => {
+                            events.push(self.fail("undecodable chunk in Bedrock event stream"));
+                            break;
+                        }
+                    }
+                }
+                (Some("exception"), _, Some(exception)) | (Some("error"), _, Some(exception)) => {
+                    events.push(SseEvent::json(&exception_to_anthropic(exception, &payload)));
+                    self.failed = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        if self.failed || self.buffer.is_empty() {
+            return Vec::new();
+        }
+        vec![self.fail("Bedrock event stream ended mid-frame")]
+    }
+}
+
+/// Parses frame headers, keeping the string-valued ones.
+fn frame_headers(mut bytes: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    while !bytes.is_empty() {
+        let name_len = *bytes.first()? as usize;
+        let name = std::str::from_utf8(bytes.get(1..1 + name_len)?).ok()?.to_string();
+        let value_type = *bytes.get(1 + name_len)?;
+        bytes = &bytes[2 + name_len..];
+        let value_len = match value_type {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 | 7 => 2 + u16::from_be_bytes(bytes.get(..2)?.try_into().ok()?) as usize,
+            _ => return None,
+        };
+        let value = bytes.get(..value_len)?;
+        if value_type == 7 {
+            headers.push((name, std::str::from_utf8(&value[2..]).ok()?.to_string()));
+        }
+        bytes = &bytes[value_len..];
+    }
+    Some(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes one event stream frame with string headers.
+    fn frame(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut header_bytes = Vec::new();
+        for (name, value) in headers {
+            header_bytes.push(name.len() as u8);
+            header_bytes.extend_from_slice(name.as_bytes());
+            header_bytes.push(7);
+            header_bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            header_bytes.extend_from_slice(value.as_bytes());
+        }
+        let total_len = (PRELUDE_LEN + header_bytes.len() + payload.len() + 4) as u32;
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&total_len.to_be_bytes());
+        frame.extend_from_slice(&(header_bytes.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&frame).to_be_bytes());
+        frame.extend_from_slice(&header_bytes);
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&crc32fast::hash(&frame).to_be_bytes());
+        frame
+    }
+
+    /// A `chunk` event frame carrying one Anthropic event.
+    fn chunk(event: &Value) -> Vec<u8> {
+        let bytes = base64::engine::general_purpose::STANDARD.encode(event.to_string());
+        frame(
+            &[(":event-type", "chunk"), (":content-type", "application/json"), (":message-type", "event")],
+            json!({ "bytes": bytes, "p": "abcd" }).to_string().as_bytes(),
+        )
+    }
+
+    #[test]
+    fn test_paths_and_request() {
+        assert_eq!(
+            path("anthropic.claude-3-5-sonnet-20240620-v1:0", true),
+            "/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/invoke-with-response-stream"
+        );
+        assert_eq!(
+            path_model("/model/anthropic.claude-3-haiku-20240307-v1%3A0/invoke").as_deref(),
+            Some("anthropic.claude-3-haiku-20240307-v1:0")
+        );
+
+        let request = json!({ "model": "anthropic.claude-v2", "stream": true, "max_tokens": 10, "messages": [] });
+        let (model, stream, body) = messages_request_to_bedrock(&request).unwrap();
+        assert_eq!(model, "anthropic.claude-v2");
+        assert!(stream);
+        assert_eq!(
+            body,
+            json!({ "anthropic_version": BEDROCK_ANTHROPIC_VERSION, "max_tokens": 10, "messages": [] })
+        );
+    }
+
+    #[test]
+    fn test_event_stream_decoded_across_chunk_boundaries() {
+        let start = json!({ "type": "message_start", "message": { "id": "msg_1" } });
+        let stop = json!({ "type": "message_stop" });
+        let mut stream = chunk(&start);
+        stream.extend(chunk(&stop));
+
+        for chunk_size in [stream.len(), 7, 1] {
+            let mut decoder = EventStreamDecoder::new();
+            let mut events = Vec::new();
+            for piece in stream.chunks(chunk_size) {
+                events.extend(decoder.push(piece));
+            }
+            events.extend(decoder.finish());
+
+            assert_eq!(events, vec![SseEvent::json(&start), SseEvent::json(&stop)], "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_event_stream_exceptions_and_corruption() {
+        let throttled = frame(
+            &[(":exception-type", "throttlingException"), (":message-type", "exception")],
+            br#"{"message":"Too many requests"}"#,
+        );
+        let events = EventStreamDecoder::new().push(&throttled);
+        let error = events[0].json_data().unwrap();
+        assert_eq!(events[0].event.as_deref(), Some("error"));
+        assert_eq!(error["error"]["type"], "rate_limit_error");
+        assert_eq!(error["error"]["message"], "Too many requests");
+
+        let mut corrupt = chunk(&json!({ "type": "ping" }));
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xff;
+        let mut decoder = EventStreamDecoder::new();
+        assert_eq!(decoder.push(&corrupt)[0].event.as_deref(), Some("error"));
+        assert!(decoder.push(&chunk(&json!({ "type": "ping" }))).is_empty());
+
+        let mut truncated = EventStreamDecoder::new();
+        assert!(truncated.push(&chunk(&json!({ "type": "ping" }))[..20]).is_empty());
+        assert_eq!(truncated.finish().len(), 1);
+    }
+}
@@ -0,0 +1,509 @@
+//! Conversions between Anthropic Messages and Gemini `generateContent`
+//! bodies. OpenAI clients reach Gemini through the Anthropic shape.
+
+use percent_encoding::utf8_percent_encode;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use super::stream::{MessageEvents, StreamTranscoder};
+use super::PATH_SEGMENT_ESCAPE;
+use crate::sse::SseEvent;
+
+pub const DEFAULT_GEMINI_BASE: &str = "https://generativelanguage.googleapis.com";
+
+/// Vertex AI serves Gemini from a regional host.
+pub fn vertex_base(location: &str) -> String {
+    format!("https://{}-aiplatform.googleapis.com", location)
+}
+
+/// Where the model lives: the Gemini API, or a Vertex AI project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeminiTarget {
+    Gemini,
+    Vertex { project: String, location: String },
+}
+
+impl GeminiTarget {
+    /// Upstream path (and query) for a model, streamed as SSE when `stream` is
+    /// set. The model comes from the client, so it is escaped to stay within
+    /// its path segment.
+    pub fn path(&self, model: &str, stream: bool) -> String {
+        let model = model.strip_prefix("models/").unwrap_or(model);
+        let model = utf8_percent_encode(model, PATH_SEGMENT_ESCAPE);
+        let method = if stream { "streamGenerateContent?alt=sse" } else { "generateContent" };
+        match self {
+            GeminiTarget::Gemini => format!("/v1beta/models/{}:{}", model, method),
+            GeminiTarget::Vertex { project, location } => format!(
+                "/v1/projects/{}/locations/{}/publishers/google/models/{}:{}",
+                project, location, model, method
+            ),
+        }
+    }
+}
+
+/// The model named in a `.../models/{model}:{method}` path.
+pub fn path_model(path: &str) -> Option<&str> {
+    let (_, rest) = path.rsplit_once("/models/")?;
+    rest.split_once(':').map(|(model, _)| model)
+}
+
+/// Converts a `/v1/messages` request into a `generateContent` request,
+/// returning the model and whether the client asked for a stream alongside it.
+pub fn messages_request_to_gemini(request: &Value) -> Result<(String, bool, Value), String> {
+    let request = request
+        .as_object()
+        .ok_or("request body must be a JSON object")?;
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or("'model' is required")?
+        .to_string();
+    let stream = request.get("stream").and_then(Value::as_bool).unwrap_or(false);
+
+    // Gemini matches function responses by name rather than by call id
+    let mut tool_names: HashMap<String, Value> = HashMap::new();
+    let mut contents = Vec::new();
+    for message in request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("'messages' must be an array")?
+    {
+        let role = match message["role"].as_str() {
+            Some("user") => "user",
+            Some("assistant") => "model",
+            other => return Err(format!("unsupported message role '{}'", other.unwrap_or(""))),
+        };
+        let parts = match &message["content"] {
+            Value::String(text) => vec![json!({ "text": text })],
+            Value::Array(blocks) => blocks
+                .iter()
+                .map(|block| content_part(block, &mut tool_names))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+            _ => Vec::new(),
+        };
+        if !parts.is_empty() {
+            contents.push(json!({ "role": role, "parts": parts }));
+        }
+    }
+
+    let mut gemini = Map::new();
+    gemini.insert("contents".to_string(), Value::Array(contents));
+
+    let system = match request.get("system") {
+        Some(Value::String(system)) => Some(system.clone()),
+        Some(Value::Array(blocks)) => Some(
+            blocks
+                .iter()
+                .filter_map(|block| block["text"].as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        _ => None,
+    };
+    if let Some(system) = system {
+        gemini.insert("systemInstruction".to_string(), json!({ "parts": [{ "text": system }] }));
+    }
+
+    let mut generation = Map::new();
+    for (from, to) in [
+        ("max_tokens", "maxOutputTokens"),
+        ("temperature", "temperature"),
+        ("top_p", "topP"),
+        ("top_k", "topK"),
+        ("stop_sequences", "stopSequences"),
+    ] {
+        if let Some(value) = request.get(from).filter(|value| !value.is_null()) {
+            generation.insert(to.to_string(), value.clone());
+        }
+    }
+    if !generation.is_empty() {
+        gemini.insert("generationConfig".to_string(), Value::Object(generation));
+    }
+
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        let declarations: Vec<Value> = tools
+            .iter()
+            .map(|tool| {
+                let mut declaration = Map::new();
+                declaration.insert("name".to_string(), tool["name"].clone());
+                if let Some(description) = tool.get("description") {
+                    declaration.insert("description".to_string(), description.clone());
+                }
+                if let Some(schema) = tool.get("input_schema") {
+                    declaration.insert("parameters".to_string(), gemini_schema(schema));
+                }
+                Value::Object(declaration)
+            })
+            .collect();
+        gemini.insert("tools".to_string(), json!([{ "functionDeclarations": declarations }]));
+    }
+
+    if let Some(choice) = request.get("tool_choice") {
+        let config = match choice["type"].as_str() {
+            Some("any") => json!({ "mode": "ANY" }),
+            Some("none") => json!({ "mode": "NONE" }),
+            Some("tool") => json!({ "mode": "ANY", "allowedFunctionNames": [choice["name"]] }),
+            _ => json!({ "mode": "AUTO" }),
+        };
+        gemini.insert("toolConfig".to_string(), json!({ "functionCallingConfig": config }));
+    }
+
+    Ok((model, stream, Value::Object(gemini)))
+}
+
+fn content_part(block: &Value, tool_names: &mut HashMap<String, Value>) -> Result<Option<Value>, String> {
+    let part = match block["type"].as_str() {
+        Some("text") => json!({ "text": block["text"] }),
+        Some("image") => {
+            let source = &block["source"];
+            match source["type"].as_str() {
+                Some("base64") => json!({
+                    "inlineData": { "mimeType": source["media_type"], "data": source["data"] }
+                }),
+                _ => json!({
+                    "fileData": { "mimeType": mime_type(source["url"].as_str().unwrap_or("")), "fileUri": source["url"] }
+                }),
+            }
+        }
+        Some("tool_use") => {
+            let id = block["id"].as_str().unwrap_or("").to_string();
+            tool_names.insert(id, block["name"].clone());
+            json!({ "functionCall": { "name": block["name"], "args": block["input"] } })
+        }
+        Some("tool_result") => {
+            let id = block["tool_use_id"].as_str().unwrap_or("");
+            let name = tool_names
+                .get(id)
+                .cloned()
+                .ok_or_else(|| format!("tool_result '{}' does not follow a matching tool_use", id))?;
+            let text = match &block["content"] {
+                Value::String(text) => text.clone(),
+                Value::Array(blocks) => blocks.iter().filter_map(|block| block["text"].as_str()).collect(),
+                _ => String::new(),
+            };
+            let key = if block["is_error"] == Value::Bool(true) { "error" } else { "content" };
+            json!({ "functionResponse": { "name": name, "response": { key: text } } })
+        }
+        Some("thinking") | Some("redacted_thinking") => return Ok(None),
+        other => return Err(format!("unsupported content block type {:?}", other.unwrap_or("?"))),
+    };
+    Ok(Some(part))
+}
+
+fn mime_type(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_ascii_lowercase();
+    match path.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        _ => "image/jpeg",
+    }
+}
+
+/// Gemini accepts an OpenAPI subset and rejects JSON Schema keywords it does
+/// not know.
+fn gemini_schema(schema: &Value) -> Value {
+    match schema {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| !matches!(key.as_str(), "$schema" | "additionalProperties"))
+                .map(|(key, value)| (key.clone(), gemini_schema(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(gemini_schema).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Converts a `generateContent` response into a `/v1/messages` response.
+pub fn gemini_response_to_messages(response: &Value) -> Value {
+    let candidate = &response["candidates"][0];
+    let mut content = Vec::new();
+    for (index, part) in candidate["content"]["parts"].as_array().into_iter().flatten().enumerate() {
+        if part["thought"] == Value::Bool(true) {
+            continue;
+        }
+        if let Some(text) = part["text"].as_str() {
+            content.push(json!({ "type": "text", "text": text }));
+        } else if let Some(call) = part.get("functionCall") {
+            content.push(json!({
+                "type": "tool_use",
+                "id": tool_use_id(call, index),
+                "name": call["name"],
+                "input": if call["args"].is_object() { call["args"].clone() } else { json!({}) },
+            }));
+        }
+    }
+
+    let called_tool = content.iter().any(|block| block["type"] == "tool_use");
+    json!({
+        "id": response.get("responseId").cloned().unwrap_or_else(|| json!("msg_gemini")),
+        "type": "message",
+        "role": "assistant",
+        "model": response["modelVersion"],
+        "content": content,
+        "stop_reason": stop_reason(candidate["finishReason"].as_str(), called_tool),
+        "stop_sequence": null,
+        "usage": messages_usage(&response["usageMetadata"]),
+    })
+}
+
+/// Converts a Google API error into the Anthropic error shape.
+pub fn gemini_error_to_anthropic(error: &Value) -> Value {
+    let error = error.get("error").unwrap_or(error);
+    let kind = match error["status"].as_str() {
+        Some("INVALID_ARGUMENT") | Some("FAILED_PRECONDITION") => "invalid_request_error",
+        Some("UNAUTHENTICATED") => "authentication_error",
+        Some("PERMISSION_DENIED") => "permission_error",
+        Some("NOT_FOUND") => "not_found_error",
+        Some("RESOURCE_EXHAUSTED") => "rate_limit_error",
+        Some("UNAVAILABLE") => "overloaded_error",
+        _ => "api_error",
+    };
+    json!({
+        "type": "error",
+        "error": { "type": kind, "message": error["message"].as_str().unwrap_or("upstream error") },
+    })
+}
+
+fn tool_use_id(call: &Value, index: usize) -> Value {
+    match call["id"].as_str() {
+        Some(id) => json!(id),
+        None => json!(format!("toolu_gemini_{}", index)),
+    }
+}
+
+fn stop_reason(finish_reason: Option<&str>, called_tool: bool) -> Value {
+    match finish_reason {
+        None => Value::Null,
+        Some("STOP") if called_tool => "tool_use".into(),
+        Some("STOP") => "end_turn".into(),
+        Some("MAX_TOKENS") => "max_tokens".into(),
+        Some("SAFETY") | Some("RECITATION") | Some("BLOCKLIST") | Some("PROHIBITED_CONTENT") | Some("SPII") => {
+            "refusal".into()
+        }
+        Some(_) => "end_turn".into(),
+    }
+}
+
+fn messages_usage(usage: &Value) -> Value {
+    let cached = usage["cachedContentTokenCount"].as_u64().unwrap_or(0);
+    json!({
+        "input_tokens": usage["promptTokenCount"].as_u64().unwrap_or(0).saturating_sub(cached),
+        "output_tokens": usage["candidatesTokenCount"].as_u64().unwrap_or(0),
+        "cache_read_input_tokens": cached,
+    })
+}
+
+/// Gemini `streamGenerateContent?alt=sse` chunks to Anthropic message events.
+#[derive(Default)]
+pub struct GeminiToAnthropic {
+    message: MessageEvents,
+    tool_calls: usize,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl GeminiToAnthropic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamTranscoder for GeminiToAnthropic {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if self.message.is_finished() {
+            return Vec::new();
+        }
+        let Some(chunk) = event.json_data() else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+        if chunk.get("error").is_some() {
+            self.message.error(gemini_error_to_anthropic(&chunk), &mut events);
+            return events;
+        }
+
+        let id = chunk.get("responseId").cloned().unwrap_or_else(|| json!("msg_gemini"));
+        self.message.start(&id, &chunk["modelVersion"], &mut events);
+        if chunk["usageMetadata"].is_object() {
+            self.usage = chunk["usageMetadata"].clone();
+        }
+
+        let candidate = &chunk["candidates"][0];
+        for part in candidate["content"]["parts"].as_array().into_iter().flatten() {
+            if part["thought"] == Value::Bool(true) {
+                continue;
+            }
+            if let Some(text) = part["text"].as_str() {
+                self.message.text(text, &mut events);
+            } else if let Some(call) = part.get("functionCall") {
+                // Gemini sends each call whole, so every call is its own block
+                let index = self.tool_calls;
+                self.tool_calls += 1;
+                self.message.tool_call(&index.to_string(), &tool_use_id(call, index), &call["name"], &mut events);
+                let args = if call["args"].is_object() { call["args"].to_string() } else { "{}".to_string() };
+                self.message.tool_arguments(&args, &mut events);
+            }
+        }
+        if let Some(reason) = candidate["finishReason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        match self.finish_reason.as_deref() {
+            Some(reason) => {
+                let stop_reason = stop_reason(Some(reason), self.tool_calls > 0);
+                self.message.finish(stop_reason, messages_usage(&self.usage), &mut events);
+            }
+            None => self.message.truncated(&mut events),
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_for_gemini_and_vertex() {
+        assert_eq!(
+            GeminiTarget::Gemini.path("gemini-1.5-pro", false),
+            "/v1beta/models/gemini-1.5-pro:generateContent"
+        );
+        assert_eq!(
+            GeminiTarget::Gemini.path("models/gemini-1.5-flash", true),
+            "/v1beta/models/gemini-1.5-flash:streamGenerateContent?alt=sse"
+        );
+        let vertex = GeminiTarget::Vertex {
+            project: "acme".to_string(),
+            location: "us-central1".to_string(),
+        };
+        assert_eq!(
+            vertex.path("gemini-1.5-pro", false),
+            "/v1/projects/acme/locations/us-central1/publishers/google/models/gemini-1.5-pro:generateContent"
+        );
+
+        // A client-chosen model cannot leave its path segment
+        assert_eq!(
+            GeminiTarget::Gemini.path("../../v1/projects/other/x?key=1#frag", false),
+            "/v1beta/models/..%2F..%2Fv1%2Fprojects%2Fother%2Fx%3Fkey%3D1%23frag:generateContent"
+        );
+        assert_eq!(
+            vertex.path("gemini:evil", true),
+            "/v1/projects/acme/locations/us-central1/publishers/google/models/gemini%3Aevil:streamGenerateContent?alt=sse"
+        );
+        assert_eq!(path_model("/v1beta/models/gemini-1.5-flash:streamGenerateContent"), Some("gemini-1.5-flash"));
+        assert_eq!(path_model("/v1/messages"), None);
+    }
+
+    #[test]
+    fn test_messages_request_to_gemini() {
+        let request = json!({
+            "model": "gemini-1.5-pro",
+            "system": "Be brief.",
+            "max_tokens": 100,
+            "stop_sequences": ["END"],
+            "messages": [
+                { "role": "user", "content": [
+                    { "type": "text", "text": "Weather?" },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBOR" } }
+                ]},
+                { "role": "assistant", "content": [
+                    { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny" }
+                ]}
+            ],
+            "tools": [{ "name": "get_weather", "input_schema": {
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "type": "object",
+                "properties": { "city": { "type": "string" } },
+                "additionalProperties": false
+            }}],
+            "tool_choice": { "type": "tool", "name": "get_weather" }
+        });
+
+        let (model, stream, gemini) = messages_request_to_gemini(&request).unwrap();
+        assert_eq!(model, "gemini-1.5-pro");
+        assert!(!stream);
+        assert_eq!(gemini["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(gemini["generationConfig"], json!({ "maxOutputTokens": 100, "stopSequences": ["END"] }));
+        assert_eq!(gemini["contents"][0]["parts"][1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(gemini["contents"][1]["role"], "model");
+        assert_eq!(gemini["contents"][1]["parts"][0]["functionCall"]["args"]["city"], "Paris");
+        assert_eq!(
+            gemini["contents"][2]["parts"][0]["functionResponse"],
+            json!({ "name": "get_weather", "response": { "content": "Sunny" } })
+        );
+        assert_eq!(
+            gemini["tools"][0]["functionDeclarations"][0]["parameters"],
+            json!({ "type": "object", "properties": { "city": { "type": "string" } } })
+        );
+        assert_eq!(gemini["toolConfig"]["functionCallingConfig"]["allowedFunctionNames"], json!(["get_weather"]));
+
+        let orphan = json!({
+            "model": "m",
+            "messages": [{ "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "x", "content": "" }] }]
+        });
+        assert!(messages_request_to_gemini(&orphan).is_err());
+    }
+
+    #[test]
+    fn test_gemini_response_to_messages() {
+        let response = json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "text": "Checking." },
+                    { "functionCall": { "name": "get_weather", "args": { "city": "Paris" } } }
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": { "promptTokenCount": 40, "candidatesTokenCount": 12, "cachedContentTokenCount": 10 },
+            "modelVersion": "gemini-1.5-pro-002",
+            "responseId": "resp_1"
+        });
+
+        let message = gemini_response_to_messages(&response);
+        assert_eq!(message["id"], "resp_1");
+        assert_eq!(message["model"], "gemini-1.5-pro-002");
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["content"][1]["name"], "get_weather");
+        assert_eq!(message["usage"], json!({ "input_tokens": 30, "output_tokens": 12, "cache_read_input_tokens": 10 }));
+
+        let error = json!({ "error": { "code": 429, "message": "quota", "status": "RESOURCE_EXHAUSTED" } });
+        assert_eq!(gemini_error_to_anthropic(&error)["error"]["type"], "rate_limit_error");
+    }
+
+    #[test]
+    fn test_gemini_stream_to_anthropic_events() {
+        let mut transcoder = GeminiToAnthropic::new();
+        let mut events = Vec::new();
+        for chunk in [
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Check"}]}}],"modelVersion":"gemini-1.5-flash","responseId":"r1"}"#,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"ing."}]}}],"modelVersion":"gemini-1.5-flash","responseId":"r1"}"#,
+            r#"{"candidates":[{"content":{"role":"model","parts":[{"functionCall":{"name":"get_weather","args":{"city":"Paris"}}}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":20,"candidatesTokenCount":8},"modelVersion":"gemini-1.5-flash","responseId":"r1"}"#,
+        ] {
+            events.extend(transcoder.event(SseEvent::new(None, chunk)));
+        }
+        events.extend(transcoder.finish());
+
+        let message = crate::sse::assemble(&events).unwrap();
+        assert_eq!(message["id"], "r1");
+        assert_eq!(message["content"][0]["text"], "Checking.");
+        assert_eq!(message["content"][1]["input"], json!({ "city": "Paris" }));
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["usage"]["output_tokens"], 8);
+    }
+}
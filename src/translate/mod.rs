@@ -0,0 +1,592 @@
+//! Protocol translation for endpoints whose clients speak a different API
+//! than the upstream behind them.
+
+pub mod azure;
+pub mod bedrock;
+pub mod gemini;
+pub mod ollama;
+pub mod openai;
+pub mod stream;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{
+        header::{ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    response::Response,
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::debug;
+
+use crate::config::{ApiType, EndpointConfig, GeminiConfig};
+use crate::sse::SseParser;
+use azure::AzureTarget;
+use bedrock::EventStreamDecoder;
+use ollama::{NdjsonDecoder, OllamaToAnthropic};
+use gemini::{GeminiTarget, GeminiToAnthropic};
+use stream::{AnthropicToOpenAi, Chain, EventDecoder, OpenAiToAnthropic, Passthrough, StreamTranscoder};
+
+/// Sent upstream when an OpenAI client does not pick an Anthropic API version.
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Translated responses are buffered; anything larger is refused.
+const MAX_TRANSLATED_BODY: usize = 64 * 1024 * 1024;
+
+/// Client-chosen names put into upstream paths keep their dots and dashes;
+/// `/`, `?`, `#`, `:` and anything else that could leave the path segment
+/// are escaped.
+pub(crate) const PATH_SEGMENT_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// Translates between a client protocol and an upstream protocol, using the
+/// Anthropic Messages shape as the common form in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translator {
+    client: ApiType,
+    upstream: ApiType,
+    gemini: GeminiTarget,
+    azure: AzureTarget,
+    /// Client model name → upstream model name, for local servers.
+    models: HashMap<String, String>,
+    default_model: Option<String>,
+}
+
+impl Translator {
+    /// `None` when clients and upstream speak the same protocol.
+    pub fn for_endpoint(endpoint: &EndpointConfig) -> Option<Self> {
+        let upstream = endpoint.api_type.unwrap_or_default();
+        let mut translator = Self::new(client_api(endpoint), upstream)?;
+        if let Some(GeminiConfig { project: Some(project), location: Some(location), .. }) = &endpoint.gemini {
+            translator.gemini = GeminiTarget::Vertex {
+                project: project.clone(),
+                location: location.clone(),
+            };
+        }
+        if let Some(azure) = &endpoint.azure {
+            translator.azure = AzureTarget::from_config(azure);
+        }
+        if let Some(local) = &endpoint.local {
+            translator.models = local.models.clone();
+            translator.default_model = local.default_model.clone();
+        }
+        Some(translator)
+    }
+
+    pub fn new(client: ApiType, upstream: ApiType) -> Option<Self> {
+        (client != upstream).then_some(Self {
+            client,
+            upstream,
+            gemini: GeminiTarget::Gemini,
+            azure: AzureTarget::default(),
+            models: HashMap::new(),
+            default_model: None,
+        })
+    }
+
+    /// Whether a client request is translated; anything else is passed
+    /// through untouched.
+    pub fn translates(&self, method: &Method, path: &str) -> bool {
+        method == Method::POST
+            && match self.client {
+                ApiType::Anthropic => path == "/v1/messages",
+                ApiType::OpenAI => path == "/v1/chat/completions",
+                ApiType::Gemini | ApiType::Bedrock | ApiType::Azure | ApiType::Ollama | ApiType::LlamaCpp => false,
+            }
+    }
+
+    /// Translates a request body, returning the upstream path (which may
+    /// carry a query) alongside it.
+    pub fn request(&self, body: &Value) -> Result<(String, Value), String> {
+        if self.same_body_format() {
+            let mut body = body.clone();
+            self.map_model(&mut body);
+            return Ok((self.chat_path(&body)?, body));
+        }
+        let messages = match self.client {
+            ApiType::OpenAI => openai::chat_request_to_messages(body)?,
+            _ => body.clone(),
+        };
+
+        match self.upstream {
+            ApiType::Anthropic => Ok(("/v1/messages".to_string(), messages)),
+            ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp => {
+                let mut request = openai::messages_request_to_chat(&messages)?;
+                self.map_model(&mut request);
+                // Anthropic streams always report usage; OpenAI only does when asked
+                let streaming = request.get("stream").and_then(Value::as_bool).unwrap_or(false);
+                if streaming {
+                    request["stream_options"] = serde_json::json!({ "include_usage": true });
+                }
+                Ok((self.chat_path(&request)?, request))
+            }
+            ApiType::Gemini => {
+                let (model, streaming, request) = gemini::messages_request_to_gemini(&messages)?;
+                Ok((self.gemini.path(&model, streaming), request))
+            }
+            ApiType::Bedrock => {
+                let (model, streaming, request) = bedrock::messages_request_to_bedrock(&messages)?;
+                Ok((bedrock::path(&model, streaming), request))
+            }
+            ApiType::Ollama => {
+                let mut chat = openai::messages_request_to_chat(&messages)?;
+                self.map_model(&mut chat);
+                Ok((ollama::CHAT_PATH.to_string(), ollama::chat_request_to_ollama(&chat)?))
+            }
+        }
+    }
+
+    fn map_model(&self, request: &mut Value) {
+        let Some(model) = request.get("model").and_then(Value::as_str) else {
+            return;
+        };
+        let mapped = self.models.get(model).or(self.default_model.as_ref());
+        if let Some(mapped) = mapped {
+            request["model"] = Value::String(mapped.clone());
+        }
+    }
+
+    /// OpenAI clients share a body format with Azure and local OpenAI-compatible
+    /// servers; only the path, credentials and model name change.
+    fn same_body_format(&self) -> bool {
+        self.client == ApiType::OpenAI && matches!(self.upstream, ApiType::Azure | ApiType::LlamaCpp)
+    }
+
+    fn chat_path(&self, request: &Value) -> Result<String, String> {
+        if self.upstream != ApiType::Azure {
+            return Ok("/v1/chat/completions".to_string());
+        }
+        let model = request.get("model").and_then(Value::as_str).ok_or("'model' is required")?;
+        Ok(self.azure.chat_path(model))
+    }
+
+    /// Adapts credentials and version headers to what the upstream expects.
+    pub fn upstream_headers(&self, headers: &mut HeaderMap) {
+        // The body is rewritten, and compressed responses could not be translated
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(ACCEPT_ENCODING);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        match self.upstream {
+            ApiType::Anthropic => Self::anthropic_headers(headers),
+            ApiType::OpenAI | ApiType::LlamaCpp | ApiType::Ollama => Self::openai_headers(headers),
+            ApiType::Gemini => Self::gemini_headers(headers),
+            ApiType::Azure => Self::azure_headers(headers),
+            // Requests are signed with the endpoint's AWS credentials instead
+            ApiType::Bedrock => {
+                headers.remove("x-api-key");
+                headers.remove(AUTHORIZATION);
+                remove_anthropic_headers(headers);
+            }
+        }
+    }
+
+    fn anthropic_headers(headers: &mut HeaderMap) {
+        if !headers.contains_key("x-api-key") {
+            if let Some(key) = bearer_token(headers).and_then(|key| HeaderValue::from_str(&key).ok()) {
+                headers.insert("x-api-key", key);
+                headers.remove(AUTHORIZATION);
+            }
+        }
+        if !headers.contains_key("anthropic-version") {
+            headers.insert("anthropic-version", HeaderValue::from_static(DEFAULT_ANTHROPIC_VERSION));
+        }
+    }
+
+    fn openai_headers(headers: &mut HeaderMap) {
+        if !headers.contains_key(AUTHORIZATION) {
+            let bearer = headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|key| HeaderValue::from_str(&format!("Bearer {}", key.trim())).ok());
+            if let Some(bearer) = bearer {
+                headers.insert(AUTHORIZATION, bearer);
+            }
+        }
+        headers.remove("x-api-key");
+        remove_anthropic_headers(headers);
+    }
+
+    fn gemini_headers(headers: &mut HeaderMap) {
+        if !headers.contains_key("x-goog-api-key") {
+            let key = headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                .map(|key| key.trim().to_string())
+                .or_else(|| bearer_token(headers))
+                .and_then(|key| HeaderValue::from_str(&key).ok());
+            if let Some(key) = key {
+                headers.insert("x-goog-api-key", key);
+            }
+        }
+        headers.remove("x-api-key");
+        headers.remove(AUTHORIZATION);
+        remove_anthropic_headers(headers);
+    }
+
+    fn azure_headers(headers: &mut HeaderMap) {
+        if !headers.contains_key("api-key") {
+            let key = headers
+                .get("x-api-key")
+                .and_then(|value| value.to_str().ok())
+                .map(|key| key.trim().to_string())
+                .or_else(|| bearer_token(headers))
+                .and_then(|key| HeaderValue::from_str(&key).ok());
+            if let Some(key) = key {
+                headers.insert("api-key", key);
+            }
+        }
+        headers.remove("x-api-key");
+        headers.remove(AUTHORIZATION);
+        remove_anthropic_headers(headers);
+    }
+
+    /// Converts an upstream response back into the client's protocol. Event
+    /// streams are transcoded as they arrive; other bodies are buffered.
+    /// `request` is the client's own body, for options that shape the reply.
+    pub async fn response(&self, mut response: Response, request: &Value) -> Response {
+        if self.same_body_format() {
+            return response;
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let event_stream = content_type.starts_with("text/event-stream");
+        let decoder: Option<Box<dyn EventDecoder>> = match self.upstream {
+            _ if event_stream => Some(Box::new(SseParser::new())),
+            ApiType::Bedrock if content_type.starts_with(bedrock::EVENT_STREAM_CONTENT_TYPE) => {
+                Some(Box::new(EventStreamDecoder::new()))
+            }
+            ApiType::Ollama if content_type.starts_with(ollama::NDJSON_CONTENT_TYPE) => {
+                Some(Box::new(NdjsonDecoder::new()))
+            }
+            _ => None,
+        };
+        if let Some(decoder) = decoder.filter(|_| response.status().is_success()) {
+            let to_anthropic: Option<Box<dyn StreamTranscoder>> = match self.upstream {
+                ApiType::Anthropic | ApiType::Bedrock => None,
+                ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp => Some(Box::new(OpenAiToAnthropic::new())),
+                ApiType::Gemini => Some(Box::new(GeminiToAnthropic::new())),
+                ApiType::Ollama => Some(Box::new(OllamaToAnthropic::new())),
+            };
+            let to_client: Option<Box<dyn StreamTranscoder>> = match self.client {
+                ApiType::OpenAI => {
+                    let include_usage = request.pointer("/stream_options/include_usage").and_then(Value::as_bool).unwrap_or(false);
+                    Some(Box::new(AnthropicToOpenAi::new(openai::unix_time(), include_usage)))
+                }
+                _ => None,
+            };
+            let transcoder: Box<dyn StreamTranscoder> = match (to_anthropic, to_client) {
+                (Some(first), Some(second)) => Box::new(Chain(first, second)),
+                (Some(transcoder), None) | (None, Some(transcoder)) => transcoder,
+                (None, None) if !event_stream => Box::new(Passthrough),
+                (None, None) => return response,
+            };
+            // Binary and NDJSON streams reach the client as SSE
+            response.headers_mut().remove(CONTENT_LENGTH);
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+            return stream::decode(response, decoder, transcoder);
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let body = match to_bytes(body, MAX_TRANSLATED_BODY).await {
+            Ok(body) => body,
+            Err(e) => {
+                return self.error_response(StatusCode::BAD_GATEWAY, &format!("failed to read upstream response: {}", e));
+            }
+        };
+        let Ok(upstream) = serde_json::from_slice::<Value>(&body) else {
+            debug!("Upstream response is not JSON; passing it through");
+            return Response::from_parts(parts, Body::from(body));
+        };
+
+        let success = parts.status.is_success();
+        let messages = match (self.upstream, success) {
+            (ApiType::Anthropic, _) => upstream,
+            (ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp, true) => openai::chat_response_to_messages(&upstream),
+            (ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp, false) => openai::openai_error_to_anthropic(&upstream),
+            (ApiType::Gemini, true) => gemini::gemini_response_to_messages(&upstream),
+            (ApiType::Gemini, false) => gemini::gemini_error_to_anthropic(&upstream),
+            (ApiType::Bedrock, true) => upstream,
+            (ApiType::Bedrock, false) => bedrock::bedrock_error_to_anthropic(parts.status, &upstream),
+            (ApiType::Ollama, true) => ollama::ollama_response_to_messages(&upstream),
+            (ApiType::Ollama, false) => ollama::ollama_error_to_anthropic(parts.status, &upstream),
+        };
+        let translated = match (self.client, success) {
+            (ApiType::OpenAI, true) => openai::messages_response_to_chat(&messages),
+            (ApiType::OpenAI, false) => openai::anthropic_error_to_openai(&messages),
+            _ => messages,
+        };
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts.headers.remove(CONTENT_ENCODING);
+        parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        Response::from_parts(parts, Body::from(translated.to_string()))
+    }
+
+    /// An error in the client's protocol, for requests that cannot be translated.
+    pub fn error_response(&self, status: StatusCode, message: &str) -> Response {
+        error_response(self.client, status, message)
+    }
+}
+
+/// The protocol clients speak to an endpoint.
+pub fn client_api(endpoint: &EndpointConfig) -> ApiType {
+    // Azure's, Ollama's and Bedrock's own paths are not routed, so their
+    // clients default to OpenAI's, or Anthropic's for Claude on Bedrock;
+    // local OpenAI servers still get model mapping
+    endpoint.client_api.unwrap_or(match endpoint.api_type.unwrap_or_default() {
+        ApiType::Azure | ApiType::Ollama | ApiType::LlamaCpp => ApiType::OpenAI,
+        ApiType::Bedrock => ApiType::Anthropic,
+        upstream => upstream,
+    })
+}
+
+/// An error body in `client`'s protocol, for requests the proxy answers itself.
+pub fn error_response(client: ApiType, status: StatusCode, message: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(error_body(client, status, message).to_string()))
+        .unwrap()
+}
+
+/// The JSON of an [`error_response`], also sent as an event to cut a stream short.
+pub fn error_body(client: ApiType, status: StatusCode, message: &str) -> Value {
+    let kind = match status {
+        StatusCode::FORBIDDEN => "permission_error",
+        status if status.is_client_error() => "invalid_request_error",
+        _ => "api_error",
+    };
+    match client {
+        ApiType::OpenAI | ApiType::Azure | ApiType::LlamaCpp => openai::openai_error(kind, message),
+        ApiType::Ollama => serde_json::json!({ "error": message }),
+        ApiType::Anthropic | ApiType::Bedrock => serde_json::json!({
+            "type": "error",
+            "error": { "type": kind, "message": message },
+        }),
+        ApiType::Gemini => serde_json::json!({
+            "error": {
+                "code": status.as_u16(),
+                "message": message,
+                "status": match status {
+                    StatusCode::FORBIDDEN => "PERMISSION_DENIED",
+                    status if status.is_client_error() => "INVALID_ARGUMENT",
+                    _ => "INTERNAL",
+                },
+            },
+        }),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|key| key.trim().to_string())
+}
+
+fn remove_anthropic_headers(headers: &mut HeaderMap) {
+    headers.remove("anthropic-version");
+    headers.remove("anthropic-beta");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn openai_to_anthropic() -> Translator {
+        Translator::new(ApiType::OpenAI, ApiType::Anthropic).unwrap()
+    }
+
+    #[test]
+    fn test_translator_only_for_differing_protocols() {
+        let mut endpoint = EndpointConfig::default();
+        assert!(Translator::for_endpoint(&endpoint).is_none());
+
+        endpoint.client_api = Some(ApiType::OpenAI);
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        assert!(translator.translates(&Method::POST, "/v1/chat/completions"));
+        assert!(!translator.translates(&Method::GET, "/v1/chat/completions"));
+        assert!(!translator.translates(&Method::POST, "/v1/messages"));
+    }
+
+    #[test]
+    fn test_bedrock_clients_default_to_anthropic() {
+        let endpoint = EndpointConfig {
+            api_type: Some(ApiType::Bedrock),
+            ..Default::default()
+        };
+        assert_eq!(client_api(&endpoint), ApiType::Anthropic);
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        assert!(translator.translates(&Method::POST, "/v1/messages"));
+    }
+
+    #[test]
+    fn test_credentials_follow_upstream_protocol() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer sk-ant-123"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        openai_to_anthropic().upstream_headers(&mut headers);
+
+        assert_eq!(headers["x-api-key"], "sk-ant-123");
+        assert_eq!(headers["anthropic-version"], DEFAULT_ANTHROPIC_VERSION);
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert!(!headers.contains_key(CONTENT_LENGTH));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-openai"));
+        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        let translator = Translator::new(ApiType::Anthropic, ApiType::OpenAI).unwrap();
+        translator.upstream_headers(&mut headers);
+
+        assert_eq!(headers[AUTHORIZATION], "Bearer sk-openai");
+        assert!(!headers.contains_key("x-api-key"));
+        assert!(!headers.contains_key("anthropic-version"));
+    }
+
+    #[test]
+    fn test_openai_client_to_vertex_gemini() {
+        let endpoint = EndpointConfig {
+            api_type: Some(ApiType::Gemini),
+            client_api: Some(ApiType::OpenAI),
+            gemini: Some(GeminiConfig {
+                project: Some("acme".to_string()),
+                location: Some("europe-west4".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        let body = serde_json::json!({
+            "model": "gemini-1.5-pro",
+            "stream": true,
+            "messages": [{ "role": "system", "content": "Be brief." }, { "role": "user", "content": "Hi" }]
+        });
+
+        let (path, request) = translator.request(&body).unwrap();
+        assert_eq!(
+            path,
+            "/v1/projects/acme/locations/europe-west4/publishers/google/models/gemini-1.5-pro:streamGenerateContent?alt=sse"
+        );
+        assert_eq!(request["systemInstruction"]["parts"][0]["text"], "Be brief.");
+        assert_eq!(request["contents"][0]["parts"][0]["text"], "Hi");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer AIza-key"));
+        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        translator.upstream_headers(&mut headers);
+        assert_eq!(headers["x-goog-api-key"], "AIza-key");
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert!(!headers.contains_key("anthropic-version"));
+    }
+
+    #[test]
+    fn test_azure_deployment_path_and_api_key() {
+        let endpoint = EndpointConfig {
+            api_type: Some(ApiType::Azure),
+            client_api: Some(ApiType::Anthropic),
+            azure: Some(crate::config::AzureConfig {
+                api_version: Some("2024-06-01".to_string()),
+                deployments: [("gpt-4o".to_string(), "chat-prod".to_string())].into(),
+            }),
+            ..Default::default()
+        };
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "max_tokens": 32,
+            "stream": true,
+            "messages": [{ "role": "user", "content": "Hi" }]
+        });
+
+        let (path, request) = translator.request(&body).unwrap();
+        assert_eq!(path, "/openai/deployments/chat-prod/chat/completions?api-version=2024-06-01");
+        assert_eq!(request["messages"][0]["content"], "Hi");
+        assert_eq!(request["stream_options"]["include_usage"], true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("azure-key"));
+        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        translator.upstream_headers(&mut headers);
+        assert_eq!(headers["api-key"], "azure-key");
+        assert!(!headers.contains_key("x-api-key"));
+        assert!(!headers.contains_key("anthropic-version"));
+    }
+
+    #[test]
+    fn test_anthropic_client_to_ollama_maps_model() {
+        let endpoint = EndpointConfig {
+            api_type: Some(ApiType::Ollama),
+            client_api: Some(ApiType::Anthropic),
+            local: Some(crate::config::LocalModelConfig {
+                models: [("claude-3-5-sonnet-latest".to_string(), "llama3.1:8b".to_string())].into(),
+                default_model: Some("qwen2.5:7b".to_string()),
+            }),
+            ..Default::default()
+        };
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        let body = serde_json::json!({
+            "model": "claude-3-5-sonnet-latest",
+            "max_tokens": 64,
+            "messages": [{ "role": "user", "content": "Hi" }]
+        });
+
+        let (path, request) = translator.request(&body).unwrap();
+        assert_eq!(path, "/api/chat");
+        assert_eq!(request["model"], "llama3.1:8b");
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["options"]["num_predict"], 64);
+
+        let (_, request) = translator.request(&serde_json::json!({ "model": "claude-3-haiku", "max_tokens": 8, "messages": [] })).unwrap();
+        assert_eq!(request["model"], "qwen2.5:7b");
+    }
+
+    #[test]
+    fn test_openai_client_to_llamacpp_keeps_body() {
+        let endpoint = EndpointConfig {
+            api_type: Some(ApiType::LlamaCpp),
+            local: Some(crate::config::LocalModelConfig {
+                models: [("gpt-4o".to_string(), "Qwen/Qwen2.5-7B-Instruct".to_string())].into(),
+                default_model: None,
+            }),
+            ..Default::default()
+        };
+        let translator = Translator::for_endpoint(&endpoint).unwrap();
+        assert!(translator.translates(&Method::POST, "/v1/chat/completions"));
+
+        let body = serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hi" }],
+            "logit_bias": { "50256": -100 }
+        });
+        let (path, request) = translator.request(&body).unwrap();
+        assert_eq!(path, "/v1/chat/completions");
+        assert_eq!(request["model"], "Qwen/Qwen2.5-7B-Instruct");
+        assert_eq!(request["logit_bias"], body["logit_bias"]);
+
+        let (_, request) = translator.request(&serde_json::json!({ "model": "other", "messages": [] })).unwrap();
+        assert_eq!(request["model"], "other");
+    }
+
+    #[tokio::test]
+    async fn test_error_response_translated() {
+        let upstream = Response::builder()
+            .status(429)
+            .header(CONTENT_LENGTH, "80")
+            .body(Body::from(r#"{"type":"error","error":{"type":"rate_limit_error","message":"slow down"}}"#))
+            .unwrap();
+
+        let response = openai_to_anthropic().response(upstream, &Value::Null).await;
+        assert_eq!(response.status(), 429);
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "rate_limit_error");
+        assert_eq!(body["error"]["message"], "slow down");
+    }
+}
@@ -0,0 +1,370 @@
+//! Ollama's native `/api/chat`. Requests are built from the OpenAI chat shape,
+//! which Ollama's messages follow closely; replies come back as Anthropic
+//! messages. Streams are newline-delimited JSON rather than SSE.
+
+use axum::http::StatusCode;
+use serde_json::{json, Map, Value};
+
+use super::stream::{EventDecoder, MessageEvents, StreamTranscoder};
+use crate::sse::SseEvent;
+
+pub const DEFAULT_OLLAMA_BASE: &str = "http://localhost:11434";
+/// llama.cpp's `llama-server` default; vLLM listens on 8000.
+pub const DEFAULT_LLAMACPP_BASE: &str = "http://localhost:8080";
+pub const CHAT_PATH: &str = "/api/chat";
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Converts an OpenAI chat request into an Ollama `/api/chat` request.
+pub fn chat_request_to_ollama(chat: &Value) -> Result<Value, String> {
+    let chat = chat.as_object().ok_or("request body must be a JSON object")?;
+
+    let mut messages = Vec::new();
+    for message in chat.get("messages").and_then(Value::as_array).into_iter().flatten() {
+        let mut converted = Map::new();
+        converted.insert("role".to_string(), message["role"].clone());
+
+        let mut images = Vec::new();
+        let content = match &message["content"] {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    match part["type"].as_str() {
+                        Some("text") => text.push_str(part["text"].as_str().unwrap_or("")),
+                        Some("image_url") => images.push(inline_image(&part["image_url"]["url"])?),
+                        _ => {}
+                    }
+                }
+                text
+            }
+            _ => String::new(),
+        };
+        converted.insert("content".to_string(), json!(content));
+        if !images.is_empty() {
+            converted.insert("images".to_string(), Value::Array(images));
+        }
+
+        // Ollama takes arguments as an object rather than a JSON string
+        if let Some(calls) = message["tool_calls"].as_array() {
+            let calls: Vec<Value> = calls
+                .iter()
+                .map(|call| {
+                    let arguments = call["function"]["arguments"]
+                        .as_str()
+                        .and_then(|arguments| serde_json::from_str(arguments).ok())
+                        .unwrap_or_else(|| json!({}));
+                    json!({ "function": { "name": call["function"]["name"], "arguments": arguments } })
+                })
+                .collect();
+            converted.insert("tool_calls".to_string(), Value::Array(calls));
+        }
+        messages.push(Value::Object(converted));
+    }
+
+    let mut options = Map::new();
+    for (from, to) in [
+        ("max_tokens", "num_predict"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop", "stop"),
+    ] {
+        if let Some(value) = chat.get(from).filter(|value| !value.is_null()) {
+            options.insert(to.to_string(), value.clone());
+        }
+    }
+
+    let mut request = Map::new();
+    request.insert("model".to_string(), chat.get("model").cloned().unwrap_or(Value::Null));
+    request.insert("messages".to_string(), Value::Array(messages));
+    // Ollama streams unless told otherwise
+    let stream = chat.get("stream").and_then(Value::as_bool).unwrap_or(false);
+    request.insert("stream".to_string(), json!(stream));
+    if !options.is_empty() {
+        request.insert("options".to_string(), Value::Object(options));
+    }
+    if let Some(tools) = chat.get("tools") {
+        request.insert("tools".to_string(), tools.clone());
+    }
+    Ok(Value::Object(request))
+}
+
+fn inline_image(url: &Value) -> Result<Value, String> {
+    url.as_str()
+        .filter(|url| url.starts_with("data:"))
+        .and_then(|url| url.split_once(";base64,"))
+        .map(|(_, data)| json!(data))
+        .ok_or_else(|| "Ollama only accepts base64-encoded images".to_string())
+}
+
+/// Converts a finished `/api/chat` reply into a `/v1/messages` response.
+pub fn ollama_response_to_messages(response: &Value) -> Value {
+    let message = &response["message"];
+    let mut content = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|text| !text.is_empty()) {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for (index, call) in message["tool_calls"].as_array().into_iter().flatten().enumerate() {
+        content.push(json!({
+            "type": "tool_use",
+            "id": tool_use_id(index),
+            "name": call["function"]["name"],
+            "input": arguments(&call["function"]["arguments"]),
+        }));
+    }
+
+    let called_tool = content.iter().any(|block| block["type"] == "tool_use");
+    json!({
+        "id": "msg_ollama",
+        "type": "message",
+        "role": "assistant",
+        "model": response["model"],
+        "content": content,
+        "stop_reason": stop_reason(response["done_reason"].as_str(), called_tool),
+        "stop_sequence": null,
+        "usage": messages_usage(response),
+    })
+}
+
+/// Ollama errors are `{"error": "..."}`; the kind comes from the status.
+pub fn ollama_error_to_anthropic(status: StatusCode, error: &Value) -> Value {
+    let kind = match status.as_u16() {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        429 => "rate_limit_error",
+        503 => "overloaded_error",
+        _ => "api_error",
+    };
+    let message = error["error"].as_str().unwrap_or("upstream error");
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+fn tool_use_id(index: usize) -> String {
+    format!("toolu_ollama_{}", index)
+}
+
+fn arguments(arguments: &Value) -> Value {
+    match arguments {
+        Value::Object(_) => arguments.clone(),
+        Value::String(text) => serde_json::from_str(text).unwrap_or_else(|_| json!({})),
+        _ => json!({}),
+    }
+}
+
+fn stop_reason(done_reason: Option<&str>, called_tool: bool) -> Value {
+    match done_reason {
+        None => Value::Null,
+        Some(_) if called_tool => "tool_use".into(),
+        Some("length") => "max_tokens".into(),
+        Some(_) => "end_turn".into(),
+    }
+}
+
+fn messages_usage(response: &Value) -> Value {
+    json!({
+        "input_tokens": response["prompt_eval_count"].as_u64().unwrap_or(0),
+        "output_tokens": response["eval_count"].as_u64().unwrap_or(0),
+    })
+}
+
+/// Splits an `application/x-ndjson` body into one event per line.
+#[derive(Default)]
+pub struct NdjsonDecoder {
+    buffer: Vec<u8>,
+}
+
+impl NdjsonDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn line(line: &[u8]) -> Option<SseEvent> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+        (!line.is_empty()).then(|| SseEvent::new(None, line))
+    }
+}
+
+impl EventDecoder for NdjsonDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            events.extend(Self::line(&line));
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let line = std::mem::take(&mut self.buffer);
+        Self::line(&line).into_iter().collect()
+    }
+}
+
+/// Ollama stream lines to Anthropic message events.
+#[derive(Default)]
+pub struct OllamaToAnthropic {
+    message: MessageEvents,
+    tool_calls: usize,
+    done_reason: Option<String>,
+    usage: Value,
+}
+
+impl OllamaToAnthropic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamTranscoder for OllamaToAnthropic {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if self.message.is_finished() {
+            return Vec::new();
+        }
+        let Some(chunk) = event.json_data() else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+        if chunk.get("error").is_some() {
+            let error = ollama_error_to_anthropic(StatusCode::INTERNAL_SERVER_ERROR, &chunk);
+            self.message.error(error, &mut events);
+            return events;
+        }
+
+        self.message.start(&json!("msg_ollama"), &chunk["model"], &mut events);
+        let message = &chunk["message"];
+        if let Some(text) = message["content"].as_str().filter(|text| !text.is_empty()) {
+            self.message.text(text, &mut events);
+        }
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            let index = self.tool_calls;
+            self.tool_calls += 1;
+            let id = json!(tool_use_id(index));
+            self.message.tool_call(&index.to_string(), &id, &call["function"]["name"], &mut events);
+            self.message.tool_arguments(&arguments(&call["function"]["arguments"]).to_string(), &mut events);
+        }
+        if chunk["done"] == Value::Bool(true) {
+            self.done_reason = Some(chunk["done_reason"].as_str().unwrap_or("stop").to_string());
+            self.usage = messages_usage(&chunk);
+        }
+        events
+    }
+
+    /// A stream without its `done` line was cut off and ends with an error.
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        let Some(reason) = self.done_reason.as_deref() else {
+            self.message.truncated(&mut events);
+            return events;
+        };
+        let stop_reason = stop_reason(Some(reason), self.tool_calls > 0);
+        let usage = match &self.usage {
+            Value::Null => json!({ "output_tokens": 0 }),
+            usage => usage.clone(),
+        };
+        self.message.finish(stop_reason, usage, &mut events);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request_to_ollama() {
+        let chat = json!({
+            "model": "llama3.1",
+            "max_tokens": 128,
+            "stop": ["END"],
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "What is this?" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBOR" } }
+                ]},
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "lookup", "arguments": "{\"q\":\"png\"}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "an image format" }
+            ]
+        });
+
+        let request = chat_request_to_ollama(&chat).unwrap();
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["options"], json!({ "num_predict": 128, "stop": ["END"] }));
+        assert_eq!(request["messages"][1]["content"], "What is this?");
+        assert_eq!(request["messages"][1]["images"], json!(["iVBOR"]));
+        assert_eq!(request["messages"][2]["tool_calls"][0]["function"]["arguments"], json!({ "q": "png" }));
+        assert_eq!(request["messages"][3], json!({ "role": "tool", "content": "an image format" }));
+
+        let linked = json!({ "model": "m", "messages": [{ "role": "user", "content": [
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+        ]}]});
+        assert!(chat_request_to_ollama(&linked).is_err());
+    }
+
+    #[test]
+    fn test_ollama_response_to_messages() {
+        let response = json!({
+            "model": "llama3.1",
+            "message": { "role": "assistant", "content": "", "tool_calls": [
+                { "function": { "name": "get_weather", "arguments": { "city": "Paris" } } }
+            ]},
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 26,
+            "eval_count": 9
+        });
+
+        let message = ollama_response_to_messages(&response);
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["content"][0]["input"]["city"], "Paris");
+        assert_eq!(message["usage"], json!({ "input_tokens": 26, "output_tokens": 9 }));
+        assert_eq!(
+            ollama_error_to_anthropic(StatusCode::NOT_FOUND, &json!({ "error": "model 'x' not found" }))["error"]["type"],
+            "not_found_error"
+        );
+    }
+
+    #[test]
+    fn test_ndjson_stream_to_anthropic_events() {
+        let body = concat!(
+            r#"{"model":"llama3.1","message":{"role":"assistant","content":"Hel"},"done":false}"#, "\n",
+            r#"{"model":"llama3.1","message":{"role":"assistant","content":"lo"},"done":false}"#, "\n",
+            r#"{"model":"llama3.1","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","prompt_eval_count":5,"eval_count":2}"#,
+        );
+
+        for chunk_size in [body.len(), 7, 1] {
+            let mut decoder = NdjsonDecoder::new();
+            let mut transcoder = OllamaToAnthropic::new();
+            let mut events = Vec::new();
+            for chunk in body.as_bytes().chunks(chunk_size) {
+                for line in decoder.push(chunk) {
+                    events.extend(transcoder.event(line));
+                }
+            }
+            for line in decoder.finish() {
+                events.extend(transcoder.event(line));
+            }
+            events.extend(transcoder.finish());
+
+            let message = crate::sse::assemble(&events).unwrap();
+            assert_eq!(message["content"][0]["text"], "Hello", "chunk size {}", chunk_size);
+            assert_eq!(message["stop_reason"], "max_tokens");
+            assert_eq!(message["usage"]["output_tokens"], 2);
+        }
+
+        // Without the `done` line the stream was cut off
+        let mut transcoder = OllamaToAnthropic::new();
+        let line = r#"{"model":"llama3.1","message":{"role":"assistant","content":"Hel"},"done":false}"#;
+        transcoder.event(SseEvent::new(None, line));
+        let events = transcoder.finish();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].json_data().unwrap()["type"], "error");
+    }
+}
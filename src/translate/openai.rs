@@ -0,0 +1,679 @@
+//! Conversions between OpenAI Chat Completions and Anthropic Messages bodies.
+
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Anthropic requires `max_tokens`; OpenAI clients often leave it out.
+const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// Converts a `/v1/chat/completions` request into a `/v1/messages` request.
+pub fn chat_request_to_messages(chat: &Value) -> Result<Value, String> {
+    let chat = chat
+        .as_object()
+        .ok_or("request body must be a JSON object")?;
+
+    if chat.get("n").and_then(Value::as_u64).unwrap_or(1) > 1 {
+        return Err("n > 1 is not supported by this endpoint".to_string());
+    }
+
+    let mut system = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+    for message in chat
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("'messages' must be an array")?
+    {
+        let role = message.get("role").and_then(Value::as_str).unwrap_or("");
+        let (role, blocks) = match role {
+            "system" | "developer" => {
+                system.push(text_of(message.get("content")));
+                continue;
+            }
+            "user" => ("user", user_blocks(message.get("content"))?),
+            "assistant" => ("assistant", assistant_blocks(message)),
+            "tool" => ("user", vec![tool_result_block(message)]),
+            other => return Err(format!("unsupported message role '{}'", other)),
+        };
+        if blocks.is_empty() {
+            continue;
+        }
+
+        // Anthropic wants alternating roles, and tool results from
+        // consecutive tool messages belong in a single user turn
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(content) = last["content"].as_array_mut() {
+                    content.extend(blocks);
+                }
+            }
+            _ => messages.push(json!({ "role": role, "content": blocks })),
+        }
+    }
+
+    let mut request = Map::new();
+    if let Some(model) = chat.get("model") {
+        request.insert("model".to_string(), model.clone());
+    }
+    if !system.is_empty() {
+        request.insert("system".to_string(), Value::String(system.join("\n\n")));
+    }
+    request.insert("messages".to_string(), Value::Array(messages));
+
+    let max_tokens = chat
+        .get("max_completion_tokens")
+        .or_else(|| chat.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+    request.insert("max_tokens".to_string(), max_tokens.into());
+
+    for field in ["temperature", "top_p", "stream"] {
+        if let Some(value) = chat.get(field).filter(|value| !value.is_null()) {
+            request.insert(field.to_string(), value.clone());
+        }
+    }
+
+    match chat.get("stop") {
+        Some(Value::String(stop)) => {
+            request.insert("stop_sequences".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) if !stops.is_empty() => {
+            request.insert("stop_sequences".to_string(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+
+    if let Some(user) = chat.get("user").and_then(Value::as_str) {
+        request.insert("metadata".to_string(), json!({ "user_id": user }));
+    }
+
+    if let Some(tools) = chat.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| tool.get("function"))
+            .map(|function| {
+                let mut tool = Map::new();
+                tool.insert("name".to_string(), function["name"].clone());
+                if let Some(description) = function.get("description") {
+                    tool.insert("description".to_string(), description.clone());
+                }
+                let schema = function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "type": "object", "properties": {} }));
+                tool.insert("input_schema".to_string(), schema);
+                Value::Object(tool)
+            })
+            .collect();
+        if !tools.is_empty() {
+            request.insert("tools".to_string(), Value::Array(tools));
+        }
+    }
+
+    let mut tool_choice = match chat.get("tool_choice") {
+        Some(Value::String(choice)) => match choice.as_str() {
+            "none" => Some(json!({ "type": "none" })),
+            "required" => Some(json!({ "type": "any" })),
+            _ => Some(json!({ "type": "auto" })),
+        },
+        Some(Value::Object(choice)) => choice
+            .get("function")
+            .and_then(|function| function.get("name"))
+            .map(|name| json!({ "type": "tool", "name": name })),
+        _ => None,
+    };
+    if chat.get("parallel_tool_calls") == Some(&Value::Bool(false)) {
+        let choice = tool_choice.get_or_insert_with(|| json!({ "type": "auto" }));
+        choice["disable_parallel_tool_use"] = Value::Bool(true);
+    }
+    if let (Some(choice), true) = (tool_choice, request.contains_key("tools")) {
+        request.insert("tool_choice".to_string(), choice);
+    }
+
+    Ok(Value::Object(request))
+}
+
+/// Converts a `/v1/messages` response into a `chat.completion` object.
+pub fn messages_response_to_chat(message: &Value) -> Value {
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    for block in message["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or("")),
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block["input"].to_string(),
+                },
+            })),
+            _ => {}
+        }
+    }
+
+    let mut reply = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { Value::String(text) },
+    });
+    if !tool_calls.is_empty() {
+        reply["tool_calls"] = Value::Array(tool_calls);
+    }
+
+    json!({
+        "id": message["id"],
+        "object": "chat.completion",
+        "created": unix_time(),
+        "model": message["model"],
+        "choices": [{
+            "index": 0,
+            "message": reply,
+            "logprobs": null,
+            "finish_reason": finish_reason(message["stop_reason"].as_str()),
+        }],
+        "usage": chat_usage(&message["usage"]),
+    })
+}
+
+/// Converts an Anthropic error body into the OpenAI error shape.
+pub fn anthropic_error_to_openai(error: &Value) -> Value {
+    let error = error.get("error").unwrap_or(error);
+    openai_error(
+        error["type"].as_str().unwrap_or("api_error"),
+        error["message"].as_str().unwrap_or("upstream error"),
+    )
+}
+
+pub fn openai_error(kind: &str, message: &str) -> Value {
+    json!({
+        "error": { "message": message, "type": kind, "param": null, "code": null }
+    })
+}
+
+/// Maps an Anthropic `stop_reason` to an OpenAI `finish_reason`.
+pub fn finish_reason(stop_reason: Option<&str>) -> Value {
+    match stop_reason {
+        None => Value::Null,
+        Some("max_tokens") => "length".into(),
+        Some("tool_use") => "tool_calls".into(),
+        Some("refusal") => "content_filter".into(),
+        Some(_) => "stop".into(),
+    }
+}
+
+/// OpenAI counts cached prompt tokens as part of `prompt_tokens`.
+pub fn chat_usage(usage: &Value) -> Value {
+    let count = |field: &str| usage[field].as_u64().unwrap_or(0);
+    let cached = count("cache_read_input_tokens");
+    let prompt = count("input_tokens") + cached + count("cache_creation_input_tokens");
+    let completion = count("output_tokens");
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+        "prompt_tokens_details": { "cached_tokens": cached },
+    })
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Converts a `/v1/messages` request into a `/v1/chat/completions` request.
+pub fn messages_request_to_chat(request: &Value) -> Result<Value, String> {
+    let request = request
+        .as_object()
+        .ok_or("request body must be a JSON object")?;
+
+    let mut messages = Vec::new();
+    match request.get("system") {
+        Some(Value::String(system)) => messages.push(json!({ "role": "system", "content": system })),
+        Some(Value::Array(blocks)) => {
+            let system = text_of(Some(&Value::Array(blocks.clone())));
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        _ => {}
+    }
+
+    for message in request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("'messages' must be an array")?
+    {
+        match message.get("role").and_then(Value::as_str) {
+            Some("user") => messages.extend(user_messages(&message["content"])?),
+            Some("assistant") => messages.push(assistant_message(&message["content"])),
+            other => return Err(format!("unsupported message role '{}'", other.unwrap_or(""))),
+        }
+    }
+
+    let mut chat = Map::new();
+    if let Some(model) = request.get("model") {
+        chat.insert("model".to_string(), model.clone());
+    }
+    chat.insert("messages".to_string(), Value::Array(messages));
+
+    for field in ["max_tokens", "temperature", "top_p", "stream"] {
+        if let Some(value) = request.get(field).filter(|value| !value.is_null()) {
+            chat.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(stop) = request.get("stop_sequences").filter(|stop| stop.is_array()) {
+        chat.insert("stop".to_string(), stop.clone());
+    }
+    if let Some(user) = request.get("metadata").and_then(|metadata| metadata.get("user_id")) {
+        chat.insert("user".to_string(), user.clone());
+    }
+
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|tool| {
+                let mut function = Map::new();
+                function.insert("name".to_string(), tool["name"].clone());
+                if let Some(description) = tool.get("description") {
+                    function.insert("description".to_string(), description.clone());
+                }
+                if let Some(schema) = tool.get("input_schema") {
+                    function.insert("parameters".to_string(), schema.clone());
+                }
+                json!({ "type": "function", "function": function })
+            })
+            .collect();
+        chat.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    if let Some(choice) = request.get("tool_choice") {
+        let tool_choice = match choice["type"].as_str() {
+            Some("any") => json!("required"),
+            Some("none") => json!("none"),
+            Some("tool") => json!({ "type": "function", "function": { "name": choice["name"] } }),
+            _ => json!("auto"),
+        };
+        chat.insert("tool_choice".to_string(), tool_choice);
+        if choice["disable_parallel_tool_use"] == Value::Bool(true) {
+            chat.insert("parallel_tool_calls".to_string(), Value::Bool(false));
+        }
+    }
+
+    Ok(Value::Object(chat))
+}
+
+/// Converts a `chat.completion` object into a `/v1/messages` response.
+pub fn chat_response_to_messages(chat: &Value) -> Value {
+    let choice = &chat["choices"][0];
+    let reply = &choice["message"];
+
+    let mut content = Vec::new();
+    let text = text_of(reply.get("content"));
+    if !text.is_empty() {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in reply["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        content.push(json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+
+    json!({
+        "id": chat["id"],
+        "type": "message",
+        "role": "assistant",
+        "model": chat["model"],
+        "content": content,
+        "stop_reason": stop_reason(choice["finish_reason"].as_str()),
+        "stop_sequence": null,
+        "usage": messages_usage(&chat["usage"]),
+    })
+}
+
+/// Converts an OpenAI error body into the Anthropic error shape.
+pub fn openai_error_to_anthropic(error: &Value) -> Value {
+    let error = error.get("error").unwrap_or(error);
+    json!({
+        "type": "error",
+        "error": {
+            "type": error["type"].as_str().unwrap_or("api_error"),
+            "message": error["message"].as_str().unwrap_or("upstream error"),
+        },
+    })
+}
+
+/// Maps an OpenAI `finish_reason` to an Anthropic `stop_reason`.
+pub fn stop_reason(finish_reason: Option<&str>) -> Value {
+    match finish_reason {
+        None => Value::Null,
+        Some("length") => "max_tokens".into(),
+        Some("tool_calls") | Some("function_call") => "tool_use".into(),
+        Some("content_filter") => "refusal".into(),
+        Some(_) => "end_turn".into(),
+    }
+}
+
+/// Anthropic reports cached prompt tokens separately from `input_tokens`.
+pub fn messages_usage(usage: &Value) -> Value {
+    let cached = usage["prompt_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0);
+    let prompt = usage["prompt_tokens"].as_u64().unwrap_or(0);
+    json!({
+        "input_tokens": prompt.saturating_sub(cached),
+        "output_tokens": usage["completion_tokens"].as_u64().unwrap_or(0),
+        "cache_read_input_tokens": cached,
+    })
+}
+
+/// Flattens string or text-part content into plain text.
+fn text_of(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn user_blocks(content: Option<&Value>) -> Result<Vec<Value>, String> {
+    let parts = match content {
+        Some(Value::String(text)) => return Ok(vec![json!({ "type": "text", "text": text })]),
+        Some(Value::Array(parts)) => parts,
+        _ => return Ok(Vec::new()),
+    };
+
+    parts
+        .iter()
+        .map(|part| match part["type"].as_str() {
+            Some("text") => Ok(json!({ "type": "text", "text": part["text"] })),
+            Some("image_url") => {
+                let url = part["image_url"]["url"]
+                    .as_str()
+                    .or_else(|| part["image_url"].as_str())
+                    .ok_or("image_url part without a url")?;
+                Ok(json!({ "type": "image", "source": image_source(url) }))
+            }
+            other => Err(format!("unsupported content part type {:?}", other.unwrap_or("?"))),
+        })
+        .collect()
+}
+
+/// `data:` URLs become base64 sources; anything else is passed by URL.
+fn image_source(url: &str) -> Value {
+    if let Some((media_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return json!({ "type": "base64", "media_type": media_type, "data": data });
+    }
+    json!({ "type": "url", "url": url })
+}
+
+fn assistant_blocks(message: &Value) -> Vec<Value> {
+    let mut blocks = Vec::new();
+    let text = text_of(message.get("content"));
+    if !text.is_empty() {
+        blocks.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        blocks.push(json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+    blocks
+}
+
+fn tool_result_block(message: &Value) -> Value {
+    json!({
+        "type": "tool_result",
+        "tool_use_id": message["tool_call_id"],
+        "content": text_of(message.get("content")),
+    })
+}
+
+/// Tool results become `tool` messages, which OpenAI wants before any other
+/// content of the same turn.
+fn user_messages(content: &Value) -> Result<Vec<Value>, String> {
+    let blocks = match content {
+        Value::String(text) => return Ok(vec![json!({ "role": "user", "content": text })]),
+        Value::Array(blocks) => blocks,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("tool_result") => messages.push(json!({
+                "role": "tool",
+                "tool_call_id": block["tool_use_id"],
+                "content": text_of(block.get("content")),
+            })),
+            Some("text") => parts.push(json!({ "type": "text", "text": block["text"] })),
+            Some("image") => {
+                let source = &block["source"];
+                let url = match source["type"].as_str() {
+                    Some("base64") => format!(
+                        "data:{};base64,{}",
+                        source["media_type"].as_str().unwrap_or("image/png"),
+                        source["data"].as_str().unwrap_or("")
+                    ),
+                    _ => source["url"].as_str().unwrap_or("").to_string(),
+                };
+                parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+            }
+            other => return Err(format!("unsupported content block type {:?}", other.unwrap_or("?"))),
+        }
+    }
+
+    if !parts.is_empty() {
+        messages.push(json!({ "role": "user", "content": parts }));
+    }
+    Ok(messages)
+}
+
+fn assistant_message(content: &Value) -> Value {
+    let blocks = match content {
+        Value::String(text) => return json!({ "role": "assistant", "content": text }),
+        Value::Array(blocks) => blocks.as_slice(),
+        _ => &[],
+    };
+
+    let text: String = blocks
+        .iter()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect();
+    let tool_calls: Vec<Value> = blocks
+        .iter()
+        .filter(|block| block["type"] == "tool_use")
+        .map(|block| {
+            json!({
+                "id": block["id"],
+                "type": "function",
+                "function": { "name": block["name"], "arguments": block["input"].to_string() },
+            })
+        })
+        .collect();
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() { Value::Null } else { Value::String(text) },
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request_to_messages() {
+        let chat = json!({
+            "model": "claude-3-5-sonnet",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "What is this?" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBOR" } }
+                ]},
+                { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "lookup", "arguments": "{\"q\":\"png\"}" }
+                }]},
+                { "role": "tool", "tool_call_id": "call_1", "content": "an image format" },
+                { "role": "user", "content": "Thanks" }
+            ],
+            "max_tokens": 100,
+            "temperature": 0.2,
+            "stop": "END",
+            "tools": [{ "type": "function", "function": {
+                "name": "lookup", "description": "Search", "parameters": { "type": "object" }
+            }}],
+            "tool_choice": "required",
+            "parallel_tool_calls": false
+        });
+
+        let request = chat_request_to_messages(&chat).unwrap();
+        assert_eq!(request["system"], "Be brief.");
+        assert_eq!(request["max_tokens"], 100);
+        assert_eq!(request["stop_sequences"], json!(["END"]));
+        assert_eq!(request["tools"][0]["input_schema"], json!({ "type": "object" }));
+        assert_eq!(request["tool_choice"], json!({ "type": "any", "disable_parallel_tool_use": true }));
+
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["content"][1]["source"]["media_type"], "image/png");
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"], json!({ "q": "png" }));
+        // The tool result and the next user message share one user turn
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "call_1");
+        assert_eq!(messages[2]["content"][1]["text"], "Thanks");
+    }
+
+    #[test]
+    fn test_chat_request_defaults_and_errors() {
+        let request = chat_request_to_messages(&json!({
+            "model": "m",
+            "messages": [{ "role": "user", "content": "hi" }]
+        }))
+        .unwrap();
+        assert_eq!(request["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(request.get("tool_choice").is_none());
+
+        assert!(chat_request_to_messages(&json!({ "messages": [], "n": 2 })).is_err());
+        assert!(chat_request_to_messages(&json!({ "messages": [{ "role": "critic" }] })).is_err());
+    }
+
+    #[test]
+    fn test_messages_response_to_chat() {
+        let message = json!({
+            "id": "msg_1",
+            "model": "claude-3-5-sonnet",
+            "content": [
+                { "type": "text", "text": "Looking it up." },
+                { "type": "tool_use", "id": "toolu_1", "name": "lookup", "input": { "q": "png" } }
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 10, "cache_read_input_tokens": 5, "output_tokens": 7 }
+        });
+
+        let chat = messages_response_to_chat(&message);
+        assert_eq!(chat["object"], "chat.completion");
+        let choice = &chat["choices"][0];
+        assert_eq!(choice["finish_reason"], "tool_calls");
+        assert_eq!(choice["message"]["content"], "Looking it up.");
+        assert_eq!(choice["message"]["tool_calls"][0]["function"]["arguments"], r#"{"q":"png"}"#);
+        assert_eq!(chat["usage"]["prompt_tokens"], 15);
+        assert_eq!(chat["usage"]["total_tokens"], 22);
+        assert_eq!(chat["usage"]["prompt_tokens_details"]["cached_tokens"], 5);
+    }
+
+    #[test]
+    fn test_error_translation() {
+        let error = json!({ "type": "error", "error": { "type": "overloaded_error", "message": "busy" } });
+        assert_eq!(
+            anthropic_error_to_openai(&error),
+            json!({ "error": { "message": "busy", "type": "overloaded_error", "param": null, "code": null } })
+        );
+    }
+
+    #[test]
+    fn test_messages_request_to_chat() {
+        let request = json!({
+            "model": "gpt-4o",
+            "system": [{ "type": "text", "text": "Be brief." }],
+            "max_tokens": 200,
+            "stop_sequences": ["END"],
+            "messages": [
+                { "role": "user", "content": [
+                    { "type": "text", "text": "Describe" },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/jpeg", "data": "abc" } }
+                ]},
+                { "role": "assistant", "content": [
+                    { "type": "thinking", "thinking": "hmm" },
+                    { "type": "tool_use", "id": "toolu_1", "name": "lookup", "input": { "q": "jpeg" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "toolu_1", "content": [{ "type": "text", "text": "a format" }] },
+                    { "type": "text", "text": "Thanks" }
+                ]}
+            ],
+            "tools": [{ "name": "lookup", "input_schema": { "type": "object" } }],
+            "tool_choice": { "type": "tool", "name": "lookup", "disable_parallel_tool_use": true }
+        });
+
+        let chat = messages_request_to_chat(&request).unwrap();
+        let messages = chat["messages"].as_array().unwrap();
+        assert_eq!(messages[0], json!({ "role": "system", "content": "Be brief." }));
+        assert_eq!(messages[1]["content"][1]["image_url"]["url"], "data:image/jpeg;base64,abc");
+        assert_eq!(messages[2]["content"], Value::Null);
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["arguments"], r#"{"q":"jpeg"}"#);
+        assert_eq!(messages[3], json!({ "role": "tool", "tool_call_id": "toolu_1", "content": "a format" }));
+        assert_eq!(messages[4]["content"][0]["text"], "Thanks");
+        assert_eq!(chat["stop"], json!(["END"]));
+        assert_eq!(chat["tools"][0]["function"]["parameters"], json!({ "type": "object" }));
+        assert_eq!(chat["tool_choice"]["function"]["name"], "lookup");
+        assert_eq!(chat["parallel_tool_calls"], false);
+    }
+
+    #[test]
+    fn test_chat_response_to_messages() {
+        let chat = json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": { "name": "lookup", "arguments": "{\"q\":\"x\"}" }
+                }]},
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 20, "completion_tokens": 4, "prompt_tokens_details": { "cached_tokens": 8 } }
+        });
+
+        let message = chat_response_to_messages(&chat);
+        assert_eq!(message["type"], "message");
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["content"][0]["type"], "tool_use");
+        assert_eq!(message["content"][0]["input"], json!({ "q": "x" }));
+        assert_eq!(message["usage"], json!({ "input_tokens": 12, "output_tokens": 4, "cache_read_input_tokens": 8 }));
+
+        let error = json!({ "error": { "message": "bad key", "type": "invalid_request_error" } });
+        assert_eq!(openai_error_to_anthropic(&error)["error"]["message"], "bad key");
+    }
+}
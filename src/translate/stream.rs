@@ -0,0 +1,649 @@
+//! Incremental transcoding of streamed responses between Anthropic message
+//! events and OpenAI `chat.completion.chunk` deltas.
+
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    response::Response,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use super::ollama::NdjsonDecoder;
+use super::openai::{chat_usage, finish_reason, messages_usage, stop_reason};
+use crate::sse::{SseEvent, SseParser};
+
+/// Error sent when an upstream stream stops before its final event.
+const TRUNCATED_MESSAGE: &str = "upstream stream ended before the response was complete";
+
+/// Turns upstream events into client events, one event at a time.
+pub trait StreamTranscoder: Send + 'static {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent>;
+
+    /// Called once when the upstream stream ends, to close anything still open.
+    fn finish(&mut self) -> Vec<SseEvent>;
+}
+
+/// Splits an upstream body into events, wherever its chunks are cut.
+pub trait EventDecoder: Send + 'static {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent>;
+
+    /// Called once when the upstream stream ends, for anything still buffered.
+    fn finish(&mut self) -> Vec<SseEvent>;
+}
+
+impl EventDecoder for SseParser {
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        SseParser::push(self, chunk)
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        SseParser::finish(self).into_iter().collect()
+    }
+}
+
+/// Rewrites the response's event stream chunk by chunk as it arrives.
+pub fn transcode(response: Response, transcoder: Box<dyn StreamTranscoder>) -> Response {
+    decode(response, Box::new(SseParser::new()), transcoder)
+}
+
+/// Like [`transcode`], for upstreams whose stream is not `text/event-stream`.
+pub fn decode(response: Response, decoder: Box<dyn EventDecoder>, transcoder: Box<dyn StreamTranscoder>) -> Response {
+    transcode_with(response, decoder, transcoder, encode)
+}
+
+/// Like [`transcode`], for NDJSON streams: each line is one event's data,
+/// and the events that come out are written back as lines.
+pub fn transcode_lines(response: Response, transcoder: Box<dyn StreamTranscoder>) -> Response {
+    transcode_with(response, Box::new(NdjsonDecoder::new()), transcoder, encode_lines)
+}
+
+fn transcode_with(
+    response: Response,
+    decoder: Box<dyn EventDecoder>,
+    transcoder: Box<dyn StreamTranscoder>,
+    encode: fn(Vec<SseEvent>) -> Option<Bytes>,
+) -> Response {
+    response.map(|body| {
+        let state = Transcoding {
+            inner: body.into_data_stream(),
+            parser: decoder,
+            transcoder,
+            encode,
+            finished: false,
+        };
+        Body::from_stream(futures::stream::unfold(state, Transcoding::next))
+    })
+}
+
+struct Transcoding {
+    inner: BodyDataStream,
+    parser: Box<dyn EventDecoder>,
+    transcoder: Box<dyn StreamTranscoder>,
+    encode: fn(Vec<SseEvent>) -> Option<Bytes>,
+    finished: bool,
+}
+
+impl Transcoding {
+    async fn next(mut self) -> Option<(Result<Bytes, axum::Error>, Self)> {
+        if self.finished {
+            return None;
+        }
+        loop {
+            match self.inner.next().await {
+                Some(Ok(chunk)) => {
+                    let events = self.parser.push(&chunk);
+                    let events = events.into_iter().flat_map(|event| self.transcoder.event(event)).collect();
+                    if let Some(out) = (self.encode)(events) {
+                        return Some((Ok(out), self));
+                    }
+                }
+                Some(Err(e)) => {
+                    self.finished = true;
+                    return Some((Err(e), self));
+                }
+                None => {
+                    self.finished = true;
+                    let mut events: Vec<SseEvent> = self
+                        .parser
+                        .finish()
+                        .into_iter()
+                        .flat_map(|event| self.transcoder.event(event))
+                        .collect();
+                    events.extend(self.transcoder.finish());
+                    return (self.encode)(events).map(|out| (Ok(out), self));
+                }
+            }
+        }
+    }
+}
+
+fn encode(events: Vec<SseEvent>) -> Option<Bytes> {
+    if events.is_empty() {
+        return None;
+    }
+    let mut out = Vec::new();
+    for event in events {
+        out.extend_from_slice(&event.to_bytes());
+    }
+    Some(Bytes::from(out))
+}
+
+fn encode_lines(events: Vec<SseEvent>) -> Option<Bytes> {
+    if events.is_empty() {
+        return None;
+    }
+    let mut out = Vec::new();
+    for event in events {
+        out.extend_from_slice(event.data.as_bytes());
+        out.push(b'\n');
+    }
+    Some(Bytes::from(out))
+}
+
+/// Anthropic `message_start` … `message_stop` events to OpenAI chunks.
+pub struct AnthropicToOpenAi {
+    created: u64,
+    id: Value,
+    model: Value,
+    usage: Value,
+    /// OpenAI `tool_calls` index for each Anthropic content block index.
+    tool_indexes: Vec<(u64, usize)>,
+    /// Whether the client asked for `stream_options.include_usage`.
+    include_usage: bool,
+    done: bool,
+}
+
+impl AnthropicToOpenAi {
+    pub fn new(created: u64, include_usage: bool) -> Self {
+        Self {
+            created,
+            id: Value::Null,
+            model: Value::Null,
+            usage: json!({}),
+            tool_indexes: Vec::new(),
+            include_usage,
+            done: false,
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Value) -> SseEvent {
+        SseEvent::new(
+            None,
+            json!({
+                "id": self.id,
+                "object": "chat.completion.chunk",
+                "created": self.created,
+                "model": self.model,
+                "choices": [{ "index": 0, "delta": delta, "logprobs": null, "finish_reason": finish_reason }],
+            })
+            .to_string(),
+        )
+    }
+
+    fn tool_index(&self, block_index: u64) -> Option<usize> {
+        self.tool_indexes
+            .iter()
+            .find(|(block, _)| *block == block_index)
+            .map(|(_, tool)| *tool)
+    }
+
+    fn merge_usage(&mut self, usage: &Value) {
+        for (key, value) in usage.as_object().into_iter().flatten() {
+            self.usage[key] = value.clone();
+        }
+    }
+
+    fn error(&mut self, error: &Value) -> Vec<SseEvent> {
+        self.done = true;
+        vec![
+            SseEvent::new(None, super::openai::anthropic_error_to_openai(error).to_string()),
+            SseEvent::new(None, "[DONE]"),
+        ]
+    }
+}
+
+impl StreamTranscoder for AnthropicToOpenAi {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Some(payload) = event.json_data() else {
+            return Vec::new();
+        };
+        let index = payload["index"].as_u64().unwrap_or(0);
+
+        match payload["type"].as_str() {
+            Some("message_start") => {
+                let message = &payload["message"];
+                self.id = message["id"].clone();
+                self.model = message["model"].clone();
+                self.merge_usage(&message["usage"]);
+                vec![self.chunk(json!({ "role": "assistant", "content": "" }), Value::Null)]
+            }
+            Some("content_block_start") if payload["content_block"]["type"] == "tool_use" => {
+                let tool = self.tool_indexes.len();
+                self.tool_indexes.push((index, tool));
+                let block = &payload["content_block"];
+                let delta = json!({ "tool_calls": [{
+                    "index": tool,
+                    "id": block["id"],
+                    "type": "function",
+                    "function": { "name": block["name"], "arguments": "" },
+                }]});
+                vec![self.chunk(delta, Value::Null)]
+            }
+            Some("content_block_delta") => {
+                let delta = &payload["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => vec![self.chunk(json!({ "content": delta["text"] }), Value::Null)],
+                    Some("input_json_delta") => match self.tool_index(index) {
+                        Some(_) if delta["partial_json"] == "" => Vec::new(),
+                        Some(tool) => {
+                            let delta = json!({ "tool_calls": [{
+                                "index": tool,
+                                "function": { "arguments": delta["partial_json"] },
+                            }]});
+                            vec![self.chunk(delta, Value::Null)]
+                        }
+                        None => Vec::new(),
+                    },
+                    _ => Vec::new(),
+                }
+            }
+            Some("message_delta") => {
+                self.merge_usage(&payload["usage"]);
+                let reason = finish_reason(payload["delta"]["stop_reason"].as_str());
+                vec![self.chunk(json!({}), reason)]
+            }
+            Some("message_stop") => {
+                if std::mem::replace(&mut self.done, true) {
+                    return Vec::new();
+                }
+                let mut events = Vec::new();
+                if self.include_usage {
+                    let usage = json!({
+                        "id": self.id,
+                        "object": "chat.completion.chunk",
+                        "created": self.created,
+                        "model": self.model,
+                        "choices": [],
+                        "usage": chat_usage(&self.usage),
+                    });
+                    events.push(SseEvent::new(None, usage.to_string()));
+                }
+                events.push(SseEvent::new(None, "[DONE]"));
+                events
+            }
+            Some("error") => self.error(&payload["error"]),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A stream that ends without `message_stop` was cut off upstream, so it
+    /// ends with an error rather than looking like a finished completion.
+    fn finish(&mut self) -> Vec<SseEvent> {
+        if self.done {
+            return Vec::new();
+        }
+        self.error(&json!({ "type": "api_error", "message": TRUNCATED_MESSAGE }))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OpenBlock {
+    Text,
+    /// A `tool_use` block, keyed by the upstream call it was built from.
+    Tool(String),
+}
+
+/// Builds a well-formed Anthropic event sequence from text and tool-call
+/// fragments, opening and closing content blocks as needed.
+#[derive(Default)]
+pub(crate) struct MessageEvents {
+    started: bool,
+    open: Option<OpenBlock>,
+    next_block: u64,
+    finished: bool,
+}
+
+impl MessageEvents {
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub(crate) fn start(&mut self, id: &Value, model: &Value, events: &mut Vec<SseEvent>) {
+        if std::mem::replace(&mut self.started, true) {
+            return;
+        }
+        events.push(SseEvent::json(&json!({
+            "type": "message_start",
+            "message": {
+                "id": id,
+                "type": "message",
+                "role": "assistant",
+                "model": model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": 0, "output_tokens": 0 },
+            },
+        })));
+    }
+
+    pub(crate) fn text(&mut self, text: &str, events: &mut Vec<SseEvent>) {
+        if text.is_empty() {
+            return;
+        }
+        if self.open != Some(OpenBlock::Text) {
+            self.open_block(OpenBlock::Text, json!({ "type": "text", "text": "" }), events);
+        }
+        events.push(SseEvent::json(&json!({
+            "type": "content_block_delta",
+            "index": self.next_block - 1,
+            "delta": { "type": "text_delta", "text": text },
+        })));
+    }
+
+    /// Opens a `tool_use` block unless the call identified by `key` is the
+    /// one already open.
+    pub(crate) fn tool_call(&mut self, key: &str, id: &Value, name: &Value, events: &mut Vec<SseEvent>) {
+        let block = OpenBlock::Tool(key.to_string());
+        if self.open.as_ref() == Some(&block) {
+            return;
+        }
+        let content_block = json!({ "type": "tool_use", "id": id, "name": name, "input": {} });
+        self.open_block(block, content_block, events);
+    }
+
+    pub(crate) fn tool_arguments(&mut self, partial_json: &str, events: &mut Vec<SseEvent>) {
+        if partial_json.is_empty() || !matches!(self.open, Some(OpenBlock::Tool(_))) {
+            return;
+        }
+        events.push(SseEvent::json(&json!({
+            "type": "content_block_delta",
+            "index": self.next_block - 1,
+            "delta": { "type": "input_json_delta", "partial_json": partial_json },
+        })));
+    }
+
+    /// Closes the open block and ends the message; does nothing if the
+    /// message never started or was already finished.
+    pub(crate) fn finish(&mut self, stop_reason: Value, usage: Value, events: &mut Vec<SseEvent>) {
+        if !self.started || std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        self.close_block(events);
+        events.push(SseEvent::json(&json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+            "usage": usage,
+        })));
+        events.push(SseEvent::json(&json!({ "type": "message_stop" })));
+    }
+
+    /// Ends a stream that was cut off upstream, before its finish reason,
+    /// with an error event rather than a message that looks complete.
+    pub(crate) fn truncated(&mut self, events: &mut Vec<SseEvent>) {
+        if self.finished {
+            return;
+        }
+        let error = json!({ "type": "error", "error": { "type": "api_error", "message": TRUNCATED_MESSAGE } });
+        self.error(error, events);
+    }
+
+    /// Ends the stream with an error event instead of a message.
+    pub(crate) fn error(&mut self, error: Value, events: &mut Vec<SseEvent>) {
+        self.finished = true;
+        events.push(SseEvent::json(&error));
+    }
+
+    fn close_block(&mut self, events: &mut Vec<SseEvent>) {
+        if self.open.take().is_some() {
+            events.push(SseEvent::json(&json!({ "type": "content_block_stop", "index": self.next_block - 1 })));
+        }
+    }
+
+    fn open_block(&mut self, block: OpenBlock, content_block: Value, events: &mut Vec<SseEvent>) {
+        self.close_block(events);
+        self.open = Some(block);
+        events.push(SseEvent::json(&json!({
+            "type": "content_block_start",
+            "index": self.next_block,
+            "content_block": content_block,
+        })));
+        self.next_block += 1;
+    }
+}
+
+/// OpenAI `chat.completion.chunk` deltas to Anthropic message events.
+#[derive(Default)]
+pub struct OpenAiToAnthropic {
+    message: MessageEvents,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl OpenAiToAnthropic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamTranscoder for OpenAiToAnthropic {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if self.message.is_finished() {
+            return Vec::new();
+        }
+        if event.data.trim() == "[DONE]" {
+            return self.finish();
+        }
+        let Some(chunk) = event.json_data() else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+        if let Some(error) = chunk.get("error") {
+            let error = super::openai::openai_error_to_anthropic(&json!({ "error": error }));
+            self.message.error(error, &mut events);
+            return events;
+        }
+
+        // Azure opens with a content-filter chunk that has no id, model or choices
+        let no_choices = chunk["choices"].as_array().is_some_and(Vec::is_empty);
+        if no_choices && chunk.get("prompt_filter_results").is_some() {
+            return events;
+        }
+
+        self.message.start(&chunk["id"], &chunk["model"], &mut events);
+        if chunk["usage"].is_object() {
+            self.usage = chunk["usage"].clone();
+        }
+
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let delta = &choice["delta"];
+            if let Some(text) = delta["content"].as_str() {
+                self.message.text(text, &mut events);
+            }
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                let key = call["index"].as_u64().unwrap_or(0).to_string();
+                self.message.tool_call(&key, &call["id"], &call["function"]["name"], &mut events);
+                if let Some(arguments) = call["function"]["arguments"].as_str() {
+                    self.message.tool_arguments(arguments, &mut events);
+                }
+            }
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                self.finish_reason = Some(reason.to_string());
+            }
+        }
+        events
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        match self.finish_reason.as_deref() {
+            Some(reason) => self.message.finish(stop_reason(Some(reason)), messages_usage(&self.usage), &mut events),
+            None => self.message.truncated(&mut events),
+        }
+        events
+    }
+}
+
+/// Leaves events as they are, for decoded streams already in the client's format.
+pub struct Passthrough;
+
+impl StreamTranscoder for Passthrough {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        vec![event]
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        Vec::new()
+    }
+}
+
+/// Runs one transcoder's output through another, e.g. Gemini → Anthropic → OpenAI.
+pub struct Chain(pub Box<dyn StreamTranscoder>, pub Box<dyn StreamTranscoder>);
+
+impl StreamTranscoder for Chain {
+    fn event(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let first = self.0.event(event);
+        first.into_iter().flat_map(|event| self.1.event(event)).collect()
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events: Vec<SseEvent> = self.0.finish().into_iter().flat_map(|event| self.1.event(event)).collect();
+        events.extend(self.1.finish());
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::gemini::GeminiToAnthropic;
+
+    const ANTHROPIC_STREAM: &str = include_str!("../../tests/golden/anthropic_tool_use.sse");
+    const ANTHROPIC_AS_OPENAI: &str = include_str!("../../tests/golden/anthropic_tool_use.openai.sse");
+    const OPENAI_STREAM: &str = include_str!("../../tests/golden/openai_tool_calls.sse");
+    const OPENAI_AS_ANTHROPIC: &str = include_str!("../../tests/golden/openai_tool_calls.anthropic.sse");
+    const GEMINI_STREAM: &str = include_str!("../../tests/golden/gemini_function_call.sse");
+    const GEMINI_AS_ANTHROPIC: &str = include_str!("../../tests/golden/gemini_function_call.anthropic.sse");
+    const GEMINI_AS_OPENAI: &str = include_str!("../../tests/golden/gemini_function_call.openai.sse");
+
+    /// Feeds `input` in chunks of `chunk_size` bytes and collects the output.
+    async fn run(input: &'static str, chunk_size: usize, transcoder: Box<dyn StreamTranscoder>) -> String {
+        let chunks: Vec<Result<Bytes, std::io::Error>> = input
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let response = Response::new(Body::from_stream(futures::stream::iter(chunks)));
+        let response = transcode(response, transcoder);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_to_openai_golden() {
+        for chunk_size in [usize::MAX, 7, 1] {
+            let output = run(ANTHROPIC_STREAM, chunk_size, Box::new(AnthropicToOpenAi::new(1700000000, true))).await;
+            assert_eq!(output, ANTHROPIC_AS_OPENAI, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_stream_to_anthropic_golden() {
+        for chunk_size in [usize::MAX, 7, 1] {
+            let output = run(OPENAI_STREAM, chunk_size, Box::new(OpenAiToAnthropic::new())).await;
+            assert_eq!(output, OPENAI_AS_ANTHROPIC, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gemini_stream_golden() {
+        for chunk_size in [usize::MAX, 7, 1] {
+            let output = run(GEMINI_STREAM, chunk_size, Box::new(GeminiToAnthropic::new())).await;
+            assert_eq!(output, GEMINI_AS_ANTHROPIC, "chunk size {}", chunk_size);
+
+            let chain = Chain(Box::new(GeminiToAnthropic::new()), Box::new(AnthropicToOpenAi::new(1700000000, true)));
+            let output = run(GEMINI_STREAM, chunk_size, Box::new(chain)).await;
+            assert_eq!(output, GEMINI_AS_OPENAI, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transcoded_streams_reassemble() {
+        let output = run(ANTHROPIC_STREAM, 64, Box::new(AnthropicToOpenAi::new(0, true))).await;
+        let message = crate::sse::assemble(&crate::sse::parse_all(output.as_bytes())).unwrap();
+        assert_eq!(message["choices"][0]["finish_reason"], "tool_calls");
+
+        let output = run(OPENAI_STREAM, 64, Box::new(OpenAiToAnthropic::new())).await;
+        let message = crate::sse::assemble(&crate::sse::parse_all(output.as_bytes())).unwrap();
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["content"][1]["input"], json!({ "city": "Paris" }));
+    }
+
+    #[test]
+    fn test_azure_content_filter_chunk_skipped() {
+        let mut transcoder = OpenAiToAnthropic::new();
+        let events = transcoder.event(SseEvent::new(
+            None,
+            r#"{"choices":[],"created":0,"id":"","model":"","object":"","prompt_filter_results":[{"prompt_index":0,"content_filter_results":{}}]}"#,
+        ));
+        assert!(events.is_empty());
+
+        let events = transcoder.event(SseEvent::new(
+            None,
+            r#"{"id":"c1","model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+        ));
+        assert_eq!(events[0].json_data().unwrap()["message"]["id"], "c1");
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_stream_to_openai_usage_only_when_asked() {
+        let output = run(ANTHROPIC_STREAM, 64, Box::new(AnthropicToOpenAi::new(0, false))).await;
+        assert!(!output.contains("\"usage\""));
+        assert!(output.ends_with("data: [DONE]\n\n"));
+    }
+
+    #[test]
+    fn test_truncated_anthropic_stream_ends_with_error() {
+        let mut transcoder = AnthropicToOpenAi::new(0, true);
+        let start = r#"{"type":"message_start","message":{"id":"msg_1","model":"m","usage":{"input_tokens":1}}}"#;
+        assert_eq!(transcoder.event(SseEvent::new(None, start)).len(), 1);
+
+        let events = transcoder.finish();
+        assert_eq!(events.len(), 2);
+        let error = events[0].json_data().unwrap();
+        assert_eq!(error["error"]["type"], "api_error");
+        assert!(error.get("choices").is_none());
+        assert_eq!(events[1].data, "[DONE]");
+        assert!(transcoder.finish().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_openai_stream_ends_with_error() {
+        let mut transcoder = OpenAiToAnthropic::new();
+        let events = transcoder.event(SseEvent::new(
+            None,
+            r#"{"id":"c1","model":"m","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+        ));
+        assert_eq!(events.len(), 3);
+
+        let events = transcoder.finish();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event.as_deref(), Some("error"));
+        assert_eq!(events[0].json_data().unwrap()["error"]["type"], "api_error");
+        assert!(transcoder.finish().is_empty());
+    }
+
+    #[test]
+    fn test_truncated_gemini_stream_reaches_openai_clients_as_error() {
+        let mut transcoder = Chain(Box::new(GeminiToAnthropic::new()), Box::new(AnthropicToOpenAi::new(0, false)));
+        let chunk = r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hi"}]}}],"responseId":"r1"}"#;
+        assert!(!transcoder.event(SseEvent::new(None, chunk)).is_empty());
+
+        let events = transcoder.finish();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].json_data().unwrap()["error"]["type"], "api_error");
+        assert_eq!(events[1].data, "[DONE]");
+    }
+}
@@ -0,0 +1,160 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Upper bound on how much of a non-streamed body is buffered to look for usage.
+const MAX_BUFFERED_BODY: usize = 8 * 1024 * 1024;
+
+/// Token counts reported by the upstream provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// Merges the usage reported by a JSON response body or a single stream event.
+    ///
+    /// Understands Anthropic (`input_tokens`/`output_tokens`, nested under `message`
+    /// in `message_start`) and OpenAI (`prompt_tokens`/`completion_tokens`) shapes.
+    pub fn merge_from(&mut self, value: &Value) {
+        let usage = value
+            .get("usage")
+            .or_else(|| value.get("message").and_then(|message| message.get("usage")));
+
+        let Some(usage) = usage.filter(|usage| usage.is_object()) else {
+            return;
+        };
+
+        if let Some(tokens) = usage
+            .get("input_tokens")
+            .or_else(|| usage.get("prompt_tokens"))
+            .and_then(Value::as_u64)
+        {
+            self.input_tokens = Some(tokens);
+        }
+        if let Some(tokens) = usage
+            .get("output_tokens")
+            .or_else(|| usage.get("completion_tokens"))
+            .and_then(Value::as_u64)
+        {
+            self.output_tokens = Some(tokens);
+        }
+    }
+}
+
+/// Scans a response body chunk by chunk and extracts the token usage from it.
+///
+/// Server-sent event streams are parsed line by line as they arrive; any other
+/// body is buffered (up to a limit) and parsed as JSON once it is complete.
+pub struct UsageTracker {
+    streaming: bool,
+    buffer: Vec<u8>,
+    overflowed: bool,
+    usage: Usage,
+}
+
+impl UsageTracker {
+    pub fn new(content_type: Option<&str>) -> Self {
+        let streaming = content_type
+            .map(|content_type| content_type.starts_with("text/event-stream"))
+            .unwrap_or(false);
+
+        Self {
+            streaming,
+            buffer: Vec::new(),
+            overflowed: false,
+            usage: Usage::default(),
+        }
+    }
+
+    pub fn observe(&mut self, chunk: &[u8]) {
+        if self.overflowed {
+            return;
+        }
+
+        self.buffer.extend_from_slice(chunk);
+
+        if self.streaming {
+            while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+                self.observe_event_line(&line);
+            }
+        }
+
+        if self.buffer.len() > MAX_BUFFERED_BODY {
+            self.buffer = Vec::new();
+            self.overflowed = true;
+        }
+    }
+
+    pub fn finish(mut self) -> Usage {
+        if self.streaming {
+            let line = std::mem::take(&mut self.buffer);
+            self.observe_event_line(&line);
+        } else if !self.overflowed {
+            if let Ok(value) = serde_json::from_slice::<Value>(&self.buffer) {
+                self.usage.merge_from(&value);
+            }
+        }
+
+        self.usage
+    }
+
+    fn observe_event_line(&mut self, line: &[u8]) {
+        let Ok(line) = std::str::from_utf8(line) else {
+            return;
+        };
+
+        if let Some(data) = line.trim().strip_prefix("data:") {
+            if let Ok(value) = serde_json::from_str::<Value>(data.trim()) {
+                self.usage.merge_from(&value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_from_anthropic_json() {
+        let mut tracker = UsageTracker::new(Some("application/json"));
+        tracker.observe(br#"{"type":"message","usage":{"input_tokens":12,"#);
+        tracker.observe(br#""output_tokens":34}}"#);
+
+        let usage = tracker.finish();
+        assert_eq!(usage.input_tokens, Some(12));
+        assert_eq!(usage.output_tokens, Some(34));
+    }
+
+    #[test]
+    fn test_usage_from_openai_json() {
+        let mut tracker = UsageTracker::new(Some("application/json"));
+        tracker.observe(br#"{"object":"chat.completion","usage":{"prompt_tokens":5,"completion_tokens":7}}"#);
+
+        let usage = tracker.finish();
+        assert_eq!(usage.input_tokens, Some(5));
+        assert_eq!(usage.output_tokens, Some(7));
+    }
+
+    #[test]
+    fn test_usage_from_anthropic_event_stream_split_across_chunks() {
+        let mut tracker = UsageTracker::new(Some("text/event-stream; charset=utf-8"));
+        tracker.observe(b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":");
+        tracker.observe(b"25,\"output_tokens\":1}}}\n\n");
+        tracker.observe(b"event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":15}}\n\n");
+
+        let usage = tracker.finish();
+        assert_eq!(usage.input_tokens, Some(25));
+        assert_eq!(usage.output_tokens, Some(15));
+    }
+
+    #[test]
+    fn test_usage_missing() {
+        let mut tracker = UsageTracker::new(None);
+        tracker.observe(b"not json");
+
+        assert_eq!(tracker.finish(), Usage::default());
+    }
+}
@@ -79,7 +79,7 @@ async fn test_e2e_proxy_request() {
     
     // Test health endpoint
     let health_response = client
-        .get(&format!("http://{}/api/v1/health", proxy_addr))
+        .get(format!("http://{}/api/v1/health", proxy_addr))
         .send()
         .await
         .expect("Failed to send request");
@@ -138,7 +138,7 @@ async fn test_e2e_proxy_with_custom_port() {
     let client = reqwest::Client::new();
     
     let response = client
-        .get(&format!("http://{}/api/v1/test", proxy_addr))
+        .get(format!("http://{}/api/v1/test", proxy_addr))
         .send()
         .await
         .expect("Failed to send request");